@@ -1,6 +1,7 @@
 use alloy_primitives::{address, utils::format_ether, Address, U256};
 use alloy_sol_types::sol;
 use simular_core::{BaseEvm, CreateFork};
+use std::path::Path;
 
 // Addresses used
 pub const AGENT: Address = address!("2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b");
@@ -22,7 +23,7 @@ sol!(UniswapFactory, "examples/abis/UniswapV3Factory.abi");
 
 #[allow(dead_code)]
 pub fn sqrtp_to_price(sqrtp: U256) -> f64 {
-    let sp: f64 = sqrtp.try_into().unwrap();
+    let sp: f64 = sqrtp.into();
     (sp / Q96).powf(2.0)
 }
 
@@ -181,7 +182,6 @@ pub fn create_snapshot() {
 
     println!("recv: {:?} dai", dai_recv);
 
-    let st = evm.create_snapshot().unwrap();
-    let jsonstate = serde_json::to_string_pretty(&st).unwrap();
-    std::fs::write("uniswap_snapshot.json", jsonstate).expect("Unable to write file");
+    evm.save_snapshot(Path::new("uniswap_snapshot.json"))
+        .expect("Unable to write file");
 }
@@ -3,12 +3,16 @@
 //!
 use alloy_dyn_abi::{DynSolEvent, DynSolType, DynSolValue, Specifier};
 use alloy_json_abi::{ContractObject, Function, JsonAbi, StateMutability};
-use alloy_primitives::{Bytes, Log, LogData};
+use alloy_primitives::{Address, Bytes, Log, LogData, U256};
 use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
 use std::collections::BTreeMap;
 
 type EventMap = BTreeMap<std::string::String, Vec<alloy_json_abi::Event>>;
 
+/// An event decoded from a log: `(event name, values)`
+pub type DecodedEvent = (String, DynSolValue);
+
 ///
 /// Wrapper around pre-processed Events to help extract log information.
 /// We flatten the structure of `events` in JsonAbi to make it easier to
@@ -42,6 +46,50 @@ pub struct ContractAbi {
     pub bytecode: Option<Bytes>,
     /// Contract event information with a log decoder
     pub events_logs: Vec<EventLog>,
+    /// The state variables of the contract, if a storage layout was added.
+    /// See `with_storage_layout`
+    pub storage_layout: Vec<StorageVariable>,
+}
+
+/// A state variable from a solc storage layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StorageVariable {
+    /// the name of the variable
+    pub name: String,
+    /// the storage slot the variable starts at
+    pub slot: U256,
+    /// the offset, in bytes, of the variable within the slot
+    pub offset: usize,
+    /// the type, e.g. "uint256", "address", "mapping(address => uint256)"
+    pub type_label: String,
+    /// how the value is stored: "inplace", "mapping", "dynamic_array" or "bytes"
+    pub encoding: String,
+    /// the number of bytes used by the variable
+    pub number_of_bytes: usize,
+}
+
+// the storage layout output by solc
+#[derive(Deserialize)]
+struct RawStorageLayout {
+    storage: Vec<RawStorageEntry>,
+    types: Option<BTreeMap<String, RawStorageType>>,
+}
+
+#[derive(Deserialize)]
+struct RawStorageEntry {
+    label: String,
+    offset: usize,
+    slot: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawStorageType {
+    encoding: String,
+    label: String,
+    number_of_bytes: String,
 }
 
 // walk through the events in JsonAbi to flatten the
@@ -77,6 +125,7 @@ impl ContractAbi {
             abi,
             bytecode: co.bytecode,
             events_logs: evts,
+            storage_layout: Vec::new(),
         }
     }
 
@@ -89,9 +138,27 @@ impl ContractAbi {
             abi,
             bytecode: bytecode.map(Bytes::from),
             events_logs: evts,
+            storage_layout: Vec::new(),
         }
     }
 
+    /// Parse the `abi` and the hex encoded `bytecode`, with or without the `0x` prefix,
+    /// as it appears in most compiler artifacts.
+    /// Note: `abi` is un-parsed json.
+    pub fn from_abi_and_hex_bytecode(abi: &str, bytecode: &str) -> Result<Self> {
+        let bytecode = alloy_primitives::hex::decode(bytecode.trim())
+            .map_err(|e| anyhow!("Abi: invalid hex bytecode: {}", e))?;
+        let abi = serde_json::from_str::<JsonAbi>(abi)
+            .map_err(|e| anyhow!("Abi: failed to parse abi: {}", e))?;
+        let evts = convert_events(&abi.events);
+        Ok(Self {
+            abi,
+            bytecode: Some(bytecode.into()),
+            events_logs: evts,
+            storage_layout: Vec::new(),
+        })
+    }
+
     /// Parse an ABI (without bytecode) from a `Vec` of contract function definitions.
     /// See [human readable abi](https://docs.ethers.org/v5/api/utils/abi/formats/#abi-formats--human-readable-abi)
     pub fn from_human_readable(input: Vec<&str>) -> Self {
@@ -101,12 +168,147 @@ impl ContractAbi {
             abi,
             bytecode: None,
             events_logs: evts,
+            storage_layout: Vec::new(),
+        }
+    }
+
+    /// Add the storage layout of the contract, as output by solc with
+    /// `--storage-layout` (the `storageLayout` of a compiler artifact).  This allows
+    /// reading state variables by name with `BaseEvm::read_variable`.
+    pub fn with_storage_layout(mut self, layout_json: &str) -> Result<Self> {
+        let layout = serde_json::from_str::<RawStorageLayout>(layout_json)
+            .map_err(|e| anyhow!("Abi: failed to parse storage layout: {}", e))?;
+        let types = layout.types.unwrap_or_default();
+        self.storage_layout = layout
+            .storage
+            .into_iter()
+            .map(|entry| {
+                let ty = match types.get(&entry.ty) {
+                    Some(ty) => ty,
+                    _ => bail!(
+                        "Abi: storage layout is missing type {} of {}",
+                        entry.ty,
+                        entry.label
+                    ),
+                };
+                Ok(StorageVariable {
+                    name: entry.label,
+                    slot: entry.slot.parse()?,
+                    offset: entry.offset,
+                    type_label: ty.label.clone(),
+                    encoding: ty.encoding.clone(),
+                    number_of_bytes: ty.number_of_bytes.parse()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(self)
+    }
+
+    /// Return the state variable `name` from the storage layout
+    pub fn storage_variable(&self, name: &str) -> Option<&StorageVariable> {
+        self.storage_layout.iter().find(|v| v.name == name)
+    }
+
+    /// Merge the functions, events, and errors from `other` into this ABI. Items
+    /// with the same signature are only included once. The bytecode, constructor,
+    /// fallback, and receive of this ABI are kept if set.
+    pub fn merge(&mut self, other: &ContractAbi) {
+        for (name, funcs) in &other.abi.functions {
+            let existing = self.abi.functions.entry(name.clone()).or_default();
+            for f in funcs {
+                if !existing.iter().any(|e| e.signature() == f.signature()) {
+                    existing.push(f.clone());
+                }
+            }
+        }
+        for (name, events) in &other.abi.events {
+            let existing = self.abi.events.entry(name.clone()).or_default();
+            for e in events {
+                if !existing.iter().any(|x| x.signature() == e.signature()) {
+                    existing.push(e.clone());
+                }
+            }
+        }
+        for (name, errors) in &other.abi.errors {
+            let existing = self.abi.errors.entry(name.clone()).or_default();
+            for e in errors {
+                if !existing.iter().any(|x| x.signature() == e.signature()) {
+                    existing.push(e.clone());
+                }
+            }
+        }
+
+        if self.abi.constructor.is_none() {
+            self.abi.constructor = other.abi.constructor.clone();
+        }
+        if self.abi.fallback.is_none() {
+            self.abi.fallback = other.abi.fallback;
+        }
+        if self.abi.receive.is_none() {
+            self.abi.receive = other.abi.receive;
         }
+        if self.bytecode.is_none() {
+            self.bytecode = other.bytecode.clone();
+        }
+        self.events_logs = convert_events(&self.abi.events);
+    }
+
+    /// Return the function selectors shared by more than one distinct function
+    /// signature, along with the colliding signatures. Selector clashes break
+    /// function dispatch and usually show up when merging ABIs.
+    pub fn selector_collisions(&self) -> Vec<([u8; 4], Vec<String>)> {
+        let mut selectors: BTreeMap<[u8; 4], Vec<String>> = BTreeMap::new();
+        for f in self.abi.functions() {
+            let signatures = selectors.entry(f.selector().0).or_default();
+            let sig = f.signature();
+            if !signatures.contains(&sig) {
+                signatures.push(sig);
+            }
+        }
+        selectors
+            .into_iter()
+            .filter(|(_, sigs)| sigs.len() > 1)
+            .collect()
+    }
+
+    /// Return the functions as a map of hex encoded selector to signature, in the
+    /// format of the 4byte directory, e.g. `"0xa9059cbb" => "transfer(address,uint256)"`.
+    /// If selectors collide only one signature is kept, see `selector_collisions`.
+    pub fn selector_map(&self) -> BTreeMap<String, String> {
+        self.abi
+            .functions()
+            .map(|f| (format!("0x{}", hex::encode(f.selector())), f.signature()))
+            .collect()
+    }
+
+    /// Return a dispatch table mapping each function selector to its signature, e.g.
+    /// to route calls over a merged ABI.  Returns an error naming the signatures if
+    /// two functions share a selector, see `selector_collisions`.
+    pub fn build_dispatch_table(&self) -> Result<BTreeMap<[u8; 4], String>> {
+        if let Some((selector, signatures)) = self.selector_collisions().first() {
+            bail!(
+                "Abi: selector 0x{} is shared by {}",
+                hex::encode(selector),
+                signatures.join(" and ")
+            );
+        }
+        Ok(self
+            .abi
+            .functions()
+            .map(|f| (f.selector().0, f.signature()))
+            .collect())
     }
 
     /// Extract and decode logs from emitted events
-    pub fn extract_logs(&self, logs: Vec<Log>) -> Vec<(String, DynSolValue)> {
-        let mut results: Vec<(String, DynSolValue)> = Vec::new();
+    pub fn extract_logs(&self, logs: Vec<Log>) -> Vec<DecodedEvent> {
+        self.decode_logs(&logs)
+    }
+
+    /// Decode the `logs`, e.g. the `logs` of a `CallResult`, returning the event name
+    /// and the decoded values (indexed first) of each log matching an event in the ABI.
+    /// Other logs are skipped.
+    pub fn decode_logs(&self, logs: &[Log]) -> Vec<DecodedEvent> {
+        let mut results: Vec<DecodedEvent> = Vec::new();
         for log in logs {
             for e in &self.events_logs {
                 if let Some(p) = e.decode(&log.data) {
@@ -122,6 +324,88 @@ impl ContractAbi {
         self.abi.functions.contains_key(name)
     }
 
+    /// Return the names of all functions.  An overloaded function is listed once
+    /// per overload.
+    pub fn function_names(&self) -> Vec<String> {
+        self.abi.functions().map(|f| f.name.clone()).collect()
+    }
+
+    /// Return the names of all events.  An overloaded event is listed once
+    /// per overload.
+    pub fn event_names(&self) -> Vec<String> {
+        self.abi.events().map(|e| e.name.clone()).collect()
+    }
+
+    /// Return the names of all custom errors.  An overloaded error is listed once
+    /// per overload.
+    pub fn error_names(&self) -> Vec<String> {
+        self.abi.errors().map(|e| e.name.clone()).collect()
+    }
+
+    /// Return the name of the function with the given 4-byte `selector`, if any
+    pub fn function_for_selector(&self, selector: &[u8; 4]) -> Option<String> {
+        self.abi
+            .functions()
+            .find(|f| f.selector() == selector)
+            .map(|f| f.name.clone())
+    }
+
+    /// Decode the call `data` for a function in the ABI, returning the name of the
+    /// function and the decoded arguments.  This is the inverse of `encode_function`.
+    pub fn decode_calldata(&self, data: &[u8]) -> Result<(String, Vec<DynSolValue>)> {
+        if data.len() < 4 {
+            bail!("Abi: call data is too short to contain a function selector");
+        }
+        let f = match self.abi.functions().find(|f| f.selector() == data[..4]) {
+            Some(f) => f,
+            _ => bail!(
+                "Abi: no function found for selector 0x{}",
+                hex::encode(&data[..4])
+            ),
+        };
+
+        let types = f
+            .inputs
+            .iter()
+            .map(|i| i.resolve())
+            .collect::<Result<Vec<_>, _>>()?;
+        match DynSolType::Tuple(types).abi_decode_params(&data[4..]) {
+            Ok(DynSolValue::Tuple(values)) => Ok((f.name.clone(), values)),
+            _ => Err(anyhow!(
+                "Abi: Unable to decode the arguments of function {}",
+                f.name
+            )),
+        }
+    }
+
+    /// Decode revert `data` as one of the custom errors in the ABI, returning the
+    /// name of the error and its values.
+    pub fn decode_error(&self, data: &[u8]) -> Result<(String, Vec<DynSolValue>)> {
+        if data.len() < 4 {
+            bail!("Abi: revert data is too short to contain an error selector");
+        }
+        let e = match self.abi.errors().find(|e| e.selector() == data[..4]) {
+            Some(e) => e,
+            _ => bail!(
+                "Abi: no error found for selector 0x{}",
+                hex::encode(&data[..4])
+            ),
+        };
+
+        let types = e
+            .inputs
+            .iter()
+            .map(|i| i.resolve())
+            .collect::<Result<Vec<_>, _>>()?;
+        match DynSolType::Tuple(types).abi_decode_params(&data[4..]) {
+            Ok(DynSolValue::Tuple(values)) => Ok((e.name.clone(), values)),
+            _ => Err(anyhow!(
+                "Abi: Unable to decode the values of error {}",
+                e.name
+            )),
+        }
+    }
+
     /// Does the ABI have a fallback?
     pub fn has_fallback(&self) -> bool {
         self.abi.fallback.is_some()
@@ -168,6 +452,22 @@ impl ContractAbi {
         Ok(([bytecode, encoded_args].concat(), is_payable))
     }
 
+    // the type used to decode the output of the function, if any
+    fn output_type(f: &Function) -> Option<DynSolType> {
+        match f.outputs.len() {
+            0 => None,
+            1 => f.outputs.first().unwrap().clone().resolve().ok(),
+            _ => {
+                let t = f
+                    .outputs
+                    .iter()
+                    .map(|i| i.resolve().unwrap())
+                    .collect::<Vec<_>>();
+                Some(DynSolType::Tuple(t))
+            }
+        }
+    }
+
     fn extract(funcs: &Function, args: &str) -> Result<DynSolValue> {
         let types = funcs
             .inputs
@@ -182,6 +482,75 @@ impl ContractAbi {
         })
     }
 
+    /// Decode the output of a call to the function `name` returning the name and value
+    /// of each return parameter, in order. Unnamed return parameters are named by their
+    /// position (`_0`, `_1`, ...). For overloaded functions, the output of the first
+    /// function that successfully decodes `data` is returned.
+    ///
+    /// Note: the values are always returned as a list, even for a function with a
+    /// single return parameter.  See `decode_single` to unwrap a single value.
+    /// A function that returns nothing decodes to an empty list.
+    pub fn decode_function_output(
+        &self,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Vec<(String, DynSolValue)>> {
+        let funcs = match self.abi.function(name) {
+            Some(funcs) => funcs,
+            _ => bail!("Abi: Function {} not found in the ABI!", name),
+        };
+
+        for f in funcs {
+            let types = match f
+                .outputs
+                .iter()
+                .map(|o| o.resolve())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(types) => types,
+                _ => continue,
+            };
+            if let Ok(DynSolValue::Tuple(values)) = DynSolType::Tuple(types).abi_decode_params(data)
+            {
+                let named = f
+                    .outputs
+                    .iter()
+                    .zip(values)
+                    .enumerate()
+                    .map(|(i, (param, value))| {
+                        let name = if param.name.is_empty() {
+                            format!("_{}", i)
+                        } else {
+                            param.name.clone()
+                        };
+                        (name, value)
+                    })
+                    .collect();
+                return Ok(named);
+            }
+        }
+
+        Err(anyhow!(
+            "Abi: Unable to decode the output of function {}",
+            name
+        ))
+    }
+
+    /// Same as `decode_function_output`, but without the names.  If the function
+    /// returns exactly one value it is returned unwrapped, otherwise all the values
+    /// are returned in a `DynSolValue::Tuple` (which is empty if nothing is returned).
+    pub fn decode_single(&self, name: &str, data: &[u8]) -> Result<DynSolValue> {
+        let mut values = self
+            .decode_function_output(name, data)?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        if values.len() == 1 {
+            return Ok(values.remove(0));
+        }
+        Ok(DynSolValue::Tuple(values))
+    }
+
     /// Encode function information for use in a transaction. Note: `args` is a string
     /// of input parameters that are parsed by alloy `DynSolType`'s  and converted into
     /// `DynSolValue`s.   See [DynSolType.coerce_str()](https://docs.rs/alloy-dyn-abi/latest/alloy_dyn_abi/enum.DynSolType.html#method.coerce_str)
@@ -198,7 +567,8 @@ impl ContractAbi {
     /// Returns a tuple with:
     /// - encoded function and args
     /// - whether the function is payable
-    /// - and the output `DynSolType` that can be used to decode the result of the call
+    /// - and the output `DynSolType` that can be used to decode the result of the call,
+    ///   or `None` if the function returns nothing
     pub fn encode_function(
         &self,
         name: &str,
@@ -213,23 +583,12 @@ impl ContractAbi {
         for f in funcs {
             let result = Self::extract(f, args);
             let is_payable = matches!(f.state_mutability, StateMutability::Payable);
-            if result.is_ok() {
+            if let Ok(values) = result {
                 // Get the return type decoder, if any...
-                let ty = match f.outputs.len() {
-                    0 => None,
-                    1 => f.outputs.first().unwrap().clone().resolve().ok(),
-                    _ => {
-                        let t = f
-                            .outputs
-                            .iter()
-                            .map(|i| i.resolve().unwrap())
-                            .collect::<Vec<_>>();
-                        Some(DynSolType::Tuple(t))
-                    }
-                };
+                let ty = Self::output_type(f);
 
                 let selector = f.selector().to_vec();
-                let encoded_args = result.unwrap().abi_encode_params();
+                let encoded_args = values.abi_encode_params();
                 let all = [selector, encoded_args].concat();
 
                 return Ok((all, is_payable, ty));
@@ -242,6 +601,160 @@ impl ContractAbi {
             "Abi: Arguments to the function do not match what is expected"
         ))
     }
+
+    /// Same as `encode_function`, but each argument may be annotated with its type,
+    /// as `type:value`, to force the type rather than rely on coercion.  Unannotated
+    /// arguments are coerced to the type expected by the function.  This also selects
+    /// the overload of the function whose parameter types match the annotations.
+    ///
+    /// ## Example
+    ///
+    /// `"(int256:-1, address:0x1111111111111111111111111111111111111111, 5)"`
+    pub fn encode_function_typed(
+        &self,
+        name: &str,
+        typed_args: &str,
+    ) -> Result<(Vec<u8>, bool, Option<DynSolType>)> {
+        let funcs = match self.abi.function(name) {
+            Some(funcs) => funcs,
+            _ => bail!("Abi: Function {} not found in the ABI!", name),
+        };
+        let args = split_typed_args(typed_args)?;
+
+        for f in funcs {
+            if f.inputs.len() != args.len() {
+                continue;
+            }
+            let types = match f
+                .inputs
+                .iter()
+                .map(|i| i.resolve())
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(types) => types,
+                _ => continue,
+            };
+            let values = types
+                .iter()
+                .zip(&args)
+                .map(|(ty, (forced, value))| match forced {
+                    Some(forced) if forced != ty => None,
+                    _ => ty.coerce_str(value).ok(),
+                })
+                .collect::<Option<Vec<_>>>();
+
+            if let Some(values) = values {
+                let is_payable = matches!(f.state_mutability, StateMutability::Payable);
+                let encoded_args = DynSolValue::Tuple(values).abi_encode_params();
+                let all = [f.selector().to_vec(), encoded_args].concat();
+                return Ok((all, is_payable, Self::output_type(f)));
+            }
+        }
+
+        Err(anyhow!(
+            "Abi: Arguments to the function do not match what is expected"
+        ))
+    }
+}
+
+/// Extract primitive Rust types from decoded values, with an error if the value
+/// is not of the expected type.
+///
+/// Note: `DynSolValue` has its own `as_address` and `as_bool` returning an `Option`,
+/// which take precedence with method call syntax.  Call these through the trait
+/// instead, e.g. `DynSolValueExt::as_address(&value)`.
+pub trait DynSolValueExt {
+    /// Any `uint<N>`
+    fn as_u256(&self) -> Result<U256>;
+    /// An `address`
+    fn as_address(&self) -> Result<Address>;
+    /// A `bool`
+    fn as_bool(&self) -> Result<bool>;
+    /// A `string`
+    fn as_string(&self) -> Result<String>;
+}
+
+impl DynSolValueExt for DynSolValue {
+    fn as_u256(&self) -> Result<U256> {
+        match self.as_uint() {
+            Some((value, _)) => Ok(value),
+            _ => bail!("Abi: expected a uint but got {:?}", self),
+        }
+    }
+
+    fn as_address(&self) -> Result<Address> {
+        match DynSolValue::as_address(self) {
+            Some(value) => Ok(value),
+            _ => bail!("Abi: expected an address but got {:?}", self),
+        }
+    }
+
+    fn as_bool(&self) -> Result<bool> {
+        match DynSolValue::as_bool(self) {
+            Some(value) => Ok(value),
+            _ => bail!("Abi: expected a bool but got {:?}", self),
+        }
+    }
+
+    fn as_string(&self) -> Result<String> {
+        match self.as_str() {
+            Some(value) => Ok(value.to_string()),
+            _ => bail!("Abi: expected a string but got {:?}", self),
+        }
+    }
+}
+
+// Split `(type:value, value, ...)` into each argument, with the type if annotated.
+// Commas and colons nested in tuples, arrays or quoted strings don't split arguments.
+fn split_typed_args(typed_args: &str) -> Result<Vec<(Option<DynSolType>, String)>> {
+    let inner = typed_args
+        .trim()
+        .strip_prefix('(')
+        .and_then(|a| a.strip_suffix(')'))
+        .ok_or(anyhow!("Abi: arguments must be enclosed in parentheses"))?;
+    if inner.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut args = Vec::new();
+    let mut start = 0;
+    for i in top_level_positions(inner, ',') {
+        args.push(&inner[start..i]);
+        start = i + 1;
+    }
+    args.push(&inner[start..]);
+
+    Ok(args
+        .into_iter()
+        .map(|arg| {
+            let arg = arg.trim();
+            match top_level_positions(arg, ':').first() {
+                Some(&i) => match DynSolType::parse(arg[..i].trim()) {
+                    Ok(ty) => (Some(ty), arg[i + 1..].trim().to_string()),
+                    _ => (None, arg.to_string()),
+                },
+                _ => (None, arg.to_string()),
+            }
+        })
+        .collect())
+}
+
+// The positions of `separator` in `s` that are not in a tuple, an array or a quoted string
+fn top_level_positions(s: &str, separator: char) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            c if c == separator && depth == 0 => positions.push(i),
+            _ => {}
+        }
+    }
+    positions
 }
 
 #[cfg(test)]
@@ -347,7 +860,7 @@ mod tests {
         assert!(hw.encode_function("hello", "(1,2").is_err());
 
         let (cencoded, is_payable, dtype) = hw
-            .encode_function("hello", &format!("(({}, {}, {}))", 10, addy.to_string(), 1))
+            .encode_function("hello", &format!("(({}, {}, {}))", 10, addy, 1))
             .unwrap();
 
         assert!(!is_payable);
@@ -379,7 +892,7 @@ mod tests {
         }
         .abi_encode();
         let (ac, _, otype) = abi
-            .encode_function("one", &format!("({},({},{}))", addy.to_string(), 10, 11))
+            .encode_function("one", &format!("({},({},{}))", addy, 10, 11))
             .unwrap();
 
         assert_eq!(sc, ac);
@@ -490,4 +1003,321 @@ mod tests {
 
         //println!("{:?}", results);
     }
+
+    #[test]
+    fn decode_borrowed_logs() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "event Transfer(address indexed from,address indexed to,uint256 amount)",
+        ]);
+        let transfer = LogData::new_unchecked(
+            vec![
+                b256!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"),
+                b256!("000000000000000000000000c2e9f25be6257c210d7adf0d4cd6e3e881ba25f8"),
+                b256!("0000000000000000000000002b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b2b"),
+            ],
+            bytes!("0000000000000000000000000000000000000000000000000000000000000005"),
+        );
+        // not in the ABI
+        let mint = LogData::new_unchecked(
+            vec![b256!(
+                "0f6798a560793a54c3bcfe86a93cde1e73087d944c0ea20544137d4121396885"
+            )],
+            Bytes::new(),
+        );
+        let logs = [
+            Log {
+                address: Address::repeat_byte(14),
+                data: mint,
+            },
+            Log {
+                address: Address::repeat_byte(14),
+                data: transfer,
+            },
+        ];
+
+        let decoded = abi.decode_logs(&logs);
+        assert_eq!(1, decoded.len());
+        let (name, values) = &decoded[0];
+        assert_eq!("Transfer", name);
+        let values = values.as_tuple().unwrap();
+        assert_eq!(DynSolValue::Address(Address::repeat_byte(0x2b)), values[1]);
+        assert_eq!(DynSolValue::Uint(U256::from(5), 256), values[2]);
+    }
+
+    #[test]
+    fn decode_named_function_output() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address to, uint256 amount) returns (bool success)",
+            "function pair() (uint256, address owner)",
+        ]);
+
+        let params = &abi.abi.function("transfer").unwrap()[0].inputs;
+        assert_eq!("to", params[0].name);
+        assert_eq!("amount", params[1].name);
+
+        let output = DynSolValue::Bool(true).abi_encode();
+        let decoded = abi.decode_function_output("transfer", &output).unwrap();
+        assert_eq!(
+            vec![("success".to_string(), DynSolValue::Bool(true))],
+            decoded
+        );
+
+        let addy = Address::repeat_byte(1);
+        let output = DynSolValue::Tuple(vec![
+            DynSolValue::Uint(U256::from(3), 256),
+            DynSolValue::Address(addy),
+        ])
+        .abi_encode_params();
+        let decoded = abi.decode_function_output("pair", &output).unwrap();
+        assert_eq!("_0", decoded[0].0);
+        assert_eq!(DynSolValue::Uint(U256::from(3), 256), decoded[0].1);
+        assert_eq!("owner", decoded[1].0);
+        assert_eq!(DynSolValue::Address(addy), decoded[1].1);
+
+        assert!(abi.decode_function_output("nope", &output).is_err());
+        assert!(abi.decode_function_output("pair", &[1u8; 3]).is_err());
+    }
+
+    #[test]
+    fn merge_and_find_selector_collisions() {
+        let mut token = ContractAbi::from_human_readable(vec![
+            "function burn(uint256)",
+            "function transfer(address, uint256) (bool)",
+            "event Transfer(address indexed from,address indexed to,uint256 amount)",
+        ]);
+        let other = ContractAbi::from_human_readable(vec![
+            "function transfer(address, uint256) (bool)",
+            "function collate_propagate_storage(bytes16)",
+            "event Mint(address indexed recip,uint256 amount)",
+        ]);
+        assert!(token.selector_collisions().is_empty());
+
+        token.merge(&other);
+        assert_eq!(1, token.abi.function("transfer").unwrap().len());
+        assert!(token.has_function("collate_propagate_storage"));
+        assert_eq!(2, token.events_logs.len());
+
+        let collisions = token.selector_collisions();
+        assert_eq!(1, collisions.len());
+        let (selector, signatures) = &collisions[0];
+        assert_eq!([0x42, 0x96, 0x6c, 0x68], *selector);
+        assert!(signatures.contains(&"burn(uint256)".to_string()));
+        assert!(signatures.contains(&"collate_propagate_storage(bytes16)".to_string()));
+    }
+
+    #[test]
+    fn dispatch_table_rejects_collisions() {
+        let mut abi = ContractAbi::from_human_readable(vec![
+            "function burn(uint256)",
+            "function transfer(address, uint256) (bool)",
+        ]);
+        let table = abi.build_dispatch_table().unwrap();
+        assert_eq!(2, table.len());
+        assert_eq!("burn(uint256)", table[&[0x42, 0x96, 0x6c, 0x68]]);
+        assert_eq!(
+            "transfer(address,uint256)",
+            table[&[0xa9, 0x05, 0x9c, 0xbb]]
+        );
+
+        abi.merge(&ContractAbi::from_human_readable(vec![
+            "function collate_propagate_storage(bytes16)",
+        ]));
+        let err = abi.build_dispatch_table().unwrap_err().to_string();
+        assert!(err.contains("0x42966c68"));
+        assert!(err.contains("burn(uint256)"));
+        assert!(err.contains("collate_propagate_storage(bytes16)"));
+    }
+
+    #[test]
+    fn selectors_in_4byte_format() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address, uint256) (bool)",
+            "function balanceOf(address) (uint256)",
+            "function approve(address, uint256) (bool)",
+        ]);
+        let selectors = abi.selector_map();
+        assert_eq!(3, selectors.len());
+        assert_eq!("transfer(address,uint256)", selectors["0xa9059cbb"]);
+        assert_eq!("balanceOf(address)", selectors["0x70a08231"]);
+        assert_eq!("approve(address,uint256)", selectors["0x095ea7b3"]);
+    }
+
+    #[test]
+    fn decode_single_unwraps_one_value() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address to, uint256 amount) returns (bool success)",
+            "function pair() (uint256, address owner)",
+        ]);
+
+        let output = DynSolValue::Bool(true).abi_encode();
+        assert_eq!(
+            DynSolValue::Bool(true),
+            abi.decode_single("transfer", &output).unwrap()
+        );
+
+        let values = vec![
+            DynSolValue::Uint(U256::from(3), 256),
+            DynSolValue::Address(Address::repeat_byte(1)),
+        ];
+        let output = DynSolValue::Tuple(values.clone()).abi_encode_params();
+        assert_eq!(
+            DynSolValue::Tuple(values),
+            abi.decode_single("pair", &output).unwrap()
+        );
+    }
+
+    #[test]
+    fn list_events_and_errors() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address, uint256) (bool)",
+            "event Transfer(address indexed from, address indexed to, uint256 amount)",
+            "event Transfer(address indexed from, address indexed to, uint256 amount, bytes data)",
+            "event Approval(address indexed owner, address indexed spender, uint256 amount)",
+            "error InsufficientBalance(uint256 available, uint256 required)",
+        ]);
+
+        assert_eq!(vec!["transfer"], abi.function_names());
+        assert_eq!(vec!["Approval", "Transfer", "Transfer"], abi.event_names());
+        assert_eq!(vec!["InsufficientBalance"], abi.error_names());
+    }
+
+    #[test]
+    fn decode_calldata_to_name_and_args() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address, uint256) (bool)",
+            "function approve(address, uint256) (bool)",
+        ]);
+        let addy = Address::repeat_byte(1);
+
+        let (calldata, _, _) = abi
+            .encode_function("transfer", &format!("({}, 10)", addy))
+            .unwrap();
+        let selector: [u8; 4] = calldata[..4].try_into().unwrap();
+        assert_eq!(
+            Some("transfer".to_string()),
+            abi.function_for_selector(&selector)
+        );
+        assert_eq!(None, abi.function_for_selector(&[0u8; 4]));
+
+        let (name, args) = abi.decode_calldata(&calldata).unwrap();
+        assert_eq!("transfer", name);
+        assert_eq!(
+            vec![
+                DynSolValue::Address(addy),
+                DynSolValue::Uint(U256::from(10), 256)
+            ],
+            args
+        );
+
+        assert!(abi.decode_calldata(&[0u8; 3]).is_err());
+        assert!(abi.decode_calldata(&[0u8; 36]).is_err());
+    }
+
+    #[test]
+    fn encode_with_explicit_types() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function set(uint256)",
+            "function set(int256)",
+            "function send(address, uint256, (uint8, bool))",
+        ]);
+
+        let (encoded, _, _) = abi.encode_function_typed("set", "(int256:-1)").unwrap();
+        let selector = &abi.abi.function("set").unwrap()[1].selector();
+        assert_eq!(selector.as_slice(), &encoded[..4]);
+        assert_eq!([0xffu8; 32].as_slice(), &encoded[4..]);
+
+        let (encoded, _, _) = abi.encode_function_typed("set", "(uint256:1)").unwrap();
+        let selector = &abi.abi.function("set").unwrap()[0].selector();
+        assert_eq!(selector.as_slice(), &encoded[..4]);
+        assert_eq!(U256::from(1), U256::from_be_slice(&encoded[4..]));
+
+        // mixed annotated and unannotated arguments
+        let addy = Address::repeat_byte(1);
+        let (typed, _, _) = abi
+            .encode_function_typed("send", &format!("(address:{}, 5, (2, true))", addy))
+            .unwrap();
+        let (coerced, _, _) = abi
+            .encode_function("send", &format!("({}, 5, (2, true))", addy))
+            .unwrap();
+        assert_eq!(coerced, typed);
+
+        assert!(abi.encode_function_typed("set", "(bytes32:1)").is_err());
+        assert!(abi.encode_function_typed("set", "int256:1").is_err());
+    }
+
+    #[test]
+    fn encode_typed_string_with_separators() {
+        let abi = ContractAbi::from_human_readable(vec!["function greet(string, uint256)"]);
+        let selector = abi.abi.function("greet").unwrap()[0].selector();
+        let expected = |greeting: &str| {
+            let args = DynSolValue::Tuple(vec![
+                DynSolValue::String(greeting.to_string()),
+                DynSolValue::Uint(U256::from(1), 256),
+            ]);
+            [selector.to_vec(), args.abi_encode_params()].concat()
+        };
+
+        let (encoded, _, _) = abi
+            .encode_function_typed("greet", r#"(string:"hello, world", 1)"#)
+            .unwrap();
+        assert_eq!(expected("hello, world"), encoded);
+
+        // a colon in an unannotated string isn't a type annotation
+        let (encoded, _, _) = abi
+            .encode_function_typed("greet", r#"("uint256:(1, 2)", uint256:1)"#)
+            .unwrap();
+        assert_eq!(expected("uint256:(1, 2)"), encoded);
+    }
+
+    #[test]
+    fn abi_with_hex_bytecode() {
+        let abi = r#"[{"type":"function","name":"value","inputs":[],"outputs":[{"name":"","type":"uint256"}],"stateMutability":"view"}]"#;
+
+        let contract = ContractAbi::from_abi_and_hex_bytecode(abi, "0x6001600201").unwrap();
+        assert_eq!(
+            Some(vec![0x60, 0x01, 0x60, 0x02, 0x01]),
+            contract.bytecode()
+        );
+        assert!(contract.has_function("value"));
+
+        let contract = ContractAbi::from_abi_and_hex_bytecode(abi, "6001600201").unwrap();
+        assert_eq!(
+            Some(vec![0x60, 0x01, 0x60, 0x02, 0x01]),
+            contract.bytecode()
+        );
+
+        assert!(ContractAbi::from_abi_and_hex_bytecode(abi, "0x60zz").is_err());
+    }
+
+    #[test]
+    fn extract_primitive_values() {
+        let abi = ContractAbi::from_human_readable(vec![
+            "function info() (uint256, address, bool, string)",
+        ]);
+        let owner = Address::repeat_byte(7);
+        let output = DynSolValue::Tuple(vec![
+            DynSolValue::Uint(U256::from(42), 256),
+            DynSolValue::Address(owner),
+            DynSolValue::Bool(true),
+            DynSolValue::String("hello".into()),
+        ])
+        .abi_encode_params();
+
+        let values = abi
+            .decode_function_output("info", &output)
+            .unwrap()
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        assert_eq!(U256::from(42), values[0].as_u256().unwrap());
+        assert_eq!(owner, DynSolValueExt::as_address(&values[1]).unwrap());
+        assert!(DynSolValueExt::as_bool(&values[2]).unwrap());
+        assert_eq!("hello", values[3].as_string().unwrap());
+
+        // type mismatch
+        assert!(DynSolValueExt::as_address(&values[0]).is_err());
+        assert!(values[1].as_u256().is_err());
+        assert!(DynSolValueExt::as_bool(&values[3]).is_err());
+        assert!(values[2].as_string().is_err());
+    }
 }
@@ -0,0 +1,469 @@
+//!
+//! A fork source that reads state directly from the LevelDB database of a local
+//! geth node, rather than through its JSON-RPC endpoint
+//!
+use alloy_primitives::{keccak256, Address, U256};
+use alloy_rlp::Header;
+use revm::primitives::{AccountInfo, Bytecode, B256, KECCAK_EMPTY};
+use rusty_leveldb::{Options, DB};
+use std::{
+    path::Path,
+    sync::mpsc::{channel, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    db::fork_source::{ForkBlock, ForkSource},
+    errors::DatabaseError,
+};
+
+// the keys of geth's database schema, see `core/rawdb/schema.go`
+const HEAD_BLOCK_KEY: &[u8] = b"LastBlock";
+const SNAPSHOT_ROOT_KEY: &[u8] = b"SnapshotRoot";
+const CONFIG_PREFIX: &[u8] = b"ethereum-config-";
+const HEADER_PREFIX: &[u8] = b"h";
+const HEADER_HASH_SUFFIX: &[u8] = b"n";
+const HEADER_NUMBER_PREFIX: &[u8] = b"H";
+const SNAPSHOT_ACCOUNT_PREFIX: &[u8] = b"a";
+const SNAPSHOT_STORAGE_PREFIX: &[u8] = b"o";
+const CODE_PREFIX: &[u8] = b"c";
+
+// a read of `key`, answered on the channel
+type Read = (Vec<u8>, Sender<Option<Vec<u8>>>);
+
+/// Reads the state of a fork from the `chaindata` LevelDB database of a geth node,
+/// e.g. `~/.ethereum/geth/chaindata`, which is much faster than RPC for heavy
+/// workloads.
+///
+/// Accounts and storage are read from geth's snapshot, which holds the state of the
+/// head block only, so the fork must be at the head block.  The node must be
+/// stopped, cleanly, as the database can't be shared.  Pebble databases, the default
+/// of recent geth versions, are not supported.
+#[derive(Debug)]
+pub struct DatadirForkSource {
+    reads: Sender<Read>,
+    owner: Option<JoinHandle<()>>,
+    head: u64,
+}
+
+impl DatadirForkSource {
+    /// Open the geth database in the directory `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, DatabaseError> {
+        let path = path.as_ref().to_path_buf();
+        let (reads, requests) = channel::<Read>();
+        let (opened, open_result) = channel();
+
+        // the database can't be shared between threads, so a thread owns it and
+        // answers the reads, until the source is dropped
+        let owner = thread::spawn(move || {
+            let options = Options {
+                create_if_missing: false,
+                ..Default::default()
+            };
+            let mut db = match DB::open(&path, options) {
+                Ok(db) => {
+                    let _ = opened.send(Ok(()));
+                    db
+                }
+                Err(e) => {
+                    let _ = opened.send(Err(format!(
+                        "Datadir: failed to open {}: {}",
+                        path.display(),
+                        e
+                    )));
+                    return;
+                }
+            };
+            for (key, reply) in requests {
+                let _ = reply.send(db.get(&key).map(|value| value.to_vec()));
+            }
+        });
+        open_result
+            .recv()
+            .map_err(|e| DatabaseError::Other(e.to_string()))?
+            .map_err(DatabaseError::Other)?;
+
+        let mut source = Self {
+            reads,
+            owner: Some(owner),
+            head: 0,
+        };
+        let hash = source.read_required(HEAD_BLOCK_KEY)?;
+        let number = source.read_required(&[HEADER_NUMBER_PREFIX, &hash].concat())?;
+        source.head = u64::from_be_bytes(
+            number
+                .try_into()
+                .map_err(|_| DatabaseError::Other("Datadir: invalid head block number".into()))?,
+        );
+
+        // the snapshot must be at the head block to read its state
+        let root = source.read_required(SNAPSHOT_ROOT_KEY)?;
+        if root != source.header(source.head)?.state_root {
+            return Err(DatabaseError::Other(
+                "Datadir: the state snapshot is not at the head block, was the node stopped cleanly?"
+                    .into(),
+            ));
+        }
+        Ok(source)
+    }
+
+    /// The number of the head block, the only block whose state can be read
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    fn read(&self, key: &[u8]) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let (reply, value) = channel();
+        self.reads
+            .send((key.to_vec(), reply))
+            .map_err(|e| DatabaseError::Other(e.to_string()))?;
+        value
+            .recv()
+            .map_err(|e| DatabaseError::Other(e.to_string()))
+    }
+
+    fn read_required(&self, key: &[u8]) -> Result<Vec<u8>, DatabaseError> {
+        self.read(key)?.ok_or_else(|| {
+            DatabaseError::Other(format!("Datadir: missing key 0x{}", hex::encode(key)))
+        })
+    }
+
+    fn canonical_hash(&self, number: u64) -> Result<Vec<u8>, DatabaseError> {
+        self.read(&[HEADER_PREFIX, &number.to_be_bytes(), HEADER_HASH_SUFFIX].concat())?
+            .ok_or(DatabaseError::GetBlockHash(U256::from(number)))
+    }
+
+    fn header(&self, number: u64) -> Result<BlockHeader, DatabaseError> {
+        let hash = self.canonical_hash(number)?;
+        let rlp = self
+            .read(&[HEADER_PREFIX, &number.to_be_bytes(), &hash].concat())?
+            .ok_or(DatabaseError::GetBlock(Some(number)))?;
+        BlockHeader::decode(&rlp).ok_or(DatabaseError::GetBlock(Some(number)))
+    }
+
+    // the chain id is in the chain config, stored with the hash of the genesis block
+    fn chain_id(&self) -> Result<u64, DatabaseError> {
+        let genesis = self.canonical_hash(0)?;
+        let config = self.read_required(&[CONFIG_PREFIX, &genesis].concat())?;
+        serde_json::from_slice::<serde_json::Value>(&config)
+            .ok()
+            .and_then(|config| config["chainId"].as_u64())
+            .ok_or_else(|| DatabaseError::Other("Datadir: chain config has no chainId".into()))
+    }
+
+    fn check_head(&self, number: u64) -> Result<(), DatabaseError> {
+        if number != self.head {
+            return Err(DatabaseError::Other(format!(
+                "Datadir: only the state of the head block {} is available, not {}",
+                self.head, number
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ForkSource for DatadirForkSource {
+    fn block(&self, number: Option<u64>) -> Result<ForkBlock, DatabaseError> {
+        let number = number.unwrap_or(self.head);
+        let header = self.header(number)?;
+        Ok(ForkBlock {
+            number,
+            timestamp: header.timestamp,
+            excess_blob_gas: header.excess_blob_gas,
+            chain_id: self.chain_id()?,
+            base_fee: header.base_fee,
+            coinbase: header.coinbase,
+            prevrandao: Some(header.mix_hash),
+            gas_limit: header.gas_limit,
+        })
+    }
+
+    fn basic(&self, address: Address, number: u64) -> Result<AccountInfo, DatabaseError> {
+        self.check_head(number)?;
+        let key = [SNAPSHOT_ACCOUNT_PREFIX, keccak256(address).as_slice()].concat();
+        match self.read(&key)? {
+            Some(rlp) => decode_slim_account(&rlp).ok_or(DatabaseError::GetAccount(address)),
+            _ => Ok(AccountInfo::default()),
+        }
+    }
+
+    fn code(&self, address: Address, number: u64) -> Result<Bytecode, DatabaseError> {
+        let info = self.basic(address, number)?;
+        if info.code_hash == KECCAK_EMPTY {
+            return Ok(Bytecode::default());
+        }
+        let code = self
+            .read(&[CODE_PREFIX, info.code_hash.as_slice()].concat())?
+            .ok_or(DatabaseError::MissingCode(info.code_hash))?;
+        Ok(Bytecode::new_raw(code.into()))
+    }
+
+    fn storage(&self, address: Address, index: U256, number: u64) -> Result<U256, DatabaseError> {
+        self.check_head(number)?;
+        let key = [
+            SNAPSHOT_STORAGE_PREFIX,
+            keccak256(address).as_slice(),
+            keccak256(B256::from(index)).as_slice(),
+        ]
+        .concat();
+        match self.read(&key)? {
+            Some(rlp) => rlp_string(&rlp)
+                .and_then(U256::try_from_be_slice)
+                .ok_or(DatabaseError::GetStorage(address, index)),
+            _ => Ok(U256::ZERO),
+        }
+    }
+
+    fn block_hash(&self, number: u64) -> Result<B256, DatabaseError> {
+        self.canonical_hash(number)
+            .map(|hash| B256::from_slice(&hash))
+    }
+}
+
+// close the database before returning, so it can be opened again right away
+impl Drop for DatadirForkSource {
+    fn drop(&mut self) {
+        // the owner thread stops once the channel is closed
+        self.reads = channel().0;
+        if let Some(owner) = self.owner.take() {
+            let _ = owner.join();
+        }
+    }
+}
+
+// The fields of a block header used by a fork
+#[derive(Debug)]
+struct BlockHeader {
+    coinbase: Address,
+    state_root: Vec<u8>,
+    gas_limit: u64,
+    timestamp: u64,
+    mix_hash: B256,
+    base_fee: Option<U256>,
+    excess_blob_gas: Option<u64>,
+}
+
+impl BlockHeader {
+    // the fields are, in order: parent hash, uncles hash, coinbase, state root,
+    // transactions root, receipts root, logs bloom, difficulty, number, gas limit,
+    // gas used, timestamp, extra data, mix hash, nonce, then the optional base fee,
+    // withdrawals root, blob gas used and excess blob gas
+    fn decode(rlp: &[u8]) -> Option<Self> {
+        let fields = rlp_list(rlp)?;
+        Some(Self {
+            coinbase: Address::try_from(*fields.get(2)?).ok()?,
+            state_root: fields.get(3)?.to_vec(),
+            gas_limit: rlp_u64(fields.get(9)?)?,
+            timestamp: rlp_u64(fields.get(11)?)?,
+            mix_hash: B256::try_from(*fields.get(13)?).ok()?,
+            base_fee: match fields.get(15) {
+                Some(fee) => Some(U256::try_from_be_slice(fee)?),
+                _ => None,
+            },
+            excess_blob_gas: match fields.get(18) {
+                Some(gas) => Some(rlp_u64(gas)?),
+                _ => None,
+            },
+        })
+    }
+}
+
+// An account in geth's snapshot: `[nonce, balance, storage root, code hash]`, where
+// an empty storage root or code hash is left out.  The code is read when needed.
+fn decode_slim_account(rlp: &[u8]) -> Option<AccountInfo> {
+    let fields = rlp_list(rlp)?;
+    let code_hash = match *fields.get(3)? {
+        [] => KECCAK_EMPTY,
+        hash => B256::try_from(hash).ok()?,
+    };
+    Some(AccountInfo {
+        nonce: rlp_u64(fields.first()?)?,
+        balance: U256::try_from_be_slice(fields.get(1)?)?,
+        code_hash,
+        code: (code_hash == KECCAK_EMPTY).then(Bytecode::default),
+    })
+}
+
+// The payloads of the strings in the RLP list `rlp`
+fn rlp_list(mut rlp: &[u8]) -> Option<Vec<&[u8]>> {
+    let header = Header::decode(&mut rlp).ok()?;
+    if !header.list {
+        return None;
+    }
+    let mut payload = rlp.get(..header.payload_length)?;
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let item = Header::decode(&mut payload).ok()?;
+        if item.list {
+            return None;
+        }
+        items.push(payload.get(..item.payload_length)?);
+        payload = &payload[item.payload_length..];
+    }
+    Some(items)
+}
+
+// The payload of the RLP string `rlp`
+fn rlp_string(mut rlp: &[u8]) -> Option<&[u8]> {
+    let header = Header::decode(&mut rlp).ok()?;
+    match header.list {
+        false => rlp.get(..header.payload_length),
+        true => None,
+    }
+}
+
+fn rlp_u64(payload: &[u8]) -> Option<u64> {
+    U256::try_from_be_slice(payload)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BaseEvm, CreateFork};
+    use alloy_rlp::Encodable;
+
+    const BLOCK: u64 = 100;
+
+    fn rlp_list(items: &[&dyn Encodable]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for item in items {
+            item.encode(&mut payload);
+        }
+        let mut out = Vec::new();
+        Header {
+            list: true,
+            payload_length: payload.len(),
+        }
+        .encode(&mut out);
+        [out, payload].concat()
+    }
+
+    // A tiny geth database at block 100, with an account holding 1 ether and a
+    // contract whose slot 1 is 42
+    fn write_fixture(path: &Path, account: Address, contract: Address, code: &[u8]) {
+        let mut db = DB::open(path, Options::default()).unwrap();
+        let state_root = B256::repeat_byte(5);
+        let genesis = B256::repeat_byte(1);
+        let hash = B256::repeat_byte(2);
+        let empty = alloy_primitives::Bytes::new();
+
+        db.put(
+            &[CONFIG_PREFIX, genesis.as_slice()].concat(),
+            br#"{"chainId": 1}"#,
+        )
+        .unwrap();
+        db.put(
+            &[HEADER_PREFIX, &0u64.to_be_bytes(), HEADER_HASH_SUFFIX].concat(),
+            genesis.as_slice(),
+        )
+        .unwrap();
+        db.put(
+            &[HEADER_PREFIX, &BLOCK.to_be_bytes(), HEADER_HASH_SUFFIX].concat(),
+            hash.as_slice(),
+        )
+        .unwrap();
+        db.put(HEAD_BLOCK_KEY, hash.as_slice()).unwrap();
+        db.put(
+            &[HEADER_NUMBER_PREFIX, hash.as_slice()].concat(),
+            &BLOCK.to_be_bytes(),
+        )
+        .unwrap();
+        let header = rlp_list(&[
+            &B256::ZERO,
+            &B256::ZERO,
+            &Address::repeat_byte(7),
+            &state_root,
+            &B256::ZERO,
+            &B256::ZERO,
+            &alloy_primitives::Bytes::from(vec![0u8; 256]),
+            &0u64,
+            &BLOCK,
+            &30_000_000u64,
+            &21_000u64,
+            &1_700_000_000u64,
+            &empty,
+            &B256::repeat_byte(8),
+            &alloy_primitives::Bytes::from(vec![0u8; 8]),
+            &U256::from(1e9),
+        ]);
+        db.put(
+            &[HEADER_PREFIX, &BLOCK.to_be_bytes(), hash.as_slice()].concat(),
+            &header,
+        )
+        .unwrap();
+        db.put(SNAPSHOT_ROOT_KEY, state_root.as_slice()).unwrap();
+
+        let account_rlp = rlp_list(&[&0u64, &U256::from(1e18), &empty, &empty]);
+        db.put(
+            &[SNAPSHOT_ACCOUNT_PREFIX, keccak256(account).as_slice()].concat(),
+            &account_rlp,
+        )
+        .unwrap();
+        let code_hash = keccak256(code);
+        let contract_rlp = rlp_list(&[&1u64, &U256::ZERO, &B256::repeat_byte(9), &code_hash]);
+        db.put(
+            &[SNAPSHOT_ACCOUNT_PREFIX, keccak256(contract).as_slice()].concat(),
+            &contract_rlp,
+        )
+        .unwrap();
+        db.put(&[CODE_PREFIX, code_hash.as_slice()].concat(), code)
+            .unwrap();
+        let mut value = Vec::new();
+        U256::from(42).encode(&mut value);
+        let slot = keccak256(B256::from(U256::from(1)));
+        db.put(
+            &[
+                SNAPSHOT_STORAGE_PREFIX,
+                keccak256(contract).as_slice(),
+                slot.as_slice(),
+            ]
+            .concat(),
+            &value,
+        )
+        .unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn read_state_from_a_geth_datadir() {
+        let dir = tempfile::tempdir().unwrap();
+        let account = Address::repeat_byte(3);
+        let contract = Address::repeat_byte(4);
+        // runtime: return the value of slot 1
+        let code = hex::decode("60015460005260206000f3").unwrap();
+        write_fixture(dir.path(), account, contract, &code);
+
+        let source = DatadirForkSource::open(dir.path()).unwrap();
+        assert_eq!(BLOCK, source.head());
+        let block = source.block(None).unwrap();
+        assert_eq!(1, block.chain_id);
+        assert_eq!(1_700_000_000, block.timestamp);
+        assert_eq!(Some(U256::from(1e9)), block.base_fee);
+        assert_eq!(B256::repeat_byte(2), source.block_hash(BLOCK).unwrap());
+        assert!(source.basic(account, BLOCK - 1).is_err());
+        drop(source);
+
+        let mut evm = BaseEvm::new(Some(
+            CreateFork::from_datadir(dir.path().into(), BLOCK).unwrap(),
+        ));
+        assert_eq!(BLOCK, evm.block_number());
+        assert_eq!(U256::from(1e18), evm.get_balance(account).unwrap());
+        assert_eq!(
+            U256::from(42),
+            evm.get_storage_slot(contract, U256::from(1)).unwrap()
+        );
+        assert_eq!(
+            U256::ZERO,
+            evm.get_storage_slot(contract, U256::from(2)).unwrap()
+        );
+        let result = evm.transact_call(contract, vec![], U256::ZERO).unwrap();
+        assert_eq!(U256::from(42), U256::from_be_slice(&result.result));
+        // a missing account is empty
+        assert_eq!(
+            U256::ZERO,
+            evm.get_balance(Address::repeat_byte(5)).unwrap()
+        );
+
+        assert!(DatadirForkSource::open(dir.path().join("missing")).is_err());
+    }
+}
@@ -1,31 +1,85 @@
 //
 use crate::{
-    db::fork_backend::ForkBackend,
+    db::{fork_backend::ForkBackend, fork_source::ForkSource},
     errors::DatabaseError,
     snapshot::{SnapShot, SnapShotAccountRecord, SnapShotSource},
 };
 use alloy_primitives::U256;
-use revm::db::{CacheDB, DatabaseRef};
+use revm::db::{AccountState, CacheDB, DatabaseRef};
 use revm::primitives::Address;
 use revm::primitives::{Account, AccountInfo, Bytecode, HashMap as Map, B256};
 use revm::{Database, DatabaseCommit};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+};
+
+/// The accounts and storage slots read through a fork
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AccessSet {
+    /// the accounts read
+    pub accounts: BTreeSet<Address>,
+    /// the storage slots read, by account
+    pub storage: BTreeMap<Address, BTreeSet<U256>>,
+}
 
 #[derive(Clone, Debug)]
 pub struct Fork {
     pub db: CacheDB<ForkBackend>,
     pub block_number: u64,
     pub timestamp: u64,
+    pub excess_blob_gas: Option<u64>,
+    pub chain_id: u64,
+    // shared by clones, so accesses made by a discarded clone (e.g. a simulation)
+    // are kept
+    accesses: Arc<Mutex<Option<AccessSet>>>,
 }
 
 impl Fork {
-    pub fn new(url: &str, starting_block_number: Option<u64>) -> Self {
-        let backend = ForkBackend::new(url, starting_block_number);
+    pub fn from_source(
+        source: Arc<dyn ForkSource>,
+        starting_block_number: Option<u64>,
+    ) -> Result<Self, DatabaseError> {
+        let backend = ForkBackend::from_source(source, starting_block_number)?;
         let block_number = backend.block_number;
         let timestamp = backend.timestamp;
-        Self {
+        let excess_blob_gas = backend.excess_blob_gas;
+        let chain_id = backend.chain_id;
+        Ok(Self {
             db: CacheDB::new(backend),
             block_number,
             timestamp,
+            excess_blob_gas,
+            chain_id,
+            accesses: Arc::default(),
+        })
+    }
+
+    /// Start recording the accounts and storage slots read, discarding any accesses
+    /// already recorded
+    pub fn start_recording_accesses(&self) {
+        *self.accesses.lock().unwrap() = Some(AccessSet::default());
+    }
+
+    /// Return the accounts and storage slots read since recording started
+    pub fn accesses(&self) -> AccessSet {
+        self.accesses.lock().unwrap().clone().unwrap_or_default()
+    }
+
+    fn record_account(&self, address: Address) {
+        if let Some(accesses) = self.accesses.lock().unwrap().as_mut() {
+            accesses.accounts.insert(address);
+        }
+    }
+
+    fn record_slots(&self, address: Address, slots: &[U256]) {
+        if let Some(accesses) = self.accesses.lock().unwrap().as_mut() {
+            accesses.accounts.insert(address);
+            accesses
+                .storage
+                .entry(address)
+                .or_default()
+                .extend(slots.iter().cloned());
         }
     }
 
@@ -37,6 +91,45 @@ impl Fork {
         &mut self.db
     }
 
+    /// Read many storage `slots` for `address`.  Slots not already in the cache are
+    /// fetched from the remote node concurrently and cached.
+    pub fn storage_batch(
+        &mut self,
+        address: Address,
+        slots: &[U256],
+    ) -> Result<Vec<U256>, DatabaseError> {
+        self.record_slots(address, slots);
+        let uncached = match self.db.accounts.get(&address) {
+            Some(account) if account.account_state == AccountState::StorageCleared => vec![],
+            Some(account) => slots
+                .iter()
+                .filter(|slot| !account.storage.contains_key(slot))
+                .cloned()
+                .collect::<Vec<_>>(),
+            _ => slots.to_vec(),
+        };
+
+        if !uncached.is_empty() {
+            let values = self.db.db.storage_batch_ref(address, &uncached)?;
+            let account = self.db.load_account(address)?;
+            for (slot, value) in uncached.into_iter().zip(values) {
+                account.storage.insert(slot, value);
+            }
+        }
+
+        slots
+            .iter()
+            .map(|slot| Database::storage(&mut self.db, address, *slot))
+            .collect()
+    }
+
+    /// Remove everything written locally.  The local database is rebuilt on the
+    /// `ForkBackend`, so state already fetched from the fork is reloaded from its
+    /// cache on next access, without a new fetch.
+    pub fn reset_local_changes(&mut self) {
+        self.db = CacheDB::new(self.db.db.clone());
+    }
+
     pub fn create_snapshot(&self, block_num: u64, timestamp: u64) -> anyhow::Result<SnapShot> {
         let accounts = self
             .database()
@@ -79,6 +172,7 @@ impl Database for Fork {
         // Note: this will always return Some, since the `SharedBackend` will always load the
         // account, this differs from `<CacheDB as Database>::basic`, See also
         // [MemDb::ensure_loaded](crate::backend::MemDb::ensure_loaded)
+        self.record_account(address);
         Database::basic(&mut self.db, address)
     }
 
@@ -87,6 +181,7 @@ impl Database for Fork {
     }
 
     fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.record_slots(address, &[index]);
         Database::storage(&mut self.db, address, index)
     }
 
@@ -99,6 +194,7 @@ impl DatabaseRef for Fork {
     type Error = DatabaseError;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        self.record_account(address);
         self.db.basic_ref(address)
     }
 
@@ -107,6 +203,7 @@ impl DatabaseRef for Fork {
     }
 
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        self.record_slots(address, &[index]);
         DatabaseRef::storage_ref(&self.db, address, index)
     }
 
@@ -1,64 +1,64 @@
 use alloy_primitives::{Address, U256};
-use anyhow::Result;
-use ethers_core::types::{Block, BlockId, BlockNumber, TxHash, H160, H256, U64};
-use ethers_providers::{Http, Middleware, Provider, ProviderError};
+use anyhow::{anyhow, Result};
+use ethers_core::types::{Block, BlockId, BlockNumber, Transaction, TxHash, H160, H256, U64};
+use ethers_providers::{Http, Middleware, Provider, ProviderError, RpcError};
+use futures::{stream, StreamExt};
 use revm::{
-    primitives::{AccountInfo, Bytecode, B256, KECCAK_EMPTY},
+    primitives::{AccountInfo, Bytecode, HashMap as Map, B256, KECCAK_EMPTY},
     DatabaseRef,
 };
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
 use tokio::runtime::{Builder, Handle, RuntimeFlavor};
 
-use crate::errors::DatabaseError;
+use crate::{
+    db::{
+        fork_source::{BlockTag, ForkBlock, ForkSource, ForkTransaction},
+        DEFAULT_MAX_CONCURRENT_REQUESTS, DEFAULT_STORAGE_CHUNK_SIZE,
+    },
+    errors::DatabaseError,
+};
 
 pub type HttpProvider = Provider<Http>;
 
+/// Fetches remote state from a JSON-RPC node
 #[derive(Clone, Debug)]
-pub struct ForkBackend {
+pub struct RpcForkSource {
     provider: Arc<HttpProvider>,
-    pub block_number: u64,
-    pub timestamp: u64,
+    max_concurrent_requests: usize,
+    // set once the node answers that it doesn't support `eth_getProof`
+    proof_unsupported: Arc<AtomicBool>,
 }
 
-impl ForkBackend {
-    pub fn new(url: &str, starting_block_number: Option<u64>) -> Self {
-        let client =
-            Provider::<Http>::try_from(url).expect("ForkBackend: failed to load HTTP provider");
-        let provider = Arc::new(client);
-
-        let blockid = if let Some(bn) = starting_block_number {
-            BlockId::from(U64::from(bn))
-        } else {
-            BlockId::from(BlockNumber::Latest)
-        };
-
-        let blk = match Self::block_on(provider.get_block(blockid)) {
-            Ok(Some(b)) => b,
-            _ => panic!("ForkBackend: failed to load block information"),
-        };
+impl RpcForkSource {
+    pub fn new(url: &str) -> Self {
+        Self::try_new(url).expect("ForkBackend: failed to load HTTP provider")
+    }
 
-        let block_number = blk
-            .number
-            .expect("ForkBackend: Got 'pending' block number")
-            .as_u64();
-        let timestamp = blk.timestamp.as_u64();
-        /*
-        let block_number = if let Some(bn) = starting_block_number {
-            bn
-        } else {
-            Self::block_on(provider.get_block_number())
-                .expect("ForkBackend: failed to load latest blocknumber from remote")
-                .as_u64()
-        };
-        */
+    /// Same as `new`, but returns an error if `url` is not a valid URL
+    pub fn try_new(url: &str) -> Result<Self> {
+        let client = Provider::<Http>::try_from(url)
+            .map_err(|e| anyhow!("ForkBackend: invalid fork url {:?}: {}", url, e))?;
+        Ok(Self::from_provider(Arc::new(client)))
+    }
 
+    /// Use an existing `provider`, which may be shared with other forks
+    pub fn from_provider(provider: Arc<HttpProvider>) -> Self {
         Self {
             provider,
-            block_number,
-            timestamp,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            proof_unsupported: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Limit the number of requests sent at the same time when fetching many values
+    pub fn with_max_concurrent_requests(mut self, max: usize) -> Self {
+        self.max_concurrent_requests = max.max(1);
+        self
+    }
+
     // adapted from revm ethersdb
     #[inline]
     fn block_on<F>(f: F) -> F::Output
@@ -89,17 +89,74 @@ impl ForkBackend {
         }
     }
 
-    fn fetch_basic_from_fork(&self, address: Address) -> Result<AccountInfo, ProviderError> {
-        let add = H160::from(address.0 .0);
-        let bn: Option<BlockId> = Some(BlockId::from(self.block_number));
+    fn fetch_block_from_fork(&self, number: Option<u64>) -> Result<ForkBlock, ProviderError> {
+        let blockid = if let Some(bn) = number {
+            BlockId::from(U64::from(bn))
+        } else {
+            BlockId::from(BlockNumber::Latest)
+        };
+        self.fetch_block_by_id(blockid)
+    }
 
-        let f = async {
-            let nonce = self.provider.get_transaction_count(add, bn);
-            let balance = self.provider.get_balance(add, bn);
-            let code = self.provider.get_code(add, bn);
-            tokio::join!(nonce, balance, code)
+    fn fetch_block_by_id(&self, blockid: BlockId) -> Result<ForkBlock, ProviderError> {
+        let blk = match Self::block_on(self.provider.get_block(blockid))? {
+            Some(b) => b,
+            _ => return Err(ProviderError::CustomError("block not found".into())),
         };
-        let (nonce, balance, code) = Self::block_on(f);
+        let number = blk
+            .number
+            .ok_or_else(|| ProviderError::CustomError("got 'pending' block number".into()))?
+            .as_u64();
+        let chain_id = Self::block_on(self.provider.get_chainid())?.as_u64();
+
+        Ok(ForkBlock {
+            number,
+            timestamp: blk.timestamp.as_u64(),
+            excess_blob_gas: blk.excess_blob_gas.map(|g| g.as_u64()),
+            chain_id,
+            base_fee: blk.base_fee_per_gas.map(|fee| U256::from_limbs(fee.0)),
+            coinbase: blk.author.map(|a| Address::from(a.0)).unwrap_or_default(),
+            prevrandao: blk.mix_hash.map(|h| B256::from(h.0)),
+            gas_limit: blk.gas_limit.as_u64(),
+        })
+    }
+
+    // The nonce, balance and code hash of an account from a single `eth_getProof`.
+    // The code is left out and fetched when needed, see `fetch_code_from_fork`.
+    // Falls back to fetching the code for nodes that don't support `eth_getProof`,
+    // which is remembered so it's only tried once.
+    async fn fetch_account(
+        &self,
+        address: Address,
+        bn: Option<BlockId>,
+    ) -> Result<AccountInfo, ProviderError> {
+        let add = H160::from(address.0 .0);
+        if !self.proof_unsupported.load(Ordering::Relaxed) {
+            match self.provider.get_proof(add, vec![], bn).await {
+                Ok(proof) => {
+                    // some nodes return a zero hash for an account that doesn't exist
+                    let code_hash = match B256::from(proof.code_hash.0) {
+                        hash if hash == B256::ZERO => KECCAK_EMPTY,
+                        hash => hash,
+                    };
+                    return Ok(AccountInfo {
+                        balance: U256::from_limbs(proof.balance.0),
+                        nonce: proof.nonce.as_u64(),
+                        code_hash,
+                        code: (code_hash == KECCAK_EMPTY).then(Bytecode::default),
+                    });
+                }
+                Err(e) if is_method_not_found(&e) => {
+                    self.proof_unsupported.store(true, Ordering::Relaxed)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let nonce = self.provider.get_transaction_count(add, bn);
+        let balance = self.provider.get_balance(add, bn);
+        let code = self.provider.get_code(add, bn);
+        let (nonce, balance, code) = tokio::join!(nonce, balance, code);
 
         let balance = U256::from_limbs(balance?.0);
         let nonce = nonce?.as_u64();
@@ -108,52 +165,558 @@ impl ForkBackend {
         Ok(AccountInfo::new(balance, nonce, code_hash, bytecode))
     }
 
+    fn fetch_basic_from_fork(
+        &self,
+        address: Address,
+        number: u64,
+    ) -> Result<AccountInfo, ProviderError> {
+        Self::block_on(self.fetch_account(address, Some(BlockId::from(number))))
+    }
+
+    fn fetch_basic_batch_from_fork(
+        &self,
+        addresses: &[Address],
+        number: u64,
+    ) -> Result<Vec<AccountInfo>, ProviderError> {
+        let bn: Option<BlockId> = Some(BlockId::from(number));
+
+        // at most `max_concurrent_requests` accounts are fetched at once
+        let f = stream::iter(
+            addresses
+                .iter()
+                .map(|address| self.fetch_account(*address, bn)),
+        )
+        .buffered(self.max_concurrent_requests)
+        .collect::<Vec<_>>();
+        Self::block_on(f).into_iter().collect()
+    }
+
+    fn fetch_code_from_fork(
+        &self,
+        address: Address,
+        number: u64,
+    ) -> Result<Bytecode, ProviderError> {
+        let add = H160::from(address.0 .0);
+        let code = Self::block_on(self.provider.get_code(add, Some(BlockId::from(number))))?;
+        Ok(Bytecode::new_raw(code.0.into()))
+    }
+
     fn fetch_storage_from_fork(
         &self,
         address: Address,
         index: U256,
+        number: u64,
     ) -> Result<U256, ProviderError> {
         let add = H160::from(address.0 .0);
-        let bn: Option<BlockId> = Some(BlockId::from(self.block_number));
+        let bn: Option<BlockId> = Some(BlockId::from(number));
 
         let index = H256::from(index.to_be_bytes());
         let slot_value: H256 = Self::block_on(self.provider.get_storage_at(add, index, bn))?;
         Ok(U256::from_be_bytes(slot_value.to_fixed_bytes()))
     }
 
-    fn fetch_blockhash_from_fork(&self, number: U256) -> Result<B256, ProviderError> {
-        if number > U256::from(u64::MAX) {
-            return Ok(KECCAK_EMPTY);
-        }
-        // We know number <= u64::MAX so unwrap is safe
-        let number = U64::from(u64::try_from(number).unwrap());
+    fn fetch_storage_batch_from_fork(
+        &self,
+        address: Address,
+        slots: &[U256],
+        number: u64,
+    ) -> Result<Vec<U256>, ProviderError> {
+        let add = H160::from(address.0 .0);
+        let bn: Option<BlockId> = Some(BlockId::from(number));
+
+        // at most `max_concurrent_requests` are in flight at once
+        let f = stream::iter(slots.iter().map(|slot| {
+            self.provider
+                .get_storage_at(add, H256::from(slot.to_be_bytes()), bn)
+        }))
+        .buffered(self.max_concurrent_requests)
+        .collect::<Vec<_>>();
+        Self::block_on(f)
+            .into_iter()
+            .map(|value| value.map(|v| U256::from_be_bytes(v.to_fixed_bytes())))
+            .collect()
+    }
+
+    fn fetch_blockhash_from_fork(&self, number: u64) -> Result<B256, ProviderError> {
         let block: Option<Block<TxHash>> =
-            Self::block_on(self.provider.get_block(BlockId::from(number)))?;
+            Self::block_on(self.provider.get_block(BlockId::from(U64::from(number))))?;
         Ok(B256::new(block.unwrap().hash.unwrap().0))
     }
+
+    fn fetch_block_transactions(&self, number: u64) -> Result<Vec<ForkTransaction>, ProviderError> {
+        let block: Option<Block<Transaction>> = Self::block_on(
+            self.provider
+                .get_block_with_txs(BlockId::from(U64::from(number))),
+        )?;
+        let block = block.ok_or_else(|| ProviderError::CustomError("block not found".into()))?;
+        block
+            .transactions
+            .into_iter()
+            .map(|tx| {
+                // nodes report the sender, but recover it from the signature if not
+                let from = match tx.from.is_zero() {
+                    true => tx
+                        .recover_from()
+                        .map_err(|e| ProviderError::CustomError(e.to_string()))?,
+                    false => tx.from,
+                };
+                Ok(ForkTransaction {
+                    from: Address::from(from.0),
+                    to: tx.to.map(|to| Address::from(to.0)),
+                    input: tx.input.0.into(),
+                    value: U256::from_limbs(tx.value.0),
+                    nonce: tx.nonce.as_u64(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ForkSource for RpcForkSource {
+    fn block(&self, number: Option<u64>) -> Result<ForkBlock, DatabaseError> {
+        self.fetch_block_from_fork(number)
+            .map_err(|_err| DatabaseError::GetBlock(number))
+    }
+
+    fn block_by_tag(&self, tag: BlockTag) -> Result<ForkBlock, DatabaseError> {
+        let number = match tag {
+            BlockTag::Latest => BlockNumber::Latest,
+            BlockTag::Safe => BlockNumber::Safe,
+            BlockTag::Finalized => BlockNumber::Finalized,
+            BlockTag::Earliest => BlockNumber::Earliest,
+            BlockTag::Pending => BlockNumber::Pending,
+        };
+        self.fetch_block_by_id(BlockId::from(number))
+            .map_err(|_err| DatabaseError::Other(format!("failed to get block {:?}", tag)))
+    }
+
+    fn basic(&self, address: Address, number: u64) -> Result<AccountInfo, DatabaseError> {
+        self.fetch_basic_from_fork(address, number)
+            .map_err(|_err| DatabaseError::GetAccount(address))
+    }
+
+    /// The accounts are fetched concurrently, up to `max_concurrent_requests` at once
+    fn basic_batch(
+        &self,
+        addresses: &[Address],
+        number: u64,
+    ) -> Result<Vec<AccountInfo>, DatabaseError> {
+        self.fetch_basic_batch_from_fork(addresses, number)
+            .map_err(|_err| {
+                DatabaseError::GetAccount(addresses.first().cloned().unwrap_or_default())
+            })
+    }
+
+    fn code(&self, address: Address, number: u64) -> Result<Bytecode, DatabaseError> {
+        self.fetch_code_from_fork(address, number)
+            .map_err(|_err| DatabaseError::GetAccount(address))
+    }
+
+    fn storage(&self, address: Address, index: U256, number: u64) -> Result<U256, DatabaseError> {
+        self.fetch_storage_from_fork(address, index, number)
+            .map_err(|_err| DatabaseError::GetStorage(address, index))
+    }
+
+    /// The requests are sent concurrently, up to `max_concurrent_requests` at once
+    fn storage_batch(
+        &self,
+        address: Address,
+        slots: &[U256],
+        number: u64,
+    ) -> Result<Vec<U256>, DatabaseError> {
+        self.fetch_storage_batch_from_fork(address, slots, number)
+            .map_err(|_err| {
+                DatabaseError::GetStorage(address, slots.first().cloned().unwrap_or_default())
+            })
+    }
+
+    fn block_hash(&self, number: u64) -> Result<B256, DatabaseError> {
+        self.fetch_blockhash_from_fork(number)
+            .map_err(|_err| DatabaseError::GetBlockHash(U256::from(number)))
+    }
+
+    fn block_transactions(&self, number: u64) -> Result<Vec<ForkTransaction>, DatabaseError> {
+        self.fetch_block_transactions(number)
+            .map_err(|_err| DatabaseError::GetBlock(Some(number)))
+    }
+}
+
+/// State fetched from the `ForkSource`.  This is shared by clones of the `ForkBackend`
+/// so remote state is only fetched once.
+#[derive(Debug, Default)]
+struct ForkCache {
+    accounts: Map<Address, AccountInfo>,
+    // code fetched when needed, and an account to fetch it from by code hash
+    contracts: Map<B256, Bytecode>,
+    code_addresses: Map<B256, Address>,
+    storage: Map<Address, Map<U256, U256>>,
+    block_hashes: Map<u64, B256>,
+}
+
+impl ForkCache {
+    fn insert_account(&mut self, address: Address, info: AccountInfo) {
+        if info.code.is_none() {
+            self.code_addresses.insert(info.code_hash, address);
+        }
+        self.accounts.insert(address, info);
+    }
+}
+
+/// Reads remote state at a given block from a `ForkSource`, caching everything fetched.
+#[derive(Clone, Debug)]
+pub struct ForkBackend {
+    source: Arc<dyn ForkSource>,
+    cache: Arc<RwLock<ForkCache>>,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub excess_blob_gas: Option<u64>,
+    pub chain_id: u64,
+    /// the maximum number of slots passed to one `ForkSource::storage_batch` call
+    pub storage_chunk_size: usize,
+}
+
+impl ForkBackend {
+    pub fn from_source(
+        source: Arc<dyn ForkSource>,
+        starting_block_number: Option<u64>,
+    ) -> Result<Self, DatabaseError> {
+        let blk = source.block(starting_block_number)?;
+
+        Ok(Self {
+            source,
+            cache: Arc::default(),
+            block_number: blk.number,
+            timestamp: blk.timestamp,
+            excess_blob_gas: blk.excess_blob_gas,
+            chain_id: blk.chain_id,
+            storage_chunk_size: DEFAULT_STORAGE_CHUNK_SIZE,
+        })
+    }
+
+    /// Fetch the header information of the block the fork is pinned to
+    pub fn fork_block(&self) -> Result<ForkBlock, DatabaseError> {
+        self.source.block(Some(self.block_number))
+    }
+
+    /// Fetch the header information and the transactions, in order, of the block `number`
+    pub fn block_with_transactions(
+        &self,
+        number: u64,
+    ) -> Result<(ForkBlock, Vec<ForkTransaction>), DatabaseError> {
+        let block = self.source.block(Some(number))?;
+        let txs = self.source.block_transactions(number)?;
+        Ok((block, txs))
+    }
+
+    /// Fetch the account information for many `addresses`.  Accounts that have not
+    /// been fetched before are requested from the source at once.
+    pub fn basic_batch_ref(&self, addresses: &[Address]) -> Result<(), DatabaseError> {
+        let missing = {
+            let cache = self.cache.read().unwrap();
+            addresses
+                .iter()
+                .filter(|address| !cache.accounts.contains_key(*address))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let infos = self.source.basic_batch(&missing, self.block_number)?;
+        let mut cache = self.cache.write().unwrap();
+        for (address, info) in missing.into_iter().zip(infos) {
+            cache.insert_account(address, info);
+        }
+        Ok(())
+    }
+
+    /// Fetch the values of many storage `slots` for the given `address`. Slots that
+    /// have not been fetched before are requested from the source in chunks of
+    /// at most `storage_chunk_size` slots.
+    pub fn storage_batch_ref(
+        &self,
+        address: Address,
+        slots: &[U256],
+    ) -> Result<Vec<U256>, DatabaseError> {
+        let missing = {
+            let cache = self.cache.read().unwrap();
+            let cached = cache.storage.get(&address);
+            slots
+                .iter()
+                .filter(|slot| !cached.is_some_and(|c| c.contains_key(slot)))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        for chunk in missing.chunks(self.storage_chunk_size.max(1)) {
+            let values = self
+                .source
+                .storage_batch(address, chunk, self.block_number)?;
+            let mut cache = self.cache.write().unwrap();
+            let cached = cache.storage.entry(address).or_default();
+            for (slot, value) in chunk.iter().zip(values) {
+                cached.insert(*slot, value);
+            }
+        }
+
+        let cache = self.cache.read().unwrap();
+        Ok(slots
+            .iter()
+            .map(|slot| cache.storage[&address][slot])
+            .collect())
+    }
 }
 
 impl DatabaseRef for ForkBackend {
     type Error = DatabaseError;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        match self.fetch_basic_from_fork(address) {
-            Ok(addr) => Ok(Some(addr)),
-            Err(_err) => Err(DatabaseError::GetAccount(address)),
+        if let Some(info) = self.cache.read().unwrap().accounts.get(&address) {
+            return Ok(Some(info.clone()));
         }
+        let info = self.source.basic(address, self.block_number)?;
+        self.cache
+            .write()
+            .unwrap()
+            .insert_account(address, info.clone());
+        Ok(Some(info))
     }
 
+    /// Code left out by the source when the account was fetched is fetched now
     fn code_by_hash_ref(&self, hash: B256) -> Result<Bytecode, Self::Error> {
-        Err(DatabaseError::MissingCode(hash))
+        let address = {
+            let cache = self.cache.read().unwrap();
+            if let Some(code) = cache.contracts.get(&hash) {
+                return Ok(code.clone());
+            }
+            match cache.code_addresses.get(&hash) {
+                Some(address) => *address,
+                _ => return Err(DatabaseError::MissingCode(hash)),
+            }
+        };
+        let code = self.source.code(address, self.block_number)?;
+        self.cache
+            .write()
+            .unwrap()
+            .contracts
+            .insert(hash, code.clone());
+        Ok(code)
     }
 
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
-        self.fetch_storage_from_fork(address, index)
-            .map_err(|_err| DatabaseError::GetStorage(address, index))
+        if let Some(value) = self
+            .cache
+            .read()
+            .unwrap()
+            .storage
+            .get(&address)
+            .and_then(|s| s.get(&index))
+        {
+            return Ok(*value);
+        }
+        let value = self.source.storage(address, index, self.block_number)?;
+        self.cache
+            .write()
+            .unwrap()
+            .storage
+            .entry(address)
+            .or_default()
+            .insert(index, value);
+        Ok(value)
     }
 
     fn block_hash_ref(&self, number: U256) -> Result<B256, Self::Error> {
-        self.fetch_blockhash_from_fork(number)
-            .map_err(|_err| DatabaseError::GetBlockHash(number))
+        if number > U256::from(u64::MAX) {
+            return Ok(KECCAK_EMPTY);
+        }
+        // We know number <= u64::MAX so unwrap is safe
+        let number = u64::try_from(number).unwrap();
+        if let Some(hash) = self.cache.read().unwrap().block_hashes.get(&number) {
+            return Ok(*hash);
+        }
+        let hash = self.source.block_hash(number)?;
+        self.cache
+            .write()
+            .unwrap()
+            .block_hashes
+            .insert(number, hash);
+        Ok(hash)
+    }
+}
+
+// JSON-RPC error code returned by a node for a method it doesn't support
+const METHOD_NOT_FOUND: i64 = -32601;
+
+fn is_method_not_found(e: &ProviderError) -> bool {
+    e.as_error_response()
+        .is_some_and(|e| e.code == METHOD_NOT_FOUND)
+}
+
+#[cfg(test)]
+pub(crate) mod mock_rpc {
+    use serde_json::{json, Value};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    #[derive(Default)]
+    struct Stats {
+        requests: AtomicUsize,
+        proof_requests: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+        // answer `eth_getProof` with a "method not found" error
+        no_proofs: bool,
+    }
+
+    /// A minimal JSON-RPC node on localhost.  Every account has a balance of 1 ether
+    /// and no code, and every storage slot has the value 42.  Storage requests are
+    /// slow, to observe how many are in flight at once.
+    pub(crate) struct MockRpc {
+        pub url: String,
+        stats: Arc<Stats>,
+    }
+
+    impl MockRpc {
+        pub fn start() -> Self {
+            Self::start_with(Stats::default())
+        }
+
+        /// Start a node that doesn't support `eth_getProof`
+        pub fn start_without_proofs() -> Self {
+            Self::start_with(Stats {
+                no_proofs: true,
+                ..Default::default()
+            })
+        }
+
+        fn start_with(stats: Stats) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let stats = Arc::new(stats);
+            let shared = stats.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let stats = shared.clone();
+                    thread::spawn(move || serve(stream, stats));
+                }
+            });
+            Self { url, stats }
+        }
+
+        /// The number of JSON-RPC calls received
+        pub fn requests(&self) -> usize {
+            self.stats.requests.load(Ordering::SeqCst)
+        }
+
+        /// The number of `eth_getProof` calls received
+        pub fn proof_requests(&self) -> usize {
+            self.stats.proof_requests.load(Ordering::SeqCst)
+        }
+
+        /// The most storage requests handled at the same time
+        pub fn max_in_flight(&self) -> usize {
+            self.stats.max_in_flight.load(Ordering::SeqCst)
+        }
+    }
+
+    fn serve(stream: TcpStream, stats: Arc<Stats>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                    return;
+                }
+                let line = line.trim().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = match serde_json::from_slice::<Value>(&body).unwrap() {
+                Value::Array(calls) => {
+                    Value::Array(calls.iter().map(|call| respond(call, &stats)).collect())
+                }
+                call => respond(&call, &stats),
+            }
+            .to_string();
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            if stream.write_all(reply.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn respond(call: &Value, stats: &Stats) -> Value {
+        stats.requests.fetch_add(1, Ordering::SeqCst);
+        if call["method"] == "eth_getProof" {
+            stats.proof_requests.fetch_add(1, Ordering::SeqCst);
+            if stats.no_proofs {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "error": {"code": -32601, "message": "the method eth_getProof does not exist"},
+                });
+            }
+        }
+        let result = match call["method"].as_str().unwrap_or_default() {
+            "eth_getBlockByNumber" => json!({
+                // the requested number, 90 for "finalized", otherwise 100
+                "number": match call["params"][0].as_str() {
+                    Some("finalized") => "0x5a",
+                    Some(number) if number.starts_with("0x") => number,
+                    _ => "0x64",
+                },
+                "timestamp": "0x6553f100",
+                "hash": format!("0x{}", "11".repeat(32)),
+                "baseFeePerGas": "0x3b9aca00",
+                "miner": format!("0x{}", "22".repeat(20)),
+                "mixHash": format!("0x{}", "33".repeat(32)),
+                "gasLimit": "0x1c9c380",
+            }),
+            "eth_chainId" => json!("0x7a69"),
+            // an account without code
+            "eth_getProof" => json!({
+                "address": call["params"][0],
+                "balance": "0xde0b6b3a7640000",
+                "codeHash": "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+                "nonce": "0x0",
+                "storageHash": format!("0x{}", "00".repeat(32)),
+                "accountProof": [],
+                "storageProof": [],
+            }),
+            "eth_getBalance" => json!("0xde0b6b3a7640000"),
+            "eth_getTransactionCount" => json!("0x0"),
+            "eth_getCode" => json!("0x"),
+            "eth_getStorageAt" => {
+                let in_flight = stats.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                stats.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(5));
+                stats.in_flight.fetch_sub(1, Ordering::SeqCst);
+                json!(format!("0x{:064x}", 42))
+            }
+            _ => Value::Null,
+        };
+        json!({"jsonrpc": "2.0", "id": call["id"], "result": result})
     }
 }
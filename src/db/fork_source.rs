@@ -0,0 +1,266 @@
+//!
+//! The source of remote state used by a fork
+//!
+use alloy_primitives::{Address, Bytes, U256};
+use revm::primitives::{AccountInfo, Bytecode, B256};
+use std::fmt::Debug;
+
+use crate::errors::DatabaseError;
+
+/// Information about the block a fork is pinned to
+#[derive(Clone, Debug, Default)]
+pub struct ForkBlock {
+    /// the block number
+    pub number: u64,
+    /// the block timestamp
+    pub timestamp: u64,
+    /// the excess blob gas (EIP-4844), if the block has it
+    pub excess_blob_gas: Option<u64>,
+    /// the chain id of the network
+    pub chain_id: u64,
+    /// the base fee per gas (EIP-1559), if the block has it
+    pub base_fee: Option<U256>,
+    /// the address receiving the block reward
+    pub coinbase: Address,
+    /// the randomness beacon (`mixHash`) after the merge, if the block has it
+    pub prevrandao: Option<B256>,
+    /// the block gas limit
+    pub gas_limit: u64,
+}
+
+/// A transaction included in a block, see `ForkSource::block_transactions`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForkTransaction {
+    /// the sender of the transaction
+    pub from: Address,
+    /// the recipient of the transaction. `None` is a contract deployment
+    pub to: Option<Address>,
+    /// the call data (or contract bytecode for a deployment)
+    pub input: Bytes,
+    /// the value sent with the transaction
+    pub value: U256,
+    /// the nonce of the sender
+    pub nonce: u64,
+}
+
+/// A named block, resolved by the node to a block number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockTag {
+    /// the most recent block
+    Latest,
+    /// the most recent block considered safe from re-orgs
+    Safe,
+    /// the most recent finalized block
+    Finalized,
+    /// the genesis block
+    Earliest,
+    /// the block being built.  Not all nodes return a number for it
+    Pending,
+}
+
+/// Provides the remote state for a fork.  The default implementation fetches state
+/// from a JSON-RPC node, but another implementation can be used with
+/// [`CreateFork::from_source`](crate::db::CreateFork::from_source), for example,
+/// to mock a node in tests, or `DatadirForkSource` (with the `datadir` feature) to read
+/// the database of a local geth node.
+pub trait ForkSource: Debug + Send + Sync {
+    /// Return information for the block `number`, or the latest block if `None`
+    fn block(&self, number: Option<u64>) -> Result<ForkBlock, DatabaseError>;
+
+    /// Return information for the block `tag` refers to.  The default implementation
+    /// only supports `Latest` and `Earliest`.
+    fn block_by_tag(&self, tag: BlockTag) -> Result<ForkBlock, DatabaseError> {
+        match tag {
+            BlockTag::Latest => self.block(None),
+            BlockTag::Earliest => self.block(Some(0)),
+            _ => Err(DatabaseError::Other(format!(
+                "block tag {:?} is not supported",
+                tag
+            ))),
+        }
+    }
+
+    /// Fetch the account information for `address` at block `number`.  The code can be
+    /// left out (`None`) as long as `code_hash` is set, it's then fetched with `code`
+    /// the first time it's needed.
+    fn basic(&self, address: Address, number: u64) -> Result<AccountInfo, DatabaseError>;
+
+    /// Fetch the code of the contract at `address` at block `number`.  The default
+    /// implementation uses the code returned by `basic`.
+    fn code(&self, address: Address, number: u64) -> Result<Bytecode, DatabaseError> {
+        Ok(self.basic(address, number)?.code.unwrap_or_default())
+    }
+
+    /// Fetch the account information for many `addresses` at block `number`.
+    /// The default implementation fetches each account in turn.
+    fn basic_batch(
+        &self,
+        addresses: &[Address],
+        number: u64,
+    ) -> Result<Vec<AccountInfo>, DatabaseError> {
+        addresses
+            .iter()
+            .map(|address| self.basic(*address, number))
+            .collect()
+    }
+
+    /// Fetch the value of the storage `index` for `address` at block `number`
+    fn storage(&self, address: Address, index: U256, number: u64) -> Result<U256, DatabaseError>;
+
+    /// Fetch the values of many storage `slots` for `address` at block `number`.
+    /// The default implementation fetches each slot in turn.
+    fn storage_batch(
+        &self,
+        address: Address,
+        slots: &[U256],
+        number: u64,
+    ) -> Result<Vec<U256>, DatabaseError> {
+        slots
+            .iter()
+            .map(|slot| self.storage(address, *slot, number))
+            .collect()
+    }
+
+    /// Fetch the hash of the block `number`
+    fn block_hash(&self, number: u64) -> Result<B256, DatabaseError>;
+
+    /// Fetch the transactions of the block `number`, in order.  The default
+    /// implementation doesn't support this.
+    fn block_transactions(&self, number: u64) -> Result<Vec<ForkTransaction>, DatabaseError> {
+        Err(DatabaseError::Other(format!(
+            "transactions of block {} are not supported",
+            number
+        )))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use alloy_primitives::keccak256;
+    use revm::primitives::KECCAK_EMPTY;
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// A `ForkSource` serving fixed state that counts the requests made to it
+    #[derive(Debug, Default)]
+    pub(crate) struct MockForkSource {
+        pub block: ForkBlock,
+        pub accounts: HashMap<Address, AccountInfo>,
+        pub storage: HashMap<(Address, U256), U256>,
+        pub basic_requests: AtomicUsize,
+        pub storage_requests: AtomicUsize,
+        pub batch_requests: AtomicUsize,
+        pub code_requests: AtomicUsize,
+        /// the number of upcoming `basic` and `storage` requests that fail
+        pub failures: AtomicUsize,
+        pub transactions: Vec<ForkTransaction>,
+    }
+
+    impl MockForkSource {
+        pub fn new(number: u64, timestamp: u64) -> Self {
+            Self {
+                block: ForkBlock {
+                    number,
+                    timestamp,
+                    excess_blob_gas: None,
+                    // a local dev chain
+                    chain_id: 31337,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        }
+
+        // whether this request should fail, see `failures`
+        fn fail(&self) -> bool {
+            self.failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+        }
+
+        pub fn basic_requests(&self) -> usize {
+            self.basic_requests.load(Ordering::SeqCst)
+        }
+
+        pub fn code_requests(&self) -> usize {
+            self.code_requests.load(Ordering::SeqCst)
+        }
+
+        pub fn storage_requests(&self) -> usize {
+            self.storage_requests.load(Ordering::SeqCst)
+        }
+
+        pub fn batch_requests(&self) -> usize {
+            self.batch_requests.load(Ordering::SeqCst)
+        }
+    }
+
+    impl ForkSource for MockForkSource {
+        fn block(&self, _number: Option<u64>) -> Result<ForkBlock, DatabaseError> {
+            Ok(self.block.clone())
+        }
+
+        fn basic(&self, address: Address, _number: u64) -> Result<AccountInfo, DatabaseError> {
+            self.basic_requests.fetch_add(1, Ordering::SeqCst);
+            if self.fail() {
+                return Err(DatabaseError::GetAccount(address));
+            }
+            // like a node, the code is fetched separately
+            let mut info = self.accounts.get(&address).cloned().unwrap_or_default();
+            if info.code_hash != KECCAK_EMPTY {
+                info.code = None;
+            }
+            Ok(info)
+        }
+
+        fn code(&self, address: Address, _number: u64) -> Result<Bytecode, DatabaseError> {
+            self.code_requests.fetch_add(1, Ordering::SeqCst);
+            Ok(self
+                .accounts
+                .get(&address)
+                .and_then(|info| info.code.clone())
+                .unwrap_or_default())
+        }
+
+        fn storage(
+            &self,
+            address: Address,
+            index: U256,
+            _number: u64,
+        ) -> Result<U256, DatabaseError> {
+            self.storage_requests.fetch_add(1, Ordering::SeqCst);
+            if self.fail() {
+                return Err(DatabaseError::GetStorage(address, index));
+            }
+            Ok(self
+                .storage
+                .get(&(address, index))
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        fn storage_batch(
+            &self,
+            address: Address,
+            slots: &[U256],
+            number: u64,
+        ) -> Result<Vec<U256>, DatabaseError> {
+            self.batch_requests.fetch_add(1, Ordering::SeqCst);
+            slots
+                .iter()
+                .map(|slot| self.storage(address, *slot, number))
+                .collect()
+        }
+
+        fn block_hash(&self, number: u64) -> Result<B256, DatabaseError> {
+            Ok(keccak256(number.to_be_bytes()))
+        }
+
+        fn block_transactions(&self, _number: u64) -> Result<Vec<ForkTransaction>, DatabaseError> {
+            Ok(self.transactions.clone())
+        }
+    }
+}
@@ -15,7 +15,7 @@ use revm::{
 
 ///
 /// This acts like a wrapper type for [InMemoryDB] but is capable of creating/applying snapshots
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemDb {
     pub db: CacheDB<EmptyDBWrapper>,
 }
@@ -29,6 +29,12 @@ impl Default for MemDb {
 }
 
 impl MemDb {
+    /// Remove all accounts written locally.  Nothing is fetched from a remote
+    /// source in memory, so this clears the database
+    pub fn reset_local_changes(&mut self) {
+        self.db = CacheDB::new(Default::default());
+    }
+
     pub fn create_snapshot(&self, block_num: u64, timestamp: u64) -> anyhow::Result<SnapShot> {
         let accounts = self
             .db
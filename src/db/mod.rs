@@ -1,23 +1,48 @@
 //!
 //! Provides access to EVM storage
 //!
+#[cfg(feature = "datadir")]
+pub(crate) mod datadir;
 pub(crate) mod fork;
 pub(crate) mod fork_backend;
+pub(crate) mod fork_source;
 pub(crate) mod in_memory_db;
 
 use alloy_primitives::{Address, U256};
 use anyhow::{anyhow, Result};
 use revm::{
+    inspector_handle_register,
+    inspectors::NoOpInspector,
     interpreter::primitives::EnvWithHandlerCfg,
     primitives::{
-        Account, AccountInfo, Bytecode, HashMap as Map, ResultAndState, B256, KECCAK_EMPTY,
+        Account, AccountInfo, Bytecode, HaltReason, HashMap as Map, ResultAndState, B256,
+        KECCAK_EMPTY,
     },
-    Database, DatabaseCommit, DatabaseRef, EvmBuilder,
+    Database, DatabaseCommit, DatabaseRef, EvmBuilder, Inspector,
+};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use std::time::{SystemTime, UNIX_EPOCH};
 
 use self::{fork::Fork, in_memory_db::MemDb};
-use crate::{errors::DatabaseError, snapshot::SnapShot};
+use crate::{
+    errors::{DatabaseError, EvmError},
+    inspectors::{CallDepthInspector, WithCallDepth},
+    snapshot::SnapShot,
+};
+#[cfg(feature = "datadir")]
+pub use datadir::DatadirForkSource;
+pub use fork::AccessSet;
+pub use fork_backend::{HttpProvider, RpcForkSource};
+pub use fork_source::{BlockTag, ForkBlock, ForkSource, ForkTransaction};
+
+/// The default maximum number of storage slots fetched from a fork source in one call
+pub const DEFAULT_STORAGE_CHUNK_SIZE: usize = 100;
+
+/// The default maximum number of requests sent to a fork RPC endpoint at the same time
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 8;
 
 /// Information related to creating a fork
 #[derive(Clone, Debug)]
@@ -26,12 +51,46 @@ pub struct CreateFork {
     pub url: String,
     /// optional block number of the fork.  If none, it will use the latest block.
     pub blocknumber: Option<u64>,
+    /// optional source of remote state. If none, state is fetched from `url`.
+    pub source: Option<Arc<dyn ForkSource>>,
+    /// optional provider to use instead of connecting to `url`
+    pub provider: Option<Arc<HttpProvider>>,
+    /// the maximum number of storage slots passed to one `ForkSource::storage_batch`
+    /// call when prefetching many slots.  Defaults to `DEFAULT_STORAGE_CHUNK_SIZE`.
+    /// This isn't a JSON-RPC batch: `RpcForkSource` sends an `eth_getStorageAt`
+    /// request per slot, see `max_concurrent_requests`.
+    pub storage_chunk_size: usize,
+    /// the maximum number of RPC requests in flight at once when prefetching
+    /// many slots.  Defaults to `DEFAULT_MAX_CONCURRENT_REQUESTS`.  Ignored when
+    /// `source` is set: limit the requests of the source itself instead, e.g. with
+    /// `RpcForkSource::with_max_concurrent_requests`.
+    pub max_concurrent_requests: usize,
+    /// optional block tag, e.g. "finalized", resolved to a block number when the fork
+    /// is created.  Takes precedence over `blocknumber`.
+    pub tag: Option<BlockTag>,
 }
 
 impl CreateFork {
     /// Fork at the given URL and block number
     pub fn new(url: String, blocknumber: Option<u64>) -> Self {
-        Self { url, blocknumber }
+        Self {
+            url,
+            blocknumber,
+            source: None,
+            provider: None,
+            storage_chunk_size: DEFAULT_STORAGE_CHUNK_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            tag: None,
+        }
+    }
+
+    /// Fork at the given URL, at the block the `tag` refers to when the fork is created.
+    /// The fork stays pinned to that block.
+    pub fn at_tag(url: String, tag: BlockTag) -> Self {
+        Self {
+            tag: Some(tag),
+            ..Self::new(url, None)
+        }
     }
 
     /// For at the given URL and use the latest block available
@@ -39,19 +98,64 @@ impl CreateFork {
         Self {
             url,
             blocknumber: None,
+            source: None,
+            provider: None,
+            storage_chunk_size: DEFAULT_STORAGE_CHUNK_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            tag: None,
         }
     }
+
+    /// Fork using an existing `provider` at the given block number.  The provider can be
+    /// shared by many forks to reuse its connections, while each fork keeps its own state.
+    pub fn with_shared_provider(provider: Arc<HttpProvider>, blocknumber: Option<u64>) -> Self {
+        Self {
+            url: String::new(),
+            blocknumber,
+            source: None,
+            provider: Some(provider),
+            storage_chunk_size: DEFAULT_STORAGE_CHUNK_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            tag: None,
+        }
+    }
+
+    /// Fork from the given `source` of remote state, rather than an RPC endpoint
+    pub fn from_source(source: Arc<dyn ForkSource>, blocknumber: Option<u64>) -> Self {
+        Self {
+            url: String::new(),
+            blocknumber,
+            source: Some(source),
+            provider: None,
+            storage_chunk_size: DEFAULT_STORAGE_CHUNK_SIZE,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+            tag: None,
+        }
+    }
+
+    /// Fork from the database of a local geth node in the directory `path`, at its
+    /// head `block`, rather than an RPC endpoint.  See [`DatadirForkSource`]
+    #[cfg(feature = "datadir")]
+    pub fn from_datadir(path: std::path::PathBuf, block: u64) -> Result<Self> {
+        let source = DatadirForkSource::open(path)?;
+        Ok(Self::from_source(Arc::new(source), Some(block)))
+    }
 }
 
 // Used by the EVM to access storage.  This can either be an in-memory only db or a forked db.
 // The EVM delegates transact() and transact_commit to this module
 //
 // This is based heavily on Foundry's approach.
+#[derive(Clone)]
 pub struct StorageBackend {
     mem_db: MemDb, // impl wrapper to handle DbErrors
     forkdb: Option<Fork>,
     pub block_number: u64, // used to record in the snapshot...
     pub timestamp: u64,
+    pub excess_blob_gas: Option<u64>, // from the forked block, if available
+    pub chain_id: Option<u64>,        // of the forked network
+    pub missing_storage_as_zero: bool, // see `storage_slot`
+    pub max_call_depth: Option<u16>,  // see `run_transact`
 }
 
 impl Default for StorageBackend {
@@ -62,30 +166,78 @@ impl Default for StorageBackend {
 
 impl StorageBackend {
     pub fn new(fork: Option<CreateFork>) -> Self {
+        Self::try_new(fork).expect("StorageBackend: failed to create the fork")
+    }
+
+    /// Same as `new`, but returns an error, instead of panicking, if the fork can't be
+    /// created, e.g. the URL is invalid or the block can't be fetched
+    pub fn try_new(fork: Option<CreateFork>) -> Result<Self> {
         if let Some(fork) = fork {
-            let backend = Fork::new(&fork.url, fork.blocknumber);
+            let source = match (fork.source, fork.provider) {
+                (Some(source), _) => source,
+                (None, provider) => {
+                    let rpc = match provider {
+                        Some(provider) => RpcForkSource::from_provider(provider),
+                        None => RpcForkSource::try_new(&fork.url)?,
+                    };
+                    Arc::new(rpc.with_max_concurrent_requests(fork.max_concurrent_requests))
+                }
+            };
+            let blocknumber = match fork.tag {
+                Some(tag) => Some(
+                    source
+                        .block_by_tag(tag)
+                        .map_err(|e| {
+                            anyhow!("StorageBackend: failed to resolve the block tag: {}", e)
+                        })?
+                        .number,
+                ),
+                None => fork.blocknumber,
+            };
+            let mut backend = Fork::from_source(source, blocknumber)
+                .map_err(|e| anyhow!("StorageBackend: failed to load the fork block: {}", e))?;
+            backend.database_mut().db.storage_chunk_size = fork.storage_chunk_size;
             let block_number = backend.block_number;
             let timestamp = backend.timestamp;
-            Self {
+            let excess_blob_gas = backend.excess_blob_gas;
+            let chain_id = Some(backend.chain_id);
+            Ok(Self {
                 mem_db: MemDb::default(),
                 forkdb: Some(backend),
                 block_number,
                 timestamp,
-            }
+                excess_blob_gas,
+                chain_id,
+                missing_storage_as_zero: true,
+                max_call_depth: None,
+            })
         } else {
             let timestamp = SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("StorageBackend: failed to get unix epoch time")
                 .as_secs();
-            Self {
+            Ok(Self {
                 mem_db: MemDb::default(),
                 forkdb: None,
                 block_number: 1,
                 timestamp,
-            }
+                excess_blob_gas: None,
+                chain_id: None,
+                missing_storage_as_zero: true,
+                max_call_depth: None,
+            })
         }
     }
 
+    /// Fork from the given `source`, at its latest block.  A source returning scripted
+    /// values and errors can be used to test how failures to fetch remote state are
+    /// handled.  Errors are not cached, so a failed read can be retried.  Only available
+    /// in tests, or with the `test-utils` feature.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn from_fork_source(source: Box<dyn ForkSource>) -> Result<Self> {
+        Self::try_new(Some(CreateFork::from_source(Arc::from(source), None)))
+    }
+
     pub fn insert_account_info(&mut self, address: Address, info: AccountInfo) {
         if let Some(fork) = self.forkdb.as_mut() {
             fork.database_mut().insert_account_info(address, info)
@@ -123,7 +275,167 @@ impl StorageBackend {
         }
     }
 
+    /// Return the storage slots of `address` that are known locally: written, or
+    /// already fetched from the fork.
+    pub fn known_storage(&self, address: Address) -> BTreeMap<U256, U256> {
+        let accounts = match self.forkdb.as_ref() {
+            Some(fork) => &fork.database().accounts,
+            _ => &self.mem_db.db.accounts,
+        };
+        accounts
+            .get(&address)
+            .map(|account| account.storage.clone().into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Return the number of storage slots known locally, across all accounts
+    pub fn known_storage_count(&self) -> usize {
+        let accounts = match self.forkdb.as_ref() {
+            Some(fork) => &fork.database().accounts,
+            _ => &self.mem_db.db.accounts,
+        };
+        accounts.values().map(|account| account.storage.len()).sum()
+    }
+
+    /// Return the accounts that are known locally, sorted by address: created,
+    /// changed, or already fetched from the fork.
+    pub fn known_accounts(&self) -> Vec<(Address, AccountInfo)> {
+        let accounts = match self.forkdb.as_ref() {
+            Some(fork) => &fork.database().accounts,
+            _ => &self.mem_db.db.accounts,
+        };
+        let mut known = accounts
+            .iter()
+            .map(|(address, account)| (*address, account.info.clone()))
+            .collect::<Vec<_>>();
+        known.sort_by_key(|(address, _)| *address);
+        known
+    }
+
+    /// Start recording the accounts and storage slots read from the fork.  Does nothing
+    /// with the in-memory database.
+    pub fn start_recording_accesses(&self) {
+        if let Some(fork) = self.forkdb.as_ref() {
+            fork.start_recording_accesses()
+        }
+    }
+
+    /// Return the accounts and storage slots read from the fork since recording started
+    pub fn accesses(&self) -> AccessSet {
+        self.forkdb
+            .as_ref()
+            .map(|fork| fork.accesses())
+            .unwrap_or_default()
+    }
+
+    /// Fetch the accounts for `addresses` from the fork, if they're not already
+    /// loaded.  Does nothing with the in-memory database.
+    pub fn prefetch_accounts(&self, addresses: &[Address]) -> Result<(), DatabaseError> {
+        match self.forkdb.as_ref() {
+            Some(fork) => {
+                let db = fork.database();
+                let missing = addresses
+                    .iter()
+                    .filter(|address| !db.accounts.contains_key(*address))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                db.db.basic_batch_ref(&missing)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the storage `slot` for the given `address`.  With the in-memory database,
+    /// a slot that has never been written is zero, or an error if
+    /// `missing_storage_as_zero` is `false`.
+    pub fn storage_slot(&self, address: Address, slot: U256) -> Result<U256, DatabaseError> {
+        if self.forkdb.is_none() {
+            self.check_missing_storage(address, &[slot])?;
+        }
+        self.storage_ref(address, slot)
+    }
+
+    /// Read many storage `slots` for the given `address`.  Missing slots are handled
+    /// the same as `storage_slot`.
+    pub fn storage_batch(
+        &mut self,
+        address: Address,
+        slots: &[U256],
+    ) -> Result<Vec<U256>, DatabaseError> {
+        if let Some(fork) = self.forkdb.as_mut() {
+            fork.storage_batch(address, slots)
+        } else {
+            self.check_missing_storage(address, slots)?;
+            slots
+                .iter()
+                .map(|slot| Database::storage(&mut self.mem_db, address, *slot))
+                .collect()
+        }
+    }
+
+    // Error on the first slot in `slots` that has never been written to the in-memory
+    // database, unless `missing_storage_as_zero` is set
+    fn check_missing_storage(&self, address: Address, slots: &[U256]) -> Result<(), DatabaseError> {
+        if self.missing_storage_as_zero {
+            return Ok(());
+        }
+        let account = self.mem_db.db.accounts.get(&address);
+        match slots
+            .iter()
+            .find(|slot| !account.is_some_and(|a| a.storage.contains_key(slot)))
+        {
+            Some(slot) => Err(DatabaseError::GetStorage(address, *slot)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the account information for `address` from the fork, ignoring any local
+    /// changes.  Returns `None` if not forking.
+    pub fn remote_basic(&self, address: Address) -> Option<Result<AccountInfo, DatabaseError>> {
+        self.forkdb.as_ref().map(|fork| {
+            fork.database()
+                .db
+                .basic_ref(address)
+                .map(|info| info.unwrap_or_default())
+        })
+    }
+
+    /// Fetch the header information of the forked block.  Returns `None` if not forking.
+    pub fn fork_block(&self) -> Option<Result<ForkBlock, DatabaseError>> {
+        self.forkdb
+            .as_ref()
+            .map(|fork| fork.database().db.fork_block())
+    }
+
+    /// Fetch the header information and the transactions of the block `number` from
+    /// the fork.  Returns `None` if not forking.
+    pub fn fork_block_transactions(
+        &self,
+        number: u64,
+    ) -> Option<Result<(ForkBlock, Vec<ForkTransaction>), DatabaseError>> {
+        self.forkdb
+            .as_ref()
+            .map(|fork| fork.database().db.block_with_transactions(number))
+    }
+
+    /// Discard state written locally (committed transactions, inserted accounts),
+    /// keeping state fetched from a fork.  See `BaseEvm::reset_local_changes`
+    pub fn reset_local_changes(&mut self) {
+        if let Some(fork) = self.forkdb.as_mut() {
+            fork.reset_local_changes()
+        } else {
+            self.mem_db.reset_local_changes()
+        }
+    }
+
+    /// Execute the transaction in `env`.  If `max_call_depth` is set and a call goes
+    /// deeper, the transaction fails with `EvmError::Halt` (`HaltReason::CallTooDeep`).
     pub fn run_transact(&mut self, env: &mut EnvWithHandlerCfg) -> Result<ResultAndState> {
+        if self.max_call_depth.is_some() {
+            let (result, _) = self.run_transact_with_inspector(env, NoOpInspector)?;
+            return Ok(result);
+        }
+
         let mut evm = create_evm(self, env.clone());
         let res = evm
             .transact()
@@ -133,6 +445,37 @@ impl StorageBackend {
         Ok(res)
     }
 
+    /// Same as `run_transact` but the `inspector` observes the execution.
+    /// The inspector is returned along with the result.
+    pub fn run_transact_with_inspector<I>(
+        &mut self,
+        env: &mut EnvWithHandlerCfg,
+        inspector: I,
+    ) -> Result<(ResultAndState, I)>
+    where
+        I: for<'a> Inspector<&'a mut Self>,
+    {
+        let inspector = WithCallDepth {
+            limit: self.max_call_depth.map(CallDepthInspector::new),
+            inner: inspector,
+        };
+        let mut evm = create_evm_with_inspector(self, env.clone(), inspector);
+        let res = evm
+            .transact()
+            .map_err(|e| anyhow!("backend failed while executing transaction:  {:?}", e))?;
+        env.env = evm.context.evm.inner.env;
+
+        let inspector = evm.context.external;
+        if inspector.exceeded() {
+            return Err(EvmError::Halt {
+                reason: HaltReason::CallTooDeep,
+                gas_used: res.result.gas_used(),
+            }
+            .into());
+        }
+        Ok((res, inspector.inner))
+    }
+
     /// Create a snapshot of the current state, delegates
     /// to the current backend database.
     pub fn create_snapshot(&self) -> Result<SnapShot> {
@@ -275,5 +618,52 @@ fn create_evm<'a, DB: Database>(
     EvmBuilder::default()
         .with_db(db)
         .with_env(env.env.clone())
+        .with_spec_id(env.handler_cfg.spec_id)
+        .build()
+}
+
+fn create_evm_with_inspector<'a, DB: Database, I: Inspector<DB>>(
+    db: DB,
+    env: revm::primitives::EnvWithHandlerCfg,
+    inspector: I,
+) -> revm::Evm<'a, I, DB> {
+    EvmBuilder::default()
+        .with_db(db)
+        .with_external_context(inspector)
+        .with_env(env.env.clone())
+        .with_spec_id(env.handler_cfg.spec_id)
+        .append_handler_register(inspector_handle_register)
         .build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fork_source::mock::MockForkSource;
+
+    #[test]
+    fn source_errors_propagate_and_are_not_cached() {
+        let alice = Address::repeat_byte(3);
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .accounts
+            .insert(alice, AccountInfo::from_balance(U256::from(5)));
+        source.storage.insert((alice, U256::from(1)), U256::from(7));
+        // the next two requests fail
+        source.failures = 2.into();
+        let backend = StorageBackend::from_fork_source(Box::new(source)).unwrap();
+
+        assert!(backend.basic_ref(alice).is_err());
+        assert!(backend.storage_ref(alice, U256::from(1)).is_err());
+
+        // the errors are not cached, so reading again succeeds
+        assert_eq!(
+            U256::from(5),
+            backend.basic_ref(alice).unwrap().unwrap().balance
+        );
+        assert_eq!(
+            U256::from(7),
+            backend.storage_ref(alice, U256::from(1)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//!
+//! Deploy several contracts in order, linking libraries into the contracts that use them
+//!
+use alloy_primitives::{hex, keccak256, Address, B256, U256};
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+
+use crate::BaseEvm;
+
+/// Replace the placeholders for `library` in the hex encoded, unlinked `bytecode` with
+/// `address`.  `library` is the fully qualified name used by the compiler, for example
+/// `contracts/Math.sol:Math`.  Both the current (`__$<hash>$__`) and the legacy
+/// (`__<name>___`) placeholder formats are replaced.
+pub fn link_bytecode(bytecode: &str, library: &str, address: Address) -> String {
+    let address = hex::encode(address);
+
+    let hash = hex::encode(keccak256(library.as_bytes()));
+    let placeholder = format!("__${}$__", &hash[..34]);
+
+    let name = &library[..library.len().min(36)];
+    let legacy = format!("__{:_<38}", name);
+
+    bytecode
+        .replace(&placeholder, &address)
+        .replace(&legacy, &address)
+}
+
+/// Derive a CREATE2 salt from a human readable `label`: `keccak256(label)`
+pub fn salt_from_label(label: &str) -> B256 {
+    keccak256(label.as_bytes())
+}
+
+/// Compute the address of a contract deployed by `deployer` with CREATE2, using
+/// `salt` and the contract's `initcode` (bytecode including constructor arguments).
+pub fn compute_create2_address(deployer: Address, salt: B256, initcode: &[u8]) -> Address {
+    deployer.create2_from_code(salt, initcode)
+}
+
+/// A contract to deploy as part of a `DeploymentPlan`
+struct PlannedContract {
+    name: String,
+    bytecode: String,
+    libraries: Vec<String>,
+}
+
+/// Deploy contracts in the order they are added.  Libraries used by a contract
+/// must be added (deployed) before it, and their addresses are linked into the
+/// contract's bytecode when it's deployed.
+///
+/// ## Example
+///
+/// ```ignore
+/// let addresses = DeploymentPlan::new(&mut evm)
+///     .add("Lib.sol:Math", math_bytecode, &[])
+///     .add("Token", token_bytecode, &["Lib.sol:Math"])
+///     .deploy_all(caller)?;
+/// ```
+pub struct DeploymentPlan<'a> {
+    evm: &'a mut BaseEvm,
+    contracts: Vec<PlannedContract>,
+}
+
+impl<'a> DeploymentPlan<'a> {
+    /// Create an empty plan that deploys to the given `evm`
+    pub fn new(evm: &'a mut BaseEvm) -> Self {
+        Self {
+            evm,
+            contracts: Vec::new(),
+        }
+    }
+
+    /// Add a contract to deploy.
+    /// - `name` is used to reference the contract. For a library, this should be the
+    ///   fully qualified name used by the compiler for the placeholder
+    /// - `bytecode` is the hex encoded, possibly unlinked, bytecode, including any
+    ///   constructor arguments
+    /// - `libraries` are the names of the (already added) libraries it links against
+    pub fn add(mut self, name: &str, bytecode: &str, libraries: &[&str]) -> Self {
+        self.contracts.push(PlannedContract {
+            name: name.into(),
+            bytecode: bytecode.trim_start_matches("0x").into(),
+            libraries: libraries.iter().map(|l| l.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Deploy all the contracts from `caller`, returning the address of each contract
+    /// by name.
+    pub fn deploy_all(&mut self, caller: Address) -> Result<HashMap<String, Address>> {
+        let mut deployed = HashMap::new();
+        for contract in &self.contracts {
+            let mut bytecode = contract.bytecode.clone();
+            for library in &contract.libraries {
+                let address = deployed.get(library).ok_or(anyhow!(
+                    "DeploymentPlan: library {} must be deployed before {}",
+                    library,
+                    contract.name
+                ))?;
+                bytecode = link_bytecode(&bytecode, library, *address);
+            }
+            if bytecode.contains("__") {
+                bail!("DeploymentPlan: {} has unlinked libraries", contract.name);
+            }
+
+            let code = hex::decode(&bytecode).map_err(|e| {
+                anyhow!(
+                    "DeploymentPlan: invalid bytecode for {}: {}",
+                    contract.name,
+                    e
+                )
+            })?;
+            let address = self.evm.deploy(caller, code, U256::ZERO)?;
+            deployed.insert(contract.name.clone(), address);
+        }
+        Ok(deployed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // returns its input * 2
+    const LIBRARY: &str = "61000b8061000d6000396000f35f356002025f5260205ff3";
+    // delegates calls to the 'Lib.sol:Doubler' library
+    const CONTRACT: &str = "6100258061000d6000396000f3365f5f3760205f365f73\
+        __$27633b4c2f61ea3080d17d2f4560ef513f$__5af45060205ff3";
+
+    #[test]
+    fn link_placeholders() {
+        let address = Address::repeat_byte(0xab);
+        let linked = link_bytecode(CONTRACT, "Lib.sol:Doubler", address);
+        assert!(!linked.contains("__"));
+        assert!(linked.contains(&"ab".repeat(20)));
+
+        let legacy = format!("73{:_<40}5af4", "__Lib.sol:Doubler");
+        assert_eq!(
+            format!("73{}5af4", "ab".repeat(20)),
+            link_bytecode(&legacy, "Lib.sol:Doubler", address)
+        );
+    }
+
+    #[test]
+    fn deploy_and_link_library() {
+        let caller = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(caller, Some(U256::from(1e18))).unwrap();
+
+        // the library must be deployed first
+        assert!(DeploymentPlan::new(&mut evm)
+            .add("Main", CONTRACT, &["Lib.sol:Doubler"])
+            .deploy_all(caller)
+            .is_err());
+
+        let addresses = DeploymentPlan::new(&mut evm)
+            .add("Lib.sol:Doubler", LIBRARY, &[])
+            .add("Main", CONTRACT, &["Lib.sol:Doubler"])
+            .deploy_all(caller)
+            .unwrap();
+        assert_eq!(2, addresses.len());
+
+        let input = U256::from(21).to_be_bytes_vec();
+        let result = evm
+            .transact_call(addresses["Main"], input, U256::ZERO)
+            .unwrap();
+        assert_eq!(U256::from(42), U256::from_be_slice(&result.result));
+    }
+
+    #[test]
+    fn create2_with_labeled_salts() {
+        let caller = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(caller, Some(U256::from(1e18))).unwrap();
+        let initcode = hex::decode(LIBRARY).unwrap();
+
+        let salt_a = salt_from_label("pool-a");
+        let salt_b = salt_from_label("pool-b");
+        assert_eq!(keccak256("pool-a"), salt_a);
+
+        let expected_a = compute_create2_address(caller, salt_a, &initcode);
+        let expected_b = compute_create2_address(caller, salt_b, &initcode);
+        assert_ne!(expected_a, expected_b);
+
+        let a = evm
+            .deploy_create2(caller, initcode.clone(), U256::ZERO, salt_a)
+            .unwrap();
+        let b = evm
+            .deploy_create2(caller, initcode.clone(), U256::ZERO, salt_b)
+            .unwrap();
+        assert_eq!(expected_a, a);
+        assert_eq!(expected_b, b);
+        assert!(evm.is_contract(a).unwrap());
+
+        // the address is already used
+        assert!(evm
+            .deploy_create2(caller, initcode, U256::ZERO, salt_a)
+            .is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//!
+//! Typed helpers to interact with ERC721 (NFT) contracts
+//!
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::sol;
+use anyhow::Result;
+
+use crate::BaseEvm;
+
+sol! {
+    /// The subset of the ERC721 standard used by the helpers
+    interface IERC721 {
+        function balanceOf(address owner) external view returns (uint256);
+        function ownerOf(uint256 tokenId) external view returns (address);
+        function transferFrom(address from, address to, uint256 tokenId) external;
+    }
+}
+
+impl BaseEvm {
+    /// Return the owner of `token_id` in the ERC721 contract at `token`
+    pub fn erc721_owner_of(&mut self, token: Address, token_id: U256) -> Result<Address> {
+        let result = self.transact_call_sol(
+            token,
+            IERC721::ownerOfCall { tokenId: token_id },
+            U256::ZERO,
+        )?;
+        Ok(result._0)
+    }
+
+    /// Return the number of tokens held by `owner` in the ERC721 contract at `token`
+    pub fn erc721_balance_of(&mut self, token: Address, owner: Address) -> Result<U256> {
+        let result = self.transact_call_sol(token, IERC721::balanceOfCall { owner }, U256::ZERO)?;
+        Ok(result._0)
+    }
+
+    /// Transfer `token_id` from `from` to `to`.  `caller` must be allowed to transfer
+    /// the token, i.e. the owner or an approved operator.
+    pub fn erc721_transfer_from(
+        &mut self,
+        caller: Address,
+        token: Address,
+        from: Address,
+        to: Address,
+        token_id: U256,
+    ) -> Result<()> {
+        self.transact_commit_sol(
+            caller,
+            token,
+            IERC721::transferFromCall {
+                from,
+                to,
+                tokenId: token_id,
+            },
+            U256::ZERO,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::hex;
+    use alloy_sol_types::SolCall;
+
+    sol! {
+        function mint(address to, uint256 tokenId) external;
+    }
+
+    // a minimal ERC721 with an unrestricted mint(address,uint256), and no approvals
+    const MINIMAL_ERC721: &str =
+        "6101118061000d6000396000f35f3560e01c80636352211e1461003557806370a082311461004657\
+        806340c10f191461006d57806323b872dd146100a4575b5f5ffd5b600435548015610031575f5260\
+        205ff35b60043579010000000000000000000000000000000000000000000000000017545f526020\
+        5ff35b60243554610031576004356024355560043579010000000000000000000000000000000000\
+        00000000000000001780546001019055005b60443554600435141561003157336004351415610031\
+        57602435604435556004357901000000000000000000000000000000000000000000000000001780\
+        54600190039055602435790100000000000000000000000000000000000000000000000000178054\
+        600101905500";
+
+    #[test]
+    fn erc721_helpers() {
+        let alice = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.create_account(alice, Some(U256::from(1e18))).unwrap();
+        let token = evm
+            .deploy(alice, hex::decode(MINIMAL_ERC721).unwrap(), U256::ZERO)
+            .unwrap();
+
+        for id in [1, 2] {
+            let call = mintCall {
+                to: alice,
+                tokenId: U256::from(id),
+            };
+            evm.transact_commit(alice, token, call.abi_encode(), U256::ZERO)
+                .unwrap();
+        }
+        assert_eq!(alice, evm.erc721_owner_of(token, U256::from(1)).unwrap());
+        assert_eq!(U256::from(2), evm.erc721_balance_of(token, alice).unwrap());
+        assert_eq!(U256::ZERO, evm.erc721_balance_of(token, bob).unwrap());
+        // not minted
+        assert!(evm.erc721_owner_of(token, U256::from(3)).is_err());
+
+        evm.erc721_transfer_from(alice, token, alice, bob, U256::from(2))
+            .unwrap();
+        assert_eq!(bob, evm.erc721_owner_of(token, U256::from(2)).unwrap());
+        assert_eq!(U256::from(1), evm.erc721_balance_of(token, alice).unwrap());
+        assert_eq!(U256::from(1), evm.erc721_balance_of(token, bob).unwrap());
+
+        // alice no longer owns it
+        assert!(evm
+            .erc721_transfer_from(alice, token, alice, bob, U256::from(2))
+            .is_err());
+    }
+}
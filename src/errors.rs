@@ -1,11 +1,14 @@
 //!
-//! Database errors
+//! Database and execution errors
 //!
-use alloy_primitives::{Address, U256};
-use revm::primitives::EVMError;
+use alloy_dyn_abi::DynSolValue;
+use alloy_primitives::{Address, Bytes, U256};
 use revm::primitives::B256;
+use revm::primitives::{EVMError, HaltReason};
 use thiserror::Error;
 
+use crate::ContractAbi;
+
 use std::convert::Infallible;
 
 /// Wrapper for Database errors
@@ -19,6 +22,8 @@ pub enum DatabaseError {
     GetAccount(Address),
     #[error("failed to get storage for {0} at {1}")]
     GetStorage(Address, U256),
+    #[error("failed to get block {0:?}")]
+    GetBlock(Option<u64>),
     #[error("failed to get block hash for {0}")]
     GetBlockHash(U256),
     #[error("{0}")]
@@ -39,3 +44,69 @@ impl From<Infallible> for DatabaseError {
         match value {}
     }
 }
+
+/// A transaction that failed to execute.  Returned, wrapped in an `anyhow::Error`,
+/// by the methods of `BaseEvm` that run a transaction.  Use `downcast_ref` to get
+/// at it, or `BaseEvm::try_transact_commit` and `try_transact_call`, which return
+/// it directly.
+#[derive(Error, Debug)]
+pub enum EvmError {
+    /// The call reverted.  `output` is the raw revert data, which may be a custom
+    /// error bubbled up from a nested call
+    #[error("{}", revert_message(.reason, *.gas_used))]
+    Revert {
+        reason: Option<String>,
+        output: Bytes,
+        gas_used: u64,
+    },
+    /// The call halted, e.g. out of gas or an invalid opcode
+    #[error("Halted: {reason:?}. Gas used: {gas_used:?}")]
+    Halt { reason: HaltReason, gas_used: u64 },
+    /// The transaction couldn't be run, e.g. it's invalid or the database failed
+    #[error("{0}")]
+    Transaction(String),
+}
+
+impl From<anyhow::Error> for EvmError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<EvmError>() {
+            Ok(err) => err,
+            Err(err) => EvmError::Transaction(err.to_string()),
+        }
+    }
+}
+
+impl EvmError {
+    /// Decode the revert data as one of the custom errors in `abi`, returning the
+    /// name of the error and its values.  Returns `None` if the call didn't revert or
+    /// the revert data doesn't match an error in the ABI.
+    pub fn as_custom_error(&self, abi: &ContractAbi) -> Option<(String, Vec<DynSolValue>)> {
+        match self {
+            EvmError::Revert { output, .. } => abi.decode_error(output).ok(),
+            EvmError::Halt { .. } | EvmError::Transaction(_) => None,
+        }
+    }
+}
+
+fn revert_message(reason: &Option<String>, gas_used: u64) -> String {
+    match reason {
+        Some(reason) => format!("Reverted: {:?}. Gas used: {:?}", reason, gas_used),
+        _ => format!("Reverted with no reason. Gas used: {:?}", gas_used),
+    }
+}
+
+/// A transfer of value that failed.  Returned, wrapped in an `anyhow::Error`, by
+/// `BaseEvm::try_transfer` and `BaseEvm::transfer`.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum TransferError {
+    /// The sender's balance is less than the value
+    #[error("Insufficient balance: {from} has {balance} but tried to send {value}")]
+    InsufficientBalance {
+        from: Address,
+        balance: U256,
+        value: U256,
+    },
+    /// The recipient is a contract without a payable `receive` or `fallback` function
+    #[error("Contract {0} cannot receive value: no payable receive or fallback function")]
+    NoReceiveFunction(Address),
+}
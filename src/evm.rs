@@ -5,29 +5,79 @@
 //! is a simplfied version of [Foundry's Executor](https://github.com/foundry-rs/foundry)
 //!
 
-use alloy_primitives::{Address, Bytes, U256};
-use alloy_sol_types::{decode_revert_reason, SolCall};
+use alloy_dyn_abi::{DynSolType, DynSolValue};
+use alloy_primitives::{keccak256, Address, Bytes, B256, I256, U256};
+use alloy_sol_types::{decode_revert_reason, SolCall, SolValue};
 use anyhow::{anyhow, bail, Result};
 use revm::{
-    db::{DatabaseCommit, DatabaseRef},
+    db::{Database, DatabaseCommit, DatabaseRef},
     primitives::{
-        Account, AccountInfo, BlockEnv, Env, EnvWithHandlerCfg, ExecutionResult, HashMap as Map,
-        Log, Output, ResultAndState, TransactTo, TxEnv,
+        hash_map::Entry, Account, AccountInfo, AccountStatus, BlockEnv, Bytecode, CreateScheme,
+        Env, EnvWithHandlerCfg, ExecutionResult, HaltReason, HashMap as Map, Log, Output,
+        ResultAndState, SpecId, StorageSlot, TransactTo, TxEnv, KECCAK_EMPTY,
     },
 };
 
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::Path,
+};
+
 use crate::{
-    db::{CreateFork, StorageBackend},
-    SnapShot,
+    abi::DecodedEvent,
+    db::{AccessSet, CreateFork, StorageBackend},
+    errors::{EvmError, TransferError},
+    gas::GasReport,
+    generate_seeded_addresses,
+    hardfork::{spec_for_block, spec_for_hardfork},
+    inspectors::{
+        GasBreakdown, GasBreakdownInspector, StorageWriteInspector, TraceInspector, TraceStep,
+        TransientStorageInspector, TransientWrite,
+    },
+    recorder::{replay, RecordedTx},
+    snapshot::{SnapShotAccountRecord, SnapShotSource},
+    ContractAbi, SnapShot,
 };
 
 /// type alias for a `revm` hashmap of `Address` => `Account`
 type StateChangeSet = Map<Address, Account>;
 
+/// type alias for the callback invoked on each commit
+type CommitCallback = Box<dyn FnMut(&CommitInfo) + Send>;
+
+/// type alias for a captured log: `(block number, log index, log)`
+type CapturedLog = (u64, usize, Log);
+
+/// type alias for the callback invoked with `(old, new)` when a watched slot changes
+type SlotCallback = Box<dyn FnMut(U256, U256) + Send>;
+
 /// EVM that supports both in-memory and forked storage.
 pub struct BaseEvm {
     backend: StorageBackend,
     env: EnvWithHandlerCfg,
+    gas_report: Option<GasReport>,
+    recorded: Option<Vec<RecordedTx>>,
+    captured_logs: Option<Vec<CapturedLog>>,
+    auto_nonce: bool,
+    on_commit: Option<CommitCallback>,
+    slot_watchers: Vec<(Address, U256, SlotCallback)>,
+    // the block number, and the number of logs committed in that block
+    block_logs: (u64, usize),
+    // see `set_gas_limit`
+    gas_limit: Option<u64>,
+}
+
+// The state saved by `BaseEvm::checkpoint`
+struct Checkpoint {
+    backend: StorageBackend,
+    env: EnvWithHandlerCfg,
+    gas_report: Option<GasReport>,
+    recorded: Option<Vec<RecordedTx>>,
+    captured_logs: Option<Vec<CapturedLog>>,
+    auto_nonce: bool,
+    block_logs: (u64, usize),
+    gas_limit: Option<u64>,
 }
 
 /// Create an EVM with the in-memory database
@@ -39,11 +89,39 @@ impl Default for BaseEvm {
 
 impl BaseEvm {
     /// Create an instance of the EVM.  If fork is None it will use the in-memory database.
-    /// Otherwise it will create a forked database.
+    /// Otherwise it will create a forked database, using the hardfork active at the
+    /// forked block for mainnet.
     pub fn new(fork: Option<CreateFork>) -> Self {
-        let env = EnvWithHandlerCfg::default();
-        let backend = StorageBackend::new(fork);
-        Self { env, backend }
+        Self::try_new(fork).expect("BaseEvm: failed to create the fork")
+    }
+
+    /// Same as `new`, but returns an error, instead of panicking, if the fork can't be
+    /// created, e.g. the fork URL is malformed or the node can't be reached.
+    pub fn try_new(fork: Option<CreateFork>) -> Result<Self> {
+        let mut env = EnvWithHandlerCfg::default();
+        let backend = StorageBackend::try_new(fork)?;
+        if let Some(excess_blob_gas) = backend.excess_blob_gas {
+            env.block.set_blob_excess_gas_and_price(excess_blob_gas);
+        }
+        // use the hardfork of the forked block, if known.  See `set_spec_id` to override
+        if let Some(spec_id) = backend
+            .chain_id
+            .and_then(|id| spec_for_block(id, backend.block_number, backend.timestamp))
+        {
+            env.handler_cfg.spec_id = spec_id;
+        }
+        Ok(Self {
+            env,
+            backend,
+            gas_report: None,
+            recorded: None,
+            captured_logs: None,
+            auto_nonce: true,
+            on_commit: None,
+            slot_watchers: Vec::new(),
+            block_logs: (0, 0),
+            gas_limit: None,
+        })
     }
 
     /// Create an instance of the EVM and load it's state from the `SnapShot`.  This
@@ -52,7 +130,164 @@ impl BaseEvm {
         let env = EnvWithHandlerCfg::default();
         let mut backend = StorageBackend::default();
         backend.load_snapshot(snap);
-        Self { env, backend }
+        Self {
+            env,
+            backend,
+            gas_report: None,
+            recorded: None,
+            captured_logs: None,
+            auto_nonce: true,
+            on_commit: None,
+            slot_watchers: Vec::new(),
+            block_logs: (0, 0),
+            gas_limit: None,
+        }
+    }
+
+    /// Reconstruct a session: create an EVM from the starting `snapshot` and re-apply
+    /// the recorded `txs` in order, at the block number and timestamp they were recorded.
+    /// See [`crate::recorder::replay`]
+    pub fn replay_from(snapshot: SnapShot, txs: &[RecordedTx]) -> Result<Self> {
+        let mut evm = Self::new_from_snapshot(snapshot);
+        replay(txs, &mut evm)?;
+        Ok(evm)
+    }
+
+    /// Same as `new_from_snapshot`, but only loads the accounts for which `keep`
+    /// returns true.  For example, to only load contracts:
+    ///
+    /// `BaseEvm::new_from_snapshot_filtered(snap, |_, account| !account.code.is_empty())`
+    pub fn new_from_snapshot_filtered(
+        mut snap: SnapShot,
+        keep: impl Fn(&Address, &SnapShotAccountRecord) -> bool,
+    ) -> Self {
+        snap.accounts
+            .retain(|address, account| keep(address, account));
+        Self::new_from_snapshot(snap)
+    }
+
+    /// Return the environment used as the template for each transaction
+    pub fn env(&self) -> &EnvWithHandlerCfg {
+        &self.env
+    }
+
+    /// Return the environment used as the template for each transaction, to change
+    /// settings not otherwise exposed.  Changes apply to all later transactions.
+    ///
+    /// Note: the following fields are set for each transaction, so changing them here
+    /// has no effect:
+    /// - `block.number` and `block.timestamp` (see `update_block`)
+    /// - `tx.caller`, `tx.transact_to`, `tx.data`, and `tx.value`
+    /// - `tx.gas_price` is set to the `effective_gas_price`
+    ///
+    /// Read-only calls, without a caller, run with no basefee or gas price.
+    pub fn env_mut(&mut self) -> &mut EnvWithHandlerCfg {
+        &mut self.env
+    }
+
+    /// Set the spec id (hardfork) used by the EVM.  The default is `SpecId::LATEST`, or
+    /// the hardfork of the forked block on mainnet
+    pub fn set_spec_id(&mut self, spec_id: SpecId) {
+        self.env.handler_cfg.spec_id = spec_id;
+    }
+
+    /// Set the maximum size, in bytes, of deployed contract code (EIP-170).  The default
+    /// is `0x6000` (24KB).  `None` removes the limit.  Note: the maximum size of
+    /// initcode (EIP-3860) is twice this limit.
+    pub fn set_max_code_size(&mut self, size: Option<usize>) {
+        self.env.cfg.limit_contract_code_size = Some(size.unwrap_or(usize::MAX));
+    }
+
+    /// Limit the depth of nested calls, below the EVM's limit of 1024, to fail fast on
+    /// runaway recursion.  The top-level call is at depth 0, as in [`TraceStep`].  A
+    /// transaction with a call or contract creation nested deeper than `depth` fails
+    /// with [`EvmError::Halt`] and a reason of `HaltReason::CallTooDeep`, and no changes
+    /// are committed.  This applies to every kind of call, including traced ones.
+    pub fn set_max_call_depth(&mut self, depth: u16) {
+        self.backend.max_call_depth = Some(depth);
+    }
+
+    /// Set the spec id by hardfork name, e.g. "shanghai" or "cancun".
+    /// See [`spec_for_hardfork`] for the supported names.
+    pub fn set_hardfork(&mut self, name: &str) -> Result<()> {
+        let spec_id = spec_for_hardfork(name).ok_or(anyhow!("unknown hardfork: {}", name))?;
+        self.set_spec_id(spec_id);
+        Ok(())
+    }
+
+    /// Register a callback invoked after every committed transaction.  This replaces
+    /// any callback already registered.  Read calls (`transact_call`, `simulate`)
+    /// don't commit, so they don't invoke it.
+    pub fn on_commit(&mut self, f: CommitCallback) {
+        self.on_commit = Some(f);
+    }
+
+    /// Register a callback invoked with the `(old, new)` values of storage `slot` of
+    /// `address` whenever a committed transaction changes it.  This can be used to
+    /// check an invariant after every write.  Several slots can be watched.
+    pub fn watch_slot(&mut self, address: Address, slot: U256, f: SlotCallback) {
+        self.slot_watchers.push((address, slot, f));
+    }
+
+    /// Set the maximum gas a transaction can use, overriding `env.tx.gas_limit`.  A
+    /// transaction exceeding it halts with `OutOfGas`.  See `transact_commit_with_gas_limit`
+    /// to set the limit for a single transaction.
+    pub fn set_gas_limit(&mut self, limit: u64) {
+        self.gas_limit = Some(limit);
+    }
+
+    /// Set the fees paid by transactions: the `gas_price` (the max fee per gas with a
+    /// `priority_fee`), and the optional EIP-1559 `priority_fee`.  The caller pays
+    /// `gas_used * effective_gas_price`, and must be able to afford the whole gas limit.
+    /// Unless one is set with `set_gas_limit`, the gas limit of a transaction is capped
+    /// to what the caller can afford, as `eth_call` does.  This doesn't change whether
+    /// the basefee check is disabled, see `sync_block_env_from_fork`.
+    pub fn set_fee_params(&mut self, gas_price: U256, priority_fee: Option<U256>) {
+        self.env.tx.gas_price = gas_price;
+        self.env.tx.gas_priority_fee = priority_fee;
+    }
+
+    /// Set whether the caller's nonce is incremented when a transaction is committed.
+    /// The default is `true`.  When `false`, the caller's nonce is reset to its
+    /// pre-transaction value after each commit, while all other state changes are applied.
+    /// This is useful when re-applying historical transactions.
+    ///
+    /// Note: transactions are sent without a nonce, so the EVM does not check nonces.
+    /// Deploying the same contract twice with auto-nonce off results in an address collision.
+    pub fn set_auto_nonce(&mut self, enabled: bool) {
+        self.auto_nonce = enabled;
+    }
+
+    /// Start recording the gas used by each function selector. See [`GasReport`]
+    pub fn enable_gas_report(&mut self) {
+        if self.gas_report.is_none() {
+            self.gas_report = Some(GasReport::default());
+        }
+    }
+
+    /// Return the gas report, if enabled
+    pub fn gas_report(&self) -> Option<&GasReport> {
+        self.gas_report.as_ref()
+    }
+
+    /// Create an instance of the EVM with the in-memory database initialized from a
+    /// Geth-style genesis allocation. See [`SnapShot::from_genesis_alloc`]
+    pub fn from_genesis_alloc(raw: &str) -> Result<Self> {
+        let mut snap = SnapShot::from_genesis_alloc(raw)?;
+        let start = StorageBackend::default();
+        snap.block_num = start.block_number;
+        snap.timestamp = start.timestamp;
+        Ok(Self::new_from_snapshot(snap))
+    }
+
+    /// Create an instance of the EVM from a snapshot JSON file previously written
+    /// with `save_snapshot`.  This will use the in-memory database.
+    pub fn load_snapshot_file(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| anyhow!("failed to read snapshot file {}: {}", path.display(), e))?;
+        let snap = serde_json::from_str::<SnapShot>(&raw)
+            .map_err(|e| anyhow!("failed to parse snapshot file {}: {}", path.display(), e))?;
+        Ok(Self::new_from_snapshot(snap))
     }
 
     /// Create an account for the given `user` with an optional balance (`amount`).
@@ -66,6 +301,75 @@ impl BaseEvm {
         Ok(())
     }
 
+    /// Install `runtime_code` at `address` without running a constructor, like
+    /// Foundry's `vm.etch`.  The account's balance, nonce and storage are kept.
+    pub fn etch(&mut self, address: Address, runtime_code: Vec<u8>) -> Result<()> {
+        let mut info = self.backend.basic_ref(address)?.unwrap_or_default();
+        let code = Bytecode::new_raw(runtime_code.into());
+        info.code_hash = code.hash_slow();
+        info.code = Some(code);
+        self.backend.insert_account_info(address, info);
+        Ok(())
+    }
+
+    /// Install `runtime_code` at `address` along with its full `storage` and `balance`,
+    /// in one step.  Any existing storage is replaced, and the nonce is kept.  This
+    /// combines `etch`, replacing the storage, and `set_balance`.
+    pub fn etch_with_storage(
+        &mut self,
+        address: Address,
+        runtime_code: Vec<u8>,
+        storage: HashMap<U256, U256>,
+        balance: U256,
+    ) -> Result<()> {
+        let checkpoint = self.checkpoint();
+        let mut info = self.backend.basic_ref(address)?.unwrap_or_default();
+        let code = Bytecode::new_raw(runtime_code.into());
+        info.code_hash = code.hash_slow();
+        info.code = Some(code);
+        info.balance = balance;
+        self.backend.insert_account_info(address, info);
+        if let Err(e) = self
+            .backend
+            .replace_account_storage(address, storage.into_iter().collect())
+        {
+            self.restore(checkpoint);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Create `count` accounts with addresses and balances derived from `seed`, so the
+    /// same seed always creates the same accounts.  Each balance is in the range
+    /// `min..=max`.  See [`generate_seeded_addresses`].
+    ///
+    /// Panics if `min > max`
+    pub fn create_random_agents(
+        &mut self,
+        seed: u64,
+        count: usize,
+        min: U256,
+        max: U256,
+    ) -> Vec<Address> {
+        assert!(min <= max, "create_random_agents: min is greater than max");
+        let range = (max - min).checked_add(U256::from(1));
+
+        let addresses = generate_seeded_addresses(seed, count);
+        for (i, address) in addresses.iter().enumerate() {
+            let random = U256::from_be_bytes(
+                keccak256([address.as_slice(), &(i as u64).to_be_bytes()].concat()).0,
+            );
+            let balance = match range {
+                Some(range) => min + random % range,
+                // the range is all of U256
+                _ => random,
+            };
+            self.backend
+                .insert_account_info(*address, AccountInfo::from_balance(balance));
+        }
+        addresses
+    }
+
     /// Return the balance for the `caller`'s account.
     pub fn get_balance(&mut self, caller: Address) -> Result<U256> {
         Ok(self
@@ -75,6 +379,285 @@ impl BaseEvm {
             .unwrap_or_default())
     }
 
+    /// Return the balances of `addresses`, in order.  For a fork, the accounts not
+    /// already loaded are fetched concurrently.
+    pub fn get_balances(&mut self, addresses: &[Address]) -> Result<Vec<U256>> {
+        self.backend.prefetch_accounts(addresses)?;
+        addresses
+            .iter()
+            .map(|address| self.get_balance(*address))
+            .collect()
+    }
+
+    /// Return the balance of `address` on the forked chain, at the fork block, ignoring
+    /// any local changes.  Returns an error if not forking.
+    pub fn remote_balance(&self, address: Address) -> Result<U256> {
+        match self.backend.remote_basic(address) {
+            Some(info) => Ok(info?.balance),
+            _ => bail!("remote_balance requires a fork"),
+        }
+    }
+
+    /// Can `caller` afford to send `value` and pay for up to `gas_limit` at `gas_price`,
+    /// i.e. is `balance >= value + gas_limit * gas_price`?  This only reads state.
+    pub fn can_afford(
+        &mut self,
+        caller: Address,
+        value: U256,
+        gas_limit: u64,
+        gas_price: U256,
+    ) -> Result<bool> {
+        let cost = U256::from(gas_limit)
+            .checked_mul(gas_price)
+            .and_then(|gas| gas.checked_add(value));
+        match cost {
+            Some(cost) => Ok(self.get_balance(caller)? >= cost),
+            _ => Ok(false),
+        }
+    }
+
+    /// Is there a contract (non-empty code) at `address`?  For a fork, this fetches
+    /// the account if it's not already loaded.
+    pub fn is_contract(&mut self, address: Address) -> Result<bool> {
+        Ok(self
+            .get_account_info(address)?
+            .is_some_and(|info| info.code_hash != KECCAK_EMPTY))
+    }
+
+    /// Return the addresses, sorted, of the known accounts with code deployed.  For a
+    /// fork, this only includes contracts already fetched from the remote node.
+    pub fn contract_addresses(&self) -> Vec<Address> {
+        self.backend
+            .known_accounts()
+            .into_iter()
+            .filter(|(_, info)| info.code_hash != KECCAK_EMPTY)
+            .map(|(address, _)| address)
+            .collect()
+    }
+
+    /// Return the number of accounts known locally: created, changed, or already
+    /// fetched from the fork
+    pub fn account_count(&self) -> usize {
+        self.backend.known_accounts().len()
+    }
+
+    /// Return the number of storage slots known locally, across all accounts:
+    /// written, or already read from the fork
+    pub fn storage_slot_count(&self) -> usize {
+        self.backend.known_storage_count()
+    }
+
+    /// Compare the state known locally by this EVM and `other`: the balance, nonce,
+    /// code and storage of each account, by address.  An account or storage slot
+    /// known by only one of them is compared to an empty account or zero.  Returns
+    /// the first difference found.
+    pub fn state_eq(&self, other: &BaseEvm) -> StateEqResult {
+        let left = self
+            .backend
+            .known_accounts()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let right = other
+            .backend
+            .known_accounts()
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let addresses = left.keys().chain(right.keys()).collect::<BTreeSet<_>>();
+
+        for address in addresses {
+            let address = *address;
+            let l = left.get(&address).cloned().unwrap_or_default();
+            let r = right.get(&address).cloned().unwrap_or_default();
+            if l.balance != r.balance {
+                return StateEqResult::Balance {
+                    address,
+                    left: l.balance,
+                    right: r.balance,
+                };
+            }
+            if l.nonce != r.nonce {
+                return StateEqResult::Nonce {
+                    address,
+                    left: l.nonce,
+                    right: r.nonce,
+                };
+            }
+            if l.code_hash != r.code_hash {
+                return StateEqResult::Code {
+                    address,
+                    left: l.code_hash,
+                    right: r.code_hash,
+                };
+            }
+
+            let left_storage = self.backend.known_storage(address);
+            let right_storage = other.backend.known_storage(address);
+            let slots = left_storage
+                .keys()
+                .chain(right_storage.keys())
+                .collect::<BTreeSet<_>>();
+            for slot in slots {
+                let l = left_storage.get(slot).cloned().unwrap_or_default();
+                let r = right_storage.get(slot).cloned().unwrap_or_default();
+                if l != r {
+                    return StateEqResult::Storage {
+                        address,
+                        slot: *slot,
+                        left: l,
+                        right: r,
+                    };
+                }
+            }
+        }
+        StateEqResult::Equal
+    }
+
+    /// Return the balance, nonce, code hash and code for the account at `address`
+    pub fn get_account_info(&mut self, address: Address) -> Result<Option<AccountInfo>> {
+        Ok(self.backend.basic(address)?)
+    }
+
+    /// Return the runtime bytecode deployed at `address`.  Returns empty `Bytes` if
+    /// the account has no code.
+    pub fn get_code(&mut self, address: Address) -> Result<Bytes> {
+        let info = match self.backend.basic_ref(address)? {
+            Some(info) => info,
+            _ => return Ok(Bytes::new()),
+        };
+        let code = match info.code {
+            Some(code) => code,
+            _ => self.backend.code_by_hash_ref(info.code_hash)?,
+        };
+        Ok(code.original_bytes())
+    }
+
+    /// Check the code deployed at `address` hashes to `expected`.  This can be used to
+    /// make sure a (forked) contract is the expected implementation before using it.
+    pub fn verify_code_hash(&mut self, address: Address, expected: B256) -> Result<bool> {
+        let code = self.get_code(address)?;
+        Ok(Bytecode::new_raw(code).hash_slow() == expected)
+    }
+
+    /// Read the value of the storage `slot` for the contract at `address`.  Useful to
+    /// check state a contract has no getter for, e.g. packed variables.  A slot that
+    /// has never been written reads as zero, see `set_treat_missing_storage_as_zero`.
+    pub fn get_storage_slot(&mut self, address: Address, slot: U256) -> Result<U256> {
+        Ok(self.backend.storage_slot(address, slot)?)
+    }
+
+    /// Write `value` to the storage `slot` of `address`, e.g. to prime a forked contract's
+    /// state before a simulation.  The account is created if it doesn't exist yet.
+    pub fn set_storage_slot(&mut self, address: Address, slot: U256, value: U256) -> Result<()> {
+        self.backend.insert_account_storage(address, slot, value)?;
+        Ok(())
+    }
+
+    /// Read the values of many storage `slots` for the contract at `address`, returned
+    /// in the same order as `slots`. When forking, slots that are not already cached
+    /// are fetched from the remote node concurrently.  A slot that has never been written
+    /// reads as zero, see `set_treat_missing_storage_as_zero`.
+    pub fn get_storage_slots(&mut self, address: Address, slots: &[U256]) -> Result<Vec<U256>> {
+        Ok(self.backend.storage_batch(address, slots)?)
+    }
+
+    /// Read the state variable `name` of the contract at `address`, using the storage
+    /// layout of `abi` (see [`ContractAbi::with_storage_layout`]).  Only value types
+    /// stored in a single slot are supported, e.g. `uint256`, `address`, `bool`, and
+    /// packed variables.  Mappings, arrays, strings and structs are not.
+    pub fn read_variable(
+        &mut self,
+        abi: &ContractAbi,
+        address: Address,
+        name: &str,
+    ) -> Result<DynSolValue> {
+        let var = abi
+            .storage_variable(name)
+            .ok_or(anyhow!("read_variable: no state variable named {}", name))?;
+        if var.encoding != "inplace" || var.offset + var.number_of_bytes > 32 {
+            bail!(
+                "read_variable: {} of type {} is not supported",
+                name,
+                var.type_label
+            );
+        }
+
+        let word = self.get_storage_slots(address, &[var.slot])?[0];
+        let bits = var.number_of_bytes * 8;
+        let mut value = word >> (var.offset * 8);
+        if bits < 256 {
+            value &= (U256::from(1) << bits) - U256::from(1);
+        }
+
+        let label = var.type_label.as_str();
+        let ty = if label.starts_with("contract ") || label == "address payable" {
+            DynSolType::Address
+        } else if label.starts_with("enum ") {
+            DynSolType::Uint(8)
+        } else {
+            DynSolType::parse(label).map_err(|_| {
+                anyhow!("read_variable: {} of type {} is not supported", name, label)
+            })?
+        };
+        match ty {
+            // stored right aligned in the slot, but ABI encoded left aligned
+            DynSolType::FixedBytes(size) => Ok(DynSolValue::FixedBytes(
+                B256::from(value << (256 - bits)),
+                size,
+            )),
+            DynSolType::Int(size) => {
+                let negative = bits < 256 && value.bit(bits - 1);
+                if negative {
+                    value |= U256::MAX << bits;
+                }
+                Ok(DynSolValue::Int(I256::from_raw(value), size))
+            }
+            DynSolType::Uint(_) | DynSolType::Address | DynSolType::Bool => {
+                Ok(ty.abi_decode(&value.to_be_bytes::<32>())?)
+            }
+            _ => bail!("read_variable: {} of type {} is not supported", name, label),
+        }
+    }
+
+    /// Set many storage slots across many contracts: `address => (slot => value)`.
+    /// Accounts that don't exist are created.  If any write fails, none of the
+    /// overrides are applied.
+    pub fn apply_storage_overrides(
+        &mut self,
+        overrides: HashMap<Address, HashMap<U256, U256>>,
+    ) -> Result<()> {
+        let checkpoint = self.checkpoint();
+        for (address, slots) in overrides {
+            for (slot, value) in slots {
+                if let Err(e) = self.backend.insert_account_storage(address, slot, value) {
+                    self.restore(checkpoint);
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the storage of the contract at `address` as a JSON object of hex encoded
+    /// `slot => value`, e.g. `{"0x0": "0x2a"}`.  Only the slots known locally are
+    /// included: those written, or already read from a fork.
+    pub fn dump_storage_json(&mut self, address: Address) -> Result<String> {
+        let storage = self
+            .backend
+            .known_storage(address)
+            .into_iter()
+            .map(|(slot, value)| (hex_quantity(slot), hex_quantity(value)))
+            .collect::<BTreeMap<_, _>>();
+        Ok(serde_json::to_string(&storage)?)
+    }
+
+    /// Set whether reading a storage slot that has never been written with
+    /// `get_storage_slot` or `get_storage_slots` returns zero, as the EVM does, or an
+    /// error.  The default is `true` (zero).  This only applies to the in-memory
+    /// database, and doesn't change how contracts read storage.
+    pub fn set_treat_missing_storage_as_zero(&mut self, enabled: bool) {
+        self.backend.missing_storage_as_zero = enabled;
+    }
+
     /// Set the balance for the given `address` with the given `amount`
     pub fn set_balance(&mut self, address: Address, amount: U256) -> Result<&mut Self> {
         let mut account = self.backend.basic_ref(address)?.unwrap_or_default();
@@ -84,93 +667,825 @@ impl BaseEvm {
         Ok(self)
     }
 
+    /// Set the token balance of `to` to `amount` by writing the token's storage
+    /// directly, like Foundry's `deal`.  `balances_slot` is the storage slot of the
+    /// token's `address => uint256` balances mapping, so the balance is stored at
+    /// `keccak256(abi.encode(to, balances_slot))`.  The total supply is not changed.
+    pub fn deal(
+        &mut self,
+        token: Address,
+        to: Address,
+        amount: U256,
+        balances_slot: U256,
+    ) -> Result<()> {
+        let slot = keccak256((to, balances_slot).abi_encode());
+        self.set_storage_slot(token, slot.into(), amount)
+    }
+
+    /// Fund `agent` with a balance of `eth`, and deal it tokens.  Each token is
+    /// `(token address, amount, balances slot)`.  See `deal`.
+    pub fn setup_agent(
+        &mut self,
+        agent: Address,
+        eth: U256,
+        tokens: &[(Address, U256, U256)],
+    ) -> Result<()> {
+        self.set_balance(agent, eth)?;
+        for (token, amount, balances_slot) in tokens {
+            self.deal(*token, agent, *amount, *balances_slot)?;
+        }
+        Ok(())
+    }
+
+    /// Start recording every committed transaction. See [`crate::recorder::replay`]
+    pub fn enable_recording(&mut self) {
+        if self.recorded.is_none() {
+            self.recorded = Some(Vec::new());
+        }
+    }
+
+    /// Return the transactions recorded so far, in commit order
+    pub fn recorded_transactions(&self) -> Vec<RecordedTx> {
+        self.recorded.clone().unwrap_or_default()
+    }
+
+    /// Set whether the logs of committed transactions are kept.  The default is
+    /// `false`.  Logs are always returned in the `CallResult` of a transaction.
+    /// Disabling capture discards the logs captured so far.
+    pub fn set_capture_logs(&mut self, enabled: bool) {
+        if !enabled {
+            self.captured_logs = None;
+        } else if self.captured_logs.is_none() {
+            self.captured_logs = Some(Vec::new());
+        }
+    }
+
+    /// Return the logs captured so far, in commit order. See `set_capture_logs`
+    pub fn captured_logs(&self) -> Vec<Log> {
+        self.captured_logs
+            .iter()
+            .flatten()
+            .map(|(_, _, log)| log.clone())
+            .collect()
+    }
+
+    /// Return and clear the logs captured so far
+    pub fn take_captured_logs(&mut self) -> Vec<Log> {
+        self.captured_logs
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, _, log)| log)
+            .collect()
+    }
+
+    /// Return the logs captured so far as a JSON array of logs in the format returned
+    /// by `eth_getLogs`: `address`, `topics`, `data`, `blockNumber`, `logIndex` and
+    /// `removed`.  Simulated transactions don't have a hash, so the block and
+    /// transaction hashes are not included.  See `set_capture_logs`
+    pub fn logs_as_rpc_json(&self) -> Result<String> {
+        let logs = self
+            .captured_logs
+            .iter()
+            .flatten()
+            .map(|(block_number, log_index, log)| {
+                serde_json::json!({
+                    "address": log.address,
+                    "topics": log.data.topics(),
+                    "data": format!("0x{}", hex::encode(&log.data.data)),
+                    "blockNumber": format!("{:#x}", block_number),
+                    "logIndex": format!("{:#x}", log_index),
+                    "removed": false,
+                })
+            })
+            .collect::<Vec<_>>();
+        Ok(serde_json::to_string(&logs)?)
+    }
+
+    /// Commit a recorded transaction at the block number and timestamp it was recorded
+    pub(crate) fn apply_recorded_tx(&mut self, tx: &RecordedTx) -> Result<CallResult> {
+        let nonce = self.caller_nonce(tx.caller)?;
+        if nonce != tx.nonce {
+            bail!(
+                "Replay: nonce gap for {}: the transaction has nonce {} but the account nonce is {}",
+                tx.caller,
+                tx.nonce,
+                nonce
+            );
+        }
+
+        self.backend.block_number = tx.block_number;
+        self.backend.timestamp = tx.timestamp;
+
+        let transact_to = match (tx.to, tx.salt) {
+            (Some(to), _) => TransactTo::call(to),
+            (_, Some(salt)) => TransactTo::create2(salt.into()),
+            _ => TransactTo::create(),
+        };
+        let mut env = self.build_env(Some(tx.caller), transact_to, tx.data.clone(), tx.value);
+        let result = self.backend.run_transact(&mut env)?;
+        let mut call_results = process_call_result(result)?;
+        self.commit(&env, &mut call_results)?;
+
+        Ok(call_results)
+    }
+
+    /// Fetch the transactions of the block `block_number` from the fork and commit them,
+    /// in order, at the block's number and timestamp.  Use this to replay a real block
+    /// on top of the forked state, e.g. fork at the block before it.  Each transaction
+    /// is sent by its (recovered) sender with its value, and gas is paid at the EVM's
+    /// gas price, not the transaction's.
+    ///
+    /// Returns an error if not forking, if a transaction's nonce doesn't match its
+    /// sender's, or if a transaction fails, e.g. it also reverted in the real block.
+    /// Transactions committed before the error are kept.
+    pub fn replay_block(&mut self, block_number: u64) -> Result<Vec<CallResult>> {
+        let (block, txs) = match self.backend.fork_block_transactions(block_number) {
+            Some(result) => result?,
+            _ => bail!("replay_block requires a fork"),
+        };
+        txs.into_iter()
+            .map(|tx| {
+                self.apply_recorded_tx(&RecordedTx {
+                    caller: tx.from,
+                    to: tx.to,
+                    data: tx.input,
+                    salt: None,
+                    value: tx.value,
+                    nonce: tx.nonce,
+                    block_number: block.number,
+                    timestamp: block.timestamp,
+                })
+            })
+            .collect()
+    }
+
+    /// Same as `transact_commit`, but returns an error, and doesn't commit, if the
+    /// transaction doesn't emit the event `event_name` of `abi`.  Like Foundry's
+    /// `expectEmit`, this is useful to write concise tests.
+    pub fn transact_commit_expect_event(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        abi: &ContractAbi,
+        event_name: &str,
+    ) -> Result<CallResult> {
+        if !abi.event_names().iter().any(|name| name == event_name) {
+            bail!("expect_event: no event named {} in the ABI", event_name);
+        }
+        let mut env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
+        let result = self.backend.run_transact(&mut env)?;
+        let mut call_results = process_call_result(result)?;
+        let emitted = abi
+            .extract_logs(call_results.logs.clone())
+            .iter()
+            .any(|(name, _)| name == event_name);
+        if !emitted {
+            bail!("expect_event: event {} was not emitted", event_name);
+        }
+        self.record_gas(&env, &call_results);
+        self.commit(&env, &mut call_results)?;
+
+        Ok(call_results)
+    }
+
     /// Create a snapshot of the current database. This can be used to reload state.
     pub fn create_snapshot(&self) -> Result<SnapShot> {
         self.backend.create_snapshot()
     }
 
+    /// Same as `create_snapshot`, but without the storage slots with a value of zero,
+    /// such as slots read from a fork and found to be empty.  See
+    /// [`SnapShot::prune_zero_storage`].
+    ///
+    /// Note: a pruned slot is missing when the snapshot is reloaded, so reading it with
+    /// `get_storage_slots` is an error if missing storage is not treated as zero.
+    pub fn create_snapshot_pruned(&self) -> Result<SnapShot> {
+        let mut snap = self.create_snapshot()?;
+        snap.prune_zero_storage();
+        Ok(snap)
+    }
+
+    /// Start recording every account and storage slot read from the fork, by
+    /// transactions and other methods, discarding any accesses already recorded.
+    /// Use with `create_snapshot_from_accesses` to create a minimal snapshot of the
+    /// state used by a simulation.  Does nothing with the in-memory database.
+    pub fn begin_access_recording(&mut self) {
+        self.backend.start_recording_accesses()
+    }
+
+    /// Return the accounts and storage slots read since `begin_access_recording`
+    pub fn access_record(&self) -> AccessSet {
+        self.backend.accesses()
+    }
+
+    /// Create a snapshot with only the accounts and storage slots read since
+    /// `begin_access_recording`, with their current values.  The snapshot can be
+    /// loaded to re-run the simulation without the fork.
+    pub fn create_snapshot_from_accesses(&self) -> Result<SnapShot> {
+        let accesses = self.access_record();
+        let mut accounts = BTreeMap::new();
+        for address in &accesses.accounts {
+            let info = self.backend.basic_ref(*address)?.unwrap_or_default();
+            let code = match info.code {
+                Some(code) => code,
+                _ => self.backend.code_by_hash_ref(info.code_hash)?,
+            };
+            let storage = accesses
+                .storage
+                .get(address)
+                .into_iter()
+                .flatten()
+                .map(|slot| Ok((*slot, self.backend.storage_ref(*address, *slot)?)))
+                .collect::<Result<BTreeMap<_, _>>>()?;
+            accounts.insert(
+                *address,
+                SnapShotAccountRecord {
+                    nonce: info.nonce,
+                    balance: info.balance,
+                    code: code.original_bytes(),
+                    storage,
+                },
+            );
+        }
+        Ok(SnapShot {
+            source: SnapShotSource::Fork,
+            block_num: self.backend.block_number,
+            timestamp: self.backend.timestamp,
+            accounts,
+        })
+    }
+
+    /// Compute the state root over all the accounts currently in the database.
+    /// See [`SnapShot::state_root`]
+    pub fn state_root(&self) -> Result<B256> {
+        Ok(self.create_snapshot()?.state_root())
+    }
+
+    /// Create a snapshot of the current database and write it to `path` as JSON.
+    /// It can be reloaded with `load_snapshot_file`.
+    pub fn save_snapshot(&self, path: &Path) -> Result<()> {
+        let snap = self.create_snapshot()?;
+        let json = serde_json::to_string_pretty(&snap)?;
+        fs::write(path, json)
+            .map_err(|e| anyhow!("failed to write snapshot file {}: {}", path.display(), e))
+    }
+
+    /// Discard state committed locally while keeping state already fetched from
+    /// a fork, so it doesn't need to be fetched again.  On an in-memory EVM this
+    /// removes all accounts.
+    pub fn reset_local_changes(&mut self) {
+        self.backend.reset_local_changes()
+    }
+
     /// Deploy a contract returning the contract's address.
     /// If `value` is specified, the constructor must be `payable`.
     pub fn deploy(&mut self, caller: Address, data: Vec<u8>, value: U256) -> Result<Address> {
-        let mut env = self.build_env(Some(caller), TransactTo::create(), data.into(), value);
+        self.deploy_with_info(caller, data, value)
+            .map(|info| info.address)
+    }
+
+    /// Same as `deploy` but returns [`DeployInfo`] which includes the runtime
+    /// bytecode returned by the constructor.
+    pub fn deploy_with_info(
+        &mut self,
+        caller: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<DeployInfo> {
+        self.deploy_to(caller, TransactTo::create(), data, value)
+    }
+
+    /// Deploy a contract with CREATE2, so its address only depends on the `caller`,
+    /// `salt`, and `data` (the initcode).  See [`crate::deploy::compute_create2_address`]
+    /// and [`crate::deploy::salt_from_label`].
+    pub fn deploy_create2(
+        &mut self,
+        caller: Address,
+        data: Vec<u8>,
+        value: U256,
+        salt: B256,
+    ) -> Result<Address> {
+        self.deploy_to(caller, TransactTo::create2(salt.into()), data, value)
+            .map(|info| info.address)
+    }
+
+    fn deploy_to(
+        &mut self,
+        caller: Address,
+        transact_to: TransactTo,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<DeployInfo> {
+        let mut env = self.build_env(Some(caller), transact_to, data.into(), value);
         let result = self.backend.run_transact(&mut env)?;
+        if let ExecutionResult::Halt {
+            reason: HaltReason::CreateCollision,
+            ..
+        } = result.result
+        {
+            let address = match env.tx.transact_to {
+                TransactTo::Create(CreateScheme::Create2 { salt }) => {
+                    caller.create2_from_code(B256::from(salt), &env.tx.data)
+                }
+                _ => caller.create(self.caller_nonce(caller)?),
+            };
+            bail!(
+                "deploy failed: a contract already exists at {} (create collision)",
+                address
+            );
+        }
         let mut call_results = process_call_result(result)?;
-        self.commit(&mut call_results);
+        self.commit(&env, &mut call_results)?;
 
         match call_results.address {
-            Some(addr) => Ok(addr),
+            Some(address) => Ok(DeployInfo {
+                address,
+                runtime_code: call_results.result,
+                gas_used: call_results.gas_used,
+            }),
             _ => Err(anyhow!("deploy did not return an Address!")),
         }
     }
 
-    /// Transfer `value` from `caller` -> `to`
+    /// Transfer `value` from `caller` -> `to`.  See `try_transfer` for the errors.
     pub fn transfer(&mut self, caller: Address, to: Address, value: U256) -> Result<()> {
-        let _ = self.transact_commit(caller, to, vec![], value)?;
+        self.try_transfer(caller, to, value)?;
         Ok(())
     }
 
-    /// Same as `transact_commit`, but supports [alloy's sol types](https://docs.rs/alloy-sol-types/latest/alloy_sol_types/index.html).
-    pub fn transact_commit_sol<T: SolCall>(
-        &mut self,
-        caller: Address,
-        to: Address,
-        args: T,
-        value: U256,
-    ) -> Result<<T as SolCall>::Return> {
-        let data = args.abi_encode();
-        let result = self.transact_commit(caller, to, data, value)?;
-        T::abi_decode_returns(&result.result, true)
-            .map_err(|e| anyhow!("transact commit sol error: {:?}", e))
+    /// Transfer `value` from `caller` -> `to`, returning whether `to` is a contract.
+    /// Fails with a `TransferError` if `caller` can't cover `value`, or if `to` is a
+    /// contract without a payable `receive` or `fallback` function.  If `to` reverts
+    /// for another reason, fails with the `EvmError`.
+    pub fn try_transfer(&mut self, caller: Address, to: Address, value: U256) -> Result<bool> {
+        let balance = self.get_balance(caller)?;
+        if balance < value {
+            return Err(TransferError::InsufficientBalance {
+                from: caller,
+                balance,
+                value,
+            }
+            .into());
+        }
+        let is_contract = self.is_contract(to)?;
+        self.send_value_and_call(caller, to, vec![], value)?;
+        Ok(is_contract)
     }
 
-    /// Write call to a contact.  Send a transaction where any state changes are persisted to the underlying database.
-    pub fn transact_commit(
+    /// Send `value` from `caller` -> `to` along with optional call `data`, committing any
+    /// state changes. If `to` has no code and `data` is empty, this is a plain transfer,
+    /// otherwise the contract at `to` is called.  Sending value with no `data` to a contract
+    /// that reverts without any output, as a contract without a payable `receive` or
+    /// `fallback` function does, returns an error saying so.  Other failures return the
+    /// `EvmError`.
+    pub fn send_value_and_call(
         &mut self,
         caller: Address,
         to: Address,
         data: Vec<u8>,
         value: U256,
     ) -> Result<CallResult> {
+        let bare_value = data.is_empty() && !self.get_code(to)?.is_empty();
         let mut env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
         let result = self.backend.run_transact(&mut env)?;
+        let no_receive = matches!(
+            &result.result,
+            ExecutionResult::Revert { output, .. } if output.is_empty()
+        );
+        if bare_value && value > U256::ZERO && no_receive {
+            return Err(TransferError::NoReceiveFunction(to).into());
+        }
         let mut call_results = process_call_result(result)?;
-        self.commit(&mut call_results);
+        self.record_gas(&env, &call_results);
+        self.commit(&env, &mut call_results)?;
 
         Ok(call_results)
     }
 
-    /// Same as `transact_call` but supports [alloy's sol types](https://docs.rs/alloy-sol-types/latest/alloy_sol_types/index.html).
-    pub fn transact_call_sol<T: SolCall>(
+    /// Same as `transact_commit`, but supports [alloy's sol types](https://docs.rs/alloy-sol-types/latest/alloy_sol_types/index.html).
+    pub fn transact_commit_sol<T: SolCall>(
         &mut self,
+        caller: Address,
         to: Address,
         args: T,
         value: U256,
     ) -> Result<<T as SolCall>::Return> {
         let data = args.abi_encode();
-        let result = self.transact_call(to, data, value)?;
-        T::abi_decode_returns(&result.result, true)
-            .map_err(|e| anyhow!("transact call sol error: {:?}", e))
-    }
-
-    /// Read call to a contract.  Send a transaction but any state changes are NOT persisted to the
-    /// database.   
-    pub fn transact_call(&mut self, to: Address, data: Vec<u8>, value: U256) -> Result<CallResult> {
-        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
-        let result = self.backend.run_transact(&mut env)?;
-        process_call_result(result)
+        let result = self.transact_commit(caller, to, data, value)?;
+        T::abi_decode_returns(&result.result, true).map_err(|e| {
+            anyhow!(
+                "transact commit sol error: {:?}. Raw output: 0x{}",
+                e,
+                hex::encode(&result.result)
+            )
+        })
     }
 
-    /// Simulate a `transact_commit` without actually committing/changing state.
-    pub fn simulate(
+    /// Same as `transact_commit_sol`, but also returns the gas used and the logs emitted,
+    /// with the events decoded using the given `abi`.
+    pub fn transact_commit_sol_full<T: SolCall>(
         &mut self,
         caller: Address,
         to: Address,
-        data: Vec<u8>,
+        args: T,
+        value: U256,
+        abi: &ContractAbi,
+    ) -> Result<SolCallResult<T>> {
+        let data = args.abi_encode();
+        let result = self.transact_commit(caller, to, data, value)?;
+        let decoded = T::abi_decode_returns(&result.result, true).map_err(|e| {
+            anyhow!(
+                "transact commit sol error: {:?}. Raw output: 0x{}",
+                e,
+                hex::encode(&result.result)
+            )
+        })?;
+        Ok(SolCallResult {
+            value: decoded,
+            gas_used: result.gas_used,
+            decoded_events: abi.extract_logs(result.logs.clone()),
+            logs: result.logs,
+        })
+    }
+
+    /// Write call to a contact.  Send a transaction where any state changes are persisted to the underlying database.
+    ///
+    /// Note: `caller` is both `msg.sender` for the top-level call and `tx.origin` for the
+    /// whole transaction.  `revm` derives `tx.origin` from the caller, so it can't be set
+    /// separately.
+    pub fn transact_commit(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<CallResult> {
+        self.transact_commit_with_gas_limit(caller, to, data, value, None)
+    }
+
+    /// Same as `transact_commit`, but the transaction can use at most `gas_limit`, if
+    /// set, instead of the limit set with `set_gas_limit`.
+    pub fn transact_commit_with_gas_limit(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        gas_limit: Option<u64>,
+    ) -> Result<CallResult> {
+        let mut env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
+        if let Some(limit) = gas_limit {
+            env.tx.gas_limit = limit;
+        }
+        Ok(self.commit_env(env)?)
+    }
+
+    /// Same as `transact_commit`, but returns the structured `EvmError` instead of an
+    /// `anyhow::Error`, so a revert can be matched on and its raw data decoded, e.g.
+    /// with `EvmError::as_custom_error`.
+    pub fn try_transact_commit(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<CallResult, EvmError> {
+        let env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
+        self.commit_env(env)
+    }
+
+    fn commit_env(&mut self, mut env: EnvWithHandlerCfg) -> Result<CallResult, EvmError> {
+        let result = self.backend.run_transact(&mut env)?;
+        let mut call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        self.commit(&env, &mut call_results)?;
+
+        Ok(call_results)
+    }
+
+    /// Same as `transact_call` but supports [alloy's sol types](https://docs.rs/alloy-sol-types/latest/alloy_sol_types/index.html).
+    pub fn transact_call_sol<T: SolCall>(
+        &mut self,
+        to: Address,
+        args: T,
+        value: U256,
+    ) -> Result<<T as SolCall>::Return> {
+        let (decoded, _) = self.transact_call_sol_raw(to, args, value)?;
+        decoded
+    }
+
+    /// Same as `transact_call_sol` but also returns the raw output of the call.
+    /// The call failing is an error, while failing to decode the output is
+    /// returned in the first element, so the raw bytes can still be inspected.
+    pub fn transact_call_sol_raw<T: SolCall>(
+        &mut self,
+        to: Address,
+        args: T,
+        value: U256,
+    ) -> Result<(Result<<T as SolCall>::Return>, Bytes)> {
+        let data = args.abi_encode();
+        let result = self.transact_call(to, data, value)?;
+        let decoded = T::abi_decode_returns(&result.result, true).map_err(|e| {
+            anyhow!(
+                "transact call sol error: {:?}. Raw output: 0x{}",
+                e,
+                hex::encode(&result.result)
+            )
+        });
+        Ok((decoded, result.result))
+    }
+
+    /// Read call to a contract.  Send a transaction but any state changes are NOT persisted to the
+    /// database.   The caller, and `tx.origin`, is `Address::ZERO`.
+    pub fn transact_call(&mut self, to: Address, data: Vec<u8>, value: U256) -> Result<CallResult> {
+        self.transact_call_with_gas_limit(to, data, value, None)
+    }
+
+    /// Same as `transact_call`, but the call can use at most `gas_limit`, if set,
+    /// instead of the limit set with `set_gas_limit`.
+    pub fn transact_call_with_gas_limit(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        gas_limit: Option<u64>,
+    ) -> Result<CallResult> {
+        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        if let Some(limit) = gas_limit {
+            env.tx.gas_limit = limit;
+        }
+        Ok(self.call_env(env)?)
+    }
+
+    /// Same as `transact_call`, but returns the structured `EvmError` instead of an
+    /// `anyhow::Error`.  See `try_transact_commit`
+    pub fn try_transact_call(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<CallResult, EvmError> {
+        let env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        self.call_env(env)
+    }
+
+    fn call_env(&mut self, mut env: EnvWithHandlerCfg) -> Result<CallResult, EvmError> {
+        let result = self.backend.run_transact(&mut env)?;
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok(call_results)
+    }
+
+    /// Same as `transact_call`, but evaluated at the given `block_number` and `timestamp`.
+    /// The current block number and timestamp of the EVM are not changed.
+    pub fn transact_call_at(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        block_number: u64,
+        timestamp: u64,
+    ) -> Result<CallResult> {
+        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        env.block.number = U256::from(block_number);
+        env.block.timestamp = U256::from(timestamp);
+        let result = self.backend.run_transact(&mut env)?;
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok(call_results)
+    }
+
+    /// Same as `transact_call`, but also returns the transient storage (EIP-1153) writes
+    /// made during the call as `(contract address, key, value)`.  Transient storage is
+    /// discarded at the end of the call, so this is the only way to observe it.
+    pub fn transact_call_with_transient(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<(CallResult, Vec<TransientWrite>)> {
+        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        let (result, inspector) = self
+            .backend
+            .run_transact_with_inspector(&mut env, TransientStorageInspector::default())?;
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok((call_results, inspector.writes))
+    }
+
+    /// Same as `transact_commit`, but returns whether the transaction succeeded and the
+    /// state it changed, even if it reverted or halted.  For a failed transaction, the
+    /// changes are the storage writes made before it failed, which are not committed.
+    pub fn transact_commit_capture_reverted(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<(bool, StateChangeSet)> {
+        let mut env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
+        let (result, inspector) = self
+            .backend
+            .run_transact_with_inspector(&mut env, StorageWriteInspector::default())?;
+        if result.result.is_success() {
+            let mut call_results = process_call_result(result)?;
+            self.record_gas(&env, &call_results);
+            self.commit(&env, &mut call_results)?;
+            return Ok((true, call_results.state_changeset.unwrap_or_default()));
+        }
+
+        let mut changes = StateChangeSet::default();
+        for (address, slot, value) in inspector.writes {
+            let original = self.backend.storage_ref(address, slot)?;
+            let account = match changes.entry(address) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let info = self.backend.basic_ref(address)?.unwrap_or_default();
+                    let mut account = Account::from(info);
+                    account.status = AccountStatus::Touched;
+                    entry.insert(account)
+                }
+            };
+            account
+                .storage
+                .insert(slot, StorageSlot::new_changed(original, value));
+        }
+        Ok((false, changes))
+    }
+
+    /// Same as `transact_call`, but also returns how the gas was used by category of
+    /// opcode (storage, memory, calls, etc...).  See `GasBreakdown`.
+    pub fn transact_call_gas_breakdown(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<(CallResult, GasBreakdown)> {
+        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        let (result, inspector) = self
+            .backend
+            .run_transact_with_inspector(&mut env, GasBreakdownInspector::default())?;
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok((call_results, inspector.breakdown))
+    }
+
+    /// Same as `transact_call`, but also returns a trace of every opcode executed.
+    /// For a large transaction, the trace can use a lot of memory, see
+    /// `transact_call_trace_streaming`.
+    pub fn transact_call_trace(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+    ) -> Result<(CallResult, Vec<TraceStep>)> {
+        let mut steps = Vec::new();
+        let result =
+            self.transact_call_trace_streaming(to, data, value, |step| steps.push(step.clone()))?;
+        Ok((result, steps))
+    }
+
+    /// Same as `transact_call`, but calls `f` with each opcode executed, in order.
+    /// The steps are not kept, so they can be filtered or aggregated as they run.
+    pub fn transact_call_trace_streaming(
+        &mut self,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        f: impl FnMut(&TraceStep),
+    ) -> Result<CallResult> {
+        let mut env = self.build_env(None, TransactTo::call(to), data.into(), value);
+        let (result, _) = self
+            .backend
+            .run_transact_with_inspector(&mut env, TraceInspector { on_step: f })?;
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok(call_results)
+    }
+
+    /// Simulate a `transact_commit` without actually committing/changing state.
+    pub fn simulate(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
         value: U256,
     ) -> Result<CallResult> {
         let mut env = self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
         let result = self.backend.run_transact(&mut env)?;
-        process_call_result(result)
+        let call_results = process_call_result(result)?;
+        self.record_gas(&env, &call_results);
+        Ok(call_results)
+    }
+
+    /// Same as `simulate`, but the `caller` has a balance of `caller_balance` for the
+    /// call.  The caller's real balance is not changed.
+    pub fn simulate_with_balance(
+        &mut self,
+        caller: Address,
+        to: Address,
+        data: Vec<u8>,
+        value: U256,
+        caller_balance: U256,
+    ) -> Result<CallResult> {
+        let checkpoint = self.checkpoint();
+        let result = self
+            .set_balance(caller, caller_balance)
+            .and_then(|evm| evm.simulate(caller, to, data, value));
+        self.restore(checkpoint);
+        result
+    }
+
+    /// Commit a bundle of transactions, in order.  Each transaction is
+    /// `(caller, to, data, value)`.  The result of each transaction is returned in order.
+    ///
+    /// With `BundleMode::AllOrNothing`, if any transaction fails all the changes made by
+    /// the bundle are rolled back and the error is returned.  Note: callbacks registered
+    /// with `on_commit` have already been called for the transactions before the failure.
+    ///
+    /// With `BundleMode::BestEffort`, each transaction is committed independently and a
+    /// failed transaction is marked by an `Err` in the results.
+    pub fn transact_bundle(
+        &mut self,
+        txs: Vec<(Address, Address, Vec<u8>, U256)>,
+        mode: BundleMode,
+    ) -> Result<Vec<Result<CallResult>>> {
+        match mode {
+            BundleMode::BestEffort => Ok(txs
+                .into_iter()
+                .map(|(caller, to, data, value)| self.transact_commit(caller, to, data, value))
+                .collect()),
+            BundleMode::AllOrNothing => {
+                let checkpoint = self.checkpoint();
+                let mut results = Vec::new();
+                for (caller, to, data, value) in txs {
+                    match self.transact_commit(caller, to, data, value) {
+                        Ok(result) => results.push(Ok(result)),
+                        Err(e) => {
+                            self.restore(checkpoint);
+                            return Err(e);
+                        }
+                    }
+                }
+                Ok(results)
+            }
+        }
+    }
+
+    /// Apply the `pending` transactions, then simulate `my_tx` on top of the resulting
+    /// state.  Each transaction is `(caller, to, data, value)`.  All changes, including
+    /// those of the pending transactions, are discarded before returning.
+    ///
+    /// An error is returned if any pending transaction fails.
+    pub fn simulate_on_top(
+        &mut self,
+        pending: Vec<(Address, Address, Vec<u8>, U256)>,
+        my_tx: (Address, Address, Vec<u8>, U256),
+    ) -> Result<CallResult> {
+        let checkpoint = self.checkpoint();
+        let result = pending
+            .into_iter()
+            .try_for_each(|(caller, to, data, value)| {
+                let mut env =
+                    self.build_env(Some(caller), TransactTo::call(to), data.into(), value);
+                let result = self.backend.run_transact(&mut env)?;
+                let call_results = process_call_result(result)?;
+                if let Some(changes) = call_results.state_changeset {
+                    self.backend.commit(changes);
+                }
+                Ok(())
+            })
+            .and_then(|_| {
+                let (caller, to, data, value) = my_tx;
+                self.simulate(caller, to, data, value)
+            });
+        self.restore(checkpoint);
+        result
+    }
+
+    /// Run `n` independent scenarios from the current state, e.g. for a Monte Carlo
+    /// simulation.  `f` is called with the EVM and the index of the scenario, and all
+    /// the changes it makes, to the state, the environment, the gas report, and the
+    /// recorded transactions and logs, are discarded before the next scenario, so each
+    /// starts from the same state.  Returns the result of each scenario in order, or the
+    /// first error.
+    pub fn run_scenarios<R>(
+        &mut self,
+        n: usize,
+        f: impl Fn(&mut BaseEvm, usize) -> Result<R>,
+    ) -> Result<Vec<R>> {
+        let mut results = Vec::with_capacity(n);
+        for i in 0..n {
+            let checkpoint = self.checkpoint();
+            let result = f(self, i);
+            self.restore(checkpoint);
+            results.push(result?);
+        }
+        Ok(results)
     }
 
     /// Advance `block.number` and `block.timestamp`. Set `interval` to the
@@ -182,6 +1497,80 @@ impl BaseEvm {
         self.backend.update_block_info(interval);
     }
 
+    /// Return the current block number
+    pub fn block_number(&self) -> u64 {
+        self.backend.block_number
+    }
+
+    /// Return the current block timestamp
+    pub fn timestamp(&self) -> u64 {
+        self.backend.timestamp
+    }
+
+    /// The gas price paid by transactions: `min(tx.gas_price, block.basefee + tx.gas_priority_fee)`,
+    /// or `tx.gas_price` if no priority fee is set.  The fees are zero by default,
+    /// and can be set with `env_mut`.
+    pub fn effective_gas_price(&self) -> U256 {
+        self.env.effective_gas_price()
+    }
+
+    /// Describe the call `data` as a readable function call, e.g. `transfer(0xAbC.., 100)`,
+    /// using the functions in `abi`.  Returns `None` if the data doesn't decode as a
+    /// call to one of them.  Useful for logging with `on_commit`.
+    pub fn describe_call(&self, abi: &ContractAbi, data: &[u8]) -> Option<String> {
+        let (name, args) = abi.decode_calldata(data).ok()?;
+        let args = args.iter().map(format_value).collect::<Vec<_>>();
+        Some(format!("{}({})", name, args.join(", ")))
+    }
+
+    /// Save the block environment: number, timestamp, basefee, coinbase and chain id.
+    /// See `restore_block_env`
+    pub fn save_block_env(&self) -> BlockEnvState {
+        BlockEnvState {
+            number: self.backend.block_number,
+            timestamp: self.backend.timestamp,
+            basefee: self.env.block.basefee,
+            coinbase: self.env.block.coinbase,
+            chain_id: self.env.cfg.chain_id,
+        }
+    }
+
+    /// Restore a block environment saved with `save_block_env`.  State is not changed.
+    pub fn restore_block_env(&mut self, state: BlockEnvState) {
+        self.backend.block_number = state.number;
+        self.backend.timestamp = state.timestamp;
+        self.env.block.basefee = state.basefee;
+        self.env.block.coinbase = state.coinbase;
+        self.env.cfg.chain_id = state.chain_id;
+    }
+
+    /// Set the block environment from the header of the forked block: the number,
+    /// timestamp, basefee, coinbase, prevrandao, gas limit and excess blob gas.
+    /// The prevrandao is kept if the header has none.  Unless a gas price is set,
+    /// see `set_fee_params`, the basefee check is disabled so transactions without
+    /// fees still run.  Returns an error if not forking.
+    pub fn sync_block_env_from_fork(&mut self) -> Result<()> {
+        let blk = match self.backend.fork_block() {
+            Some(blk) => blk?,
+            _ => bail!("sync_block_env_from_fork requires a fork"),
+        };
+        self.backend.block_number = blk.number;
+        self.backend.timestamp = blk.timestamp;
+        self.env.block.basefee = blk.base_fee.unwrap_or_default();
+        self.env.block.coinbase = blk.coinbase;
+        if blk.prevrandao.is_some() {
+            self.env.block.prevrandao = blk.prevrandao;
+        }
+        self.env.block.gas_limit = U256::from(blk.gas_limit);
+        self.env.cfg.disable_base_fee = self.env.tx.gas_price.is_zero();
+        if let Some(excess_blob_gas) = blk.excess_blob_gas {
+            self.env
+                .block
+                .set_blob_excess_gas_and_price(excess_blob_gas);
+        }
+        Ok(())
+    }
+
     fn build_env(
         &self,
         caller: Option<Address>,
@@ -192,10 +1581,20 @@ impl BaseEvm {
         let blkn = self.backend.block_number;
         let ts = self.backend.timestamp;
 
+        // read-only calls (no caller) don't pay for gas
+        let (basefee, gas_price, gas_priority_fee) = match caller {
+            Some(_) => (
+                self.env.block.basefee,
+                self.effective_gas_price(),
+                self.env.tx.gas_priority_fee,
+            ),
+            _ => (U256::ZERO, U256::ZERO, None),
+        };
+
         let env = Env {
             cfg: self.env.cfg.clone(),
             block: BlockEnv {
-                basefee: U256::ZERO,
+                basefee,
                 timestamp: U256::from(ts),
                 number: U256::from(blkn),
                 ..self.env.block.clone()
@@ -205,8 +1604,11 @@ impl BaseEvm {
                 transact_to,
                 data,
                 value,
-                gas_price: U256::ZERO,
-                gas_priority_fee: None,
+                gas_price,
+                gas_priority_fee,
+                gas_limit: self
+                    .gas_limit
+                    .unwrap_or_else(|| self.affordable_gas_limit(caller, value, gas_price)),
                 ..self.env.tx.clone()
             },
         };
@@ -214,14 +1616,235 @@ impl BaseEvm {
         EnvWithHandlerCfg::new_with_spec_id(Box::new(env), self.env.handler_cfg.spec_id)
     }
 
-    fn commit(&mut self, result: &mut CallResult) {
+    // the default gas limit can't be more than the block gas limit
+    fn default_gas_limit(&self) -> u64 {
+        let block_limit = u64::try_from(self.env.block.gas_limit).unwrap_or(u64::MAX);
+        self.env.tx.gas_limit.min(block_limit)
+    }
+
+    // with a gas price, cap the default gas limit to what the caller can pay for,
+    // otherwise the max fee of the (very large) default exceeds any balance
+    fn affordable_gas_limit(&self, caller: Option<Address>, value: U256, gas_price: U256) -> u64 {
+        let limit = self.default_gas_limit();
+        match caller {
+            Some(caller) if !gas_price.is_zero() => {
+                let balance = self
+                    .backend
+                    .basic_ref(caller)
+                    .ok()
+                    .flatten()
+                    .map(|info| info.balance)
+                    .unwrap_or_default();
+                let allowance = balance.saturating_sub(value) / gas_price;
+                u64::try_from(allowance).unwrap_or(u64::MAX).min(limit)
+            }
+            _ => limit,
+        }
+    }
+
+    // save the mutable state of the EVM, to be restored with `restore`.  Callbacks
+    // aren't saved.
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            backend: self.backend.clone(),
+            env: self.env.clone(),
+            gas_report: self.gas_report.clone(),
+            recorded: self.recorded.clone(),
+            captured_logs: self.captured_logs.clone(),
+            auto_nonce: self.auto_nonce,
+            block_logs: self.block_logs,
+            gas_limit: self.gas_limit,
+        }
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        self.backend = checkpoint.backend;
+        self.env = checkpoint.env;
+        self.gas_report = checkpoint.gas_report;
+        self.recorded = checkpoint.recorded;
+        self.captured_logs = checkpoint.captured_logs;
+        self.auto_nonce = checkpoint.auto_nonce;
+        self.block_logs = checkpoint.block_logs;
+        self.gas_limit = checkpoint.gas_limit;
+    }
+
+    fn record_gas(&mut self, env: &EnvWithHandlerCfg, result: &CallResult) {
+        if let Some(report) = self.gas_report.as_mut() {
+            report.record(&env.tx.data, result.gas_used);
+        }
+    }
+
+    fn caller_nonce(&self, caller: Address) -> Result<u64> {
+        Ok(self
+            .backend
+            .basic_ref(caller)?
+            .map(|info| info.nonce)
+            .unwrap_or_default())
+    }
+
+    fn commit(&mut self, env: &EnvWithHandlerCfg, result: &mut CallResult) -> Result<()> {
+        if let Some(recorded) = self.recorded.as_mut() {
+            let nonce = self
+                .backend
+                .basic_ref(env.tx.caller)?
+                .map(|info| info.nonce)
+                .unwrap_or_default();
+            recorded.push(RecordedTx {
+                caller: env.tx.caller,
+                to: match env.tx.transact_to {
+                    TransactTo::Call(to) => Some(to),
+                    _ => None,
+                },
+                data: env.tx.data.clone(),
+                salt: match env.tx.transact_to {
+                    TransactTo::Create(CreateScheme::Create2 { salt }) => Some(salt.into()),
+                    _ => None,
+                },
+                value: env.tx.value,
+                nonce,
+                block_number: self.backend.block_number,
+                timestamp: self.backend.timestamp,
+            });
+        }
+        if self.block_logs.0 != self.backend.block_number {
+            self.block_logs = (self.backend.block_number, 0);
+        }
+        result.log_index_base = self.block_logs.1;
+        self.block_logs.1 += result.logs.len();
+        if let Some(logs) = self.captured_logs.as_mut() {
+            let block_number = self.backend.block_number;
+            logs.extend(
+                result
+                    .logs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, log)| (block_number, result.log_index_base + i, log.clone())),
+            );
+        }
         if let Some(changes) = &result.state_changeset {
-            self.backend.commit(changes.clone());
+            let mut changes = changes.clone();
+            if !self.auto_nonce {
+                let caller = env.tx.caller;
+                if let (Some(account), Ok(Some(info))) =
+                    (changes.get_mut(&caller), self.backend.basic_ref(caller))
+                {
+                    account.info.nonce = info.nonce;
+                }
+            }
+            for (address, slot, f) in self.slot_watchers.iter_mut() {
+                if let Some(value) = changes
+                    .get(address)
+                    .and_then(|account| account.storage.get(slot))
+                    .filter(|value| value.is_changed())
+                {
+                    f(value.original_value(), value.present_value());
+                }
+            }
+            self.backend.commit(changes);
+        }
+        if let Some(f) = self.on_commit.as_mut() {
+            f(&CommitInfo {
+                caller: env.tx.caller,
+                to: match env.tx.transact_to {
+                    TransactTo::Call(to) => Some(to),
+                    _ => result.address,
+                },
+                gas_used: result.gas_used,
+            });
         }
+        Ok(())
     }
 }
 
+/// How `BaseEvm::transact_bundle` handles a failed transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BundleMode {
+    /// Roll back the whole bundle if any transaction fails
+    AllOrNothing,
+    /// Commit each successful transaction, and report the failures
+    BestEffort,
+}
+
+/// The block environment saved by `BaseEvm::save_block_env`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockEnvState {
+    /// The block number
+    pub number: u64,
+    /// The block timestamp
+    pub timestamp: u64,
+    /// The block basefee
+    pub basefee: U256,
+    /// The block beneficiary
+    pub coinbase: Address,
+    /// The chain id
+    pub chain_id: u64,
+}
+
+/// The result of `BaseEvm::state_eq`: `Equal`, or the first difference found.
+/// `left` is the value in the EVM `state_eq` was called on, `right` the value in `other`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateEqResult {
+    Equal,
+    Balance {
+        address: Address,
+        left: U256,
+        right: U256,
+    },
+    Nonce {
+        address: Address,
+        left: u64,
+        right: u64,
+    },
+    /// the hashes of the code differ
+    Code {
+        address: Address,
+        left: B256,
+        right: B256,
+    },
+    Storage {
+        address: Address,
+        slot: U256,
+        left: U256,
+        right: U256,
+    },
+}
+
+/// Information about a committed transaction.  See `BaseEvm::on_commit`.  Failed
+/// transactions are not committed, so only successful transactions are reported.
+#[derive(Clone, Debug)]
+pub struct CommitInfo {
+    /// The sender of the transaction
+    pub caller: Address,
+    /// The recipient of the transaction, or the address of a deployed contract
+    pub to: Option<Address>,
+    /// The gas used by the transaction
+    pub gas_used: u64,
+}
+
+/// The result of `BaseEvm::transact_commit_sol_full`
+pub struct SolCallResult<T: SolCall> {
+    /// The decoded return value
+    pub value: T::Return,
+    /// The gas used by the transaction
+    pub gas_used: u64,
+    /// The logs emitted by the transaction
+    pub logs: Vec<Log>,
+    /// The events decoded from the logs
+    pub decoded_events: Vec<DecodedEvent>,
+}
+
+/// Information about a deployed contract
+pub struct DeployInfo {
+    /// The address of the contract
+    pub address: Address,
+    /// The runtime bytecode returned by the constructor
+    pub runtime_code: Bytes,
+    /// The gas used to deploy the contract
+    pub gas_used: u64,
+}
+
 /// Container for the results of a transaction
+#[derive(Debug)]
 pub struct CallResult {
     /// The raw result of the call.
     pub result: Bytes,
@@ -233,11 +1856,99 @@ pub struct CallResult {
     pub gas_refunded: u64,
     /// The logs emitted during the call
     pub logs: Vec<Log>,
+    /// The index within the block of the first log, for a committed transaction.  The
+    /// index of `logs[i]` is `log_index_base + i`
+    pub log_index_base: usize,
     /// Changes made to the database
     pub state_changeset: Option<StateChangeSet>,
 }
 
-fn process_call_result(result: ResultAndState) -> Result<CallResult> {
+impl CallResult {
+    /// Summarize the changes made by the call to each account it touched, ordered by
+    /// address.  Only storage slots whose value changed are included.
+    pub fn changed_accounts(&self) -> Vec<AccountChange> {
+        let changes = match self.state_changeset.as_ref() {
+            Some(changes) => changes,
+            _ => return Vec::new(),
+        };
+        let mut accounts = changes
+            .iter()
+            .filter(|(_, account)| account.is_touched())
+            .map(|(address, account)| AccountChange {
+                address: *address,
+                balance: account.info.balance,
+                nonce: account.info.nonce,
+                code: if account.is_created() {
+                    account.info.code.as_ref().map(|c| c.original_bytes())
+                } else {
+                    None
+                },
+                destroyed: account.is_selfdestructed(),
+                storage: account
+                    .storage
+                    .iter()
+                    .filter(|(_, slot)| slot.is_changed())
+                    .map(|(k, slot)| (*k, (slot.original_value(), slot.present_value())))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        accounts.sort_by_key(|a| a.address);
+        accounts
+    }
+}
+
+/// The changes made to a single account by a call.  See `CallResult::changed_accounts`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountChange {
+    /// The address of the account
+    pub address: Address,
+    /// The balance after the call
+    pub balance: U256,
+    /// The nonce after the call
+    pub nonce: u64,
+    /// The code, if the account was created by the call
+    pub code: Option<Bytes>,
+    /// Whether the account self-destructed
+    pub destroyed: bool,
+    /// The changed storage slots: `slot => (original value, new value)`
+    pub storage: BTreeMap<U256, (U256, U256)>,
+}
+
+// hex encode without leading zeros, as JSON-RPC does, e.g. "0x0" or "0x2a"
+fn hex_quantity(value: U256) -> String {
+    let hex = hex::encode(value.to_be_bytes_trimmed_vec());
+    match hex.trim_start_matches('0') {
+        "" => "0x0".to_string(),
+        digits => format!("0x{}", digits),
+    }
+}
+
+// format a decoded value as it would be written in Solidity
+fn format_value(value: &DynSolValue) -> String {
+    let join = |values: &[DynSolValue]| {
+        values
+            .iter()
+            .map(format_value)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match value {
+        DynSolValue::Bool(b) => b.to_string(),
+        DynSolValue::Int(i, _) => i.to_string(),
+        DynSolValue::Uint(u, _) => u.to_string(),
+        DynSolValue::FixedBytes(b, size) => format!("0x{}", hex::encode(&b[..*size])),
+        DynSolValue::Address(a) => a.to_string(),
+        DynSolValue::Function(f) => format!("0x{}", hex::encode(f)),
+        DynSolValue::Bytes(b) => format!("0x{}", hex::encode(b)),
+        DynSolValue::String(s) => format!("{:?}", s),
+        DynSolValue::Array(values) | DynSolValue::FixedArray(values) => {
+            format!("[{}]", join(values))
+        }
+        DynSolValue::Tuple(values) => format!("({})", join(values)),
+    }
+}
+
+fn process_call_result(result: ResultAndState) -> Result<CallResult, EvmError> {
     let ResultAndState {
         result: exec_result,
         state: state_changeset,
@@ -251,12 +1962,15 @@ fn process_call_result(result: ResultAndState) -> Result<CallResult> {
             logs,
             ..
         } => (gas_refunded, gas_used, output, logs),
-        ExecutionResult::Revert { gas_used, output } => match decode_revert_reason(&output) {
-            Some(reason) => bail!("Reverted: {:?}. Gas used: {:?}", reason, gas_used),
-            _ => bail!("Reverted with no reason. Gas used: {:?}", gas_used),
-        },
+        ExecutionResult::Revert { gas_used, output } => {
+            return Err(EvmError::Revert {
+                reason: decode_revert_reason(&output),
+                output,
+                gas_used,
+            })
+        }
         ExecutionResult::Halt { reason, gas_used } => {
-            bail!("Halted: {:?}. Gas used: {:?}", reason, gas_used)
+            return Err(EvmError::Halt { reason, gas_used })
         }
     };
 
@@ -267,6 +1981,7 @@ fn process_call_result(result: ResultAndState) -> Result<CallResult> {
             gas_refunded,
             logs,
             address: None,
+            log_index_base: 0,
             state_changeset: Some(state_changeset),
         }),
         Output::Create(data, address) => Ok(CallResult {
@@ -275,6 +1990,7 @@ fn process_call_result(result: ResultAndState) -> Result<CallResult> {
             gas_used,
             logs,
             gas_refunded,
+            log_index_base: 0,
             state_changeset: Some(state_changeset),
         }),
     }
@@ -282,12 +1998,31 @@ fn process_call_result(result: ResultAndState) -> Result<CallResult> {
 
 #[cfg(test)]
 mod tests {
-    use crate::ContractAbi;
-    use crate::{generate_random_addresses, BaseEvm};
+    use crate::db::{
+        fork_backend::mock_rpc::MockRpc, fork_source::mock::MockForkSource, BlockTag, ForkSource,
+        ForkTransaction, HttpProvider,
+    };
+    use crate::errors::{EvmError, TransferError};
+    use crate::gas::GasComparison;
+    use crate::recorder::replay;
+    use crate::{db::CreateFork, ContractAbi};
+    use crate::{
+        evm::{BundleMode, StateEqResult},
+        generate_random_addresses, BaseEvm,
+    };
     use alloy_dyn_abi::DynSolValue;
-    use alloy_primitives::{Address, U256};
-    use alloy_sol_types::{sol, SolConstructor};
+    use alloy_primitives::{keccak256, Address, B256, I256, U256};
+    use alloy_sol_types::{sol, SolCall, SolConstructor, SolEvent};
+    use revm::{
+        interpreter::opcode,
+        primitives::{calc_blob_gasprice, AccountInfo, Bytecode, HaltReason, SpecId},
+        DatabaseRef,
+    };
     use rstest::*;
+    use std::{
+        collections::{BTreeMap, BTreeSet, HashMap},
+        sync::{Arc, Mutex},
+    };
 
     sol! {
         struct ChangeIt {
@@ -314,9 +2049,26 @@ mod tests {
         }
     }
 
-    sol! {
-        contract BlockMeta {
-            function getMeta() external view returns (uint, uint);
+    // An EVM with a funded owner, `Address::repeat_byte(12)`, and a `TestContract`
+    // deployed by the owner with a value of 1: `(evm, owner, contract address)`
+    #[fixture]
+    fn deployed_contract(contract_bytecode: Vec<u8>) -> (BaseEvm, Address, Address) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        (evm, owner, addr)
+    }
+
+    sol! {
+        contract BlockMeta {
+            function getMeta() external view returns (uint, uint);
         }
     }
 
@@ -679,4 +2431,2815 @@ mod tests {
         assert_eq!(expected_block, tx3._1);
         assert_eq!(expected_time, tx3._0);
     }
+
+    #[test]
+    fn compare_gas_across_spec_ids() {
+        let owner = Address::repeat_byte(12);
+        // runtime: return the value of slot 0.  No PUSH0, so it runs before shanghai
+        let initcode = hex::decode("61000b8061000d6000396000f360005460005260206000f3").unwrap();
+        let data = vec![1, 2, 3, 4];
+
+        let mut istanbul = BaseEvm::default();
+        istanbul.set_spec_id(SpecId::ISTANBUL);
+        let mut berlin = BaseEvm::default();
+        berlin.set_spec_id(SpecId::BERLIN);
+
+        let comparison = GasComparison::run(&mut istanbul, &mut berlin, |evm| {
+            evm.create_account(owner, Some(U256::from(1e18)))?;
+            let addr = evm.deploy(owner, initcode.clone(), U256::from(0))?;
+            for _ in 0..3 {
+                evm.transact_commit(owner, addr, data.clone(), U256::from(0))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(1, comparison.deltas.len());
+        let stats = comparison.get(&[1, 2, 3, 4]).unwrap();
+        let left = stats.left.as_ref().unwrap();
+        let right = stats.right.as_ref().unwrap();
+        assert_eq!(3, left.calls);
+        assert_eq!(3, right.calls);
+        // 21000 + 64 for the calldata + 18 for the opcodes, and the SLOAD: 800 in
+        // istanbul, 2100 for a cold slot in berlin (EIP-2929)
+        assert_eq!(21_882, left.avg());
+        assert_eq!(23_182, right.avg());
+        assert_eq!(1_300, stats.delta());
+    }
+
+    #[rstest]
+    fn deploy_returns_runtime_code(meta_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        let info = evm
+            .deploy_with_info(owner, meta_bytecode.clone(), U256::from(0))
+            .unwrap();
+
+        assert!(!info.runtime_code.is_empty());
+        assert!(info.gas_used > 0);
+        // the runtime code is a suffix of the creation code
+        assert!(meta_bytecode.ends_with(&info.runtime_code));
+        assert_eq!(info.runtime_code, evm.get_code(info.address).unwrap());
+        assert!(evm.get_code(owner).unwrap().is_empty());
+    }
+
+    #[rstest]
+    fn send_value_to_accounts_and_contracts(contract_bytecode: Vec<u8>) {
+        let one_eth = U256::from(1e18);
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(10e18))).unwrap();
+
+        // EOA: plain transfer
+        evm.send_value_and_call(owner, bob, vec![], one_eth)
+            .unwrap();
+        assert_eq!(one_eth, evm.get_balance(bob).unwrap());
+
+        // contract that accepts any value. runtime code: STOP
+        let receiver_code = hex::decode("6100018061000d6000396000f300").unwrap();
+        let receiver = evm.deploy(owner, receiver_code, U256::from(0)).unwrap();
+        evm.send_value_and_call(owner, receiver, vec![], one_eth)
+            .unwrap();
+        assert_eq!(one_eth, evm.get_balance(receiver).unwrap());
+
+        // TestContract has no receive or fallback function
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let no_receive = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let err = evm
+            .send_value_and_call(owner, no_receive, vec![], one_eth)
+            .unwrap_err();
+        assert!(err.to_string().contains("no payable receive or fallback"));
+        assert_eq!(U256::from(0), evm.get_balance(no_receive).unwrap());
+
+        // ... but value can be sent with a call to a payable function
+        evm.send_value_and_call(
+            owner,
+            no_receive,
+            TestContract::depositCall {}.abi_encode(),
+            one_eth,
+        )
+        .unwrap();
+        assert_eq!(one_eth, evm.get_balance(no_receive).unwrap());
+    }
+
+    #[test]
+    fn send_value_to_reverting_receive() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(10e18))).unwrap();
+
+        // runtime code: always revert with `Error("nope")`
+        let initcode = hex::decode(
+            "6100238061000d6000396000f3\
+             6308c379a060e01b5f5260206004526004602452636e6f706560e01b60445260645ffd",
+        )
+        .unwrap();
+        let receiver = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        let err = evm
+            .send_value_and_call(owner, receiver, vec![], U256::from(1e18))
+            .unwrap_err();
+        match err.downcast_ref::<EvmError>() {
+            Some(EvmError::Revert { reason, .. }) => {
+                assert!(reason.as_ref().unwrap().contains("nope"))
+            }
+            _ => panic!("expected a revert, got: {err}"),
+        }
+    }
+
+    #[test]
+    fn try_transfer_to_reverting_receive() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(10e18))).unwrap();
+
+        // runtime code: always revert with `Error("nope")`
+        let initcode = hex::decode(
+            "6100238061000d6000396000f3\
+             6308c379a060e01b5f5260206004526004602452636e6f706560e01b60445260645ffd",
+        )
+        .unwrap();
+        let receiver = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        let err = evm
+            .try_transfer(owner, receiver, U256::from(1e18))
+            .unwrap_err();
+        assert!(err.downcast_ref::<TransferError>().is_none());
+        match err.downcast_ref::<EvmError>() {
+            Some(EvmError::Revert { reason, .. }) => {
+                assert!(reason.as_ref().unwrap().contains("nope"))
+            }
+            _ => panic!("expected a revert, got: {err}"),
+        }
+        assert_eq!(U256::ZERO, evm.get_balance(receiver).unwrap());
+    }
+
+    #[rstest]
+    fn try_transfer_typed_errors(contract_bytecode: Vec<u8>) {
+        let one_eth = U256::from(1e18);
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(10e18))).unwrap();
+
+        // EOA
+        assert!(!evm.try_transfer(owner, bob, one_eth).unwrap());
+        assert_eq!(one_eth, evm.get_balance(bob).unwrap());
+
+        // contract that accepts any value. runtime code: STOP
+        let receiver_code = hex::decode("6100018061000d6000396000f300").unwrap();
+        let receiver = evm.deploy(owner, receiver_code, U256::from(0)).unwrap();
+        assert!(evm.try_transfer(owner, receiver, one_eth).unwrap());
+        assert_eq!(one_eth, evm.get_balance(receiver).unwrap());
+
+        // TestContract has no receive or fallback function
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let no_receive = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let err = evm.try_transfer(owner, no_receive, one_eth).unwrap_err();
+        assert_eq!(
+            Some(&TransferError::NoReceiveFunction(no_receive)),
+            err.downcast_ref::<TransferError>()
+        );
+        assert!(evm.transfer(owner, no_receive, one_eth).is_err());
+        assert_eq!(U256::from(0), evm.get_balance(no_receive).unwrap());
+
+        // underfunded
+        let err = evm.try_transfer(bob, owner, U256::from(2e18)).unwrap_err();
+        assert_eq!(
+            Some(&TransferError::InsufficientBalance {
+                from: bob,
+                balance: one_eth,
+                value: U256::from(2e18),
+            }),
+            err.downcast_ref::<TransferError>()
+        );
+        assert_eq!(one_eth, evm.get_balance(bob).unwrap());
+    }
+
+    #[rstest]
+    fn gas_limit_halts_out_of_gas(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::ZERO)
+            .unwrap();
+        let data = TestContract::increment_0Call {}.abi_encode();
+
+        // enough for the intrinsic gas, but not the storage write
+        let err = evm
+            .transact_commit_with_gas_limit(owner, addr, data.clone(), U256::ZERO, Some(25_000))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EvmError>(),
+            Some(EvmError::Halt {
+                reason: HaltReason::OutOfGas(_),
+                ..
+            })
+        ));
+        assert!(evm
+            .transact_call_with_gas_limit(addr, data.clone(), U256::ZERO, Some(25_000))
+            .is_err());
+
+        // the default limit is unchanged
+        evm.transact_commit(owner, addr, data.clone(), U256::ZERO)
+            .unwrap();
+
+        evm.set_gas_limit(25_000);
+        assert!(evm
+            .transact_commit(owner, addr, data.clone(), U256::ZERO)
+            .is_err());
+        // a per call limit takes precedence
+        evm.transact_commit_with_gas_limit(owner, addr, data, U256::ZERO, Some(100_000))
+            .unwrap();
+    }
+
+    #[rstest]
+    fn compare_the_state_of_two_evms(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let bytecode = [contract_bytecode, args].concat();
+
+        let run = || {
+            let mut evm = BaseEvm::default();
+            evm.create_account(owner, Some(U256::from(10e18))).unwrap();
+            let addr = evm.deploy(owner, bytecode.clone(), U256::ZERO).unwrap();
+            evm.transact_commit(
+                owner,
+                addr,
+                TestContract::increment_0Call {}.abi_encode(),
+                U256::ZERO,
+            )
+            .unwrap();
+            evm.transfer(owner, bob, U256::from(1e18)).unwrap();
+            (evm, addr)
+        };
+        let (mut a, addr) = run();
+        let (b, _) = run();
+        assert_eq!(StateEqResult::Equal, a.state_eq(&b));
+
+        a.transact_commit(
+            owner,
+            addr,
+            TestContract::increment_0Call {}.abi_encode(),
+            U256::ZERO,
+        )
+        .unwrap();
+        // the owner's nonce is the first difference
+        assert_eq!(
+            StateEqResult::Nonce {
+                address: owner,
+                left: 4,
+                right: 3,
+            },
+            a.state_eq(&b)
+        );
+
+        let (mut a, _) = run();
+        a.create_account(bob, Some(U256::from(5))).unwrap();
+        assert_eq!(
+            StateEqResult::Balance {
+                address: bob,
+                left: U256::from(5),
+                right: U256::from(1e18),
+            },
+            a.state_eq(&b)
+        );
+
+        let (mut a, _) = run();
+        a.backend
+            .insert_account_storage(addr, U256::from(1), U256::from(7))
+            .unwrap();
+        assert_eq!(
+            StateEqResult::Storage {
+                address: addr,
+                slot: U256::from(1),
+                left: U256::from(7),
+                right: U256::from(2),
+            },
+            a.state_eq(&b)
+        );
+    }
+
+    #[rstest]
+    fn read_a_storage_slot(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::ZERO)
+            .unwrap();
+
+        evm.transact_commit_sol(
+            owner,
+            addr,
+            TestContract::increment_1Call {
+                _input: U256::from(5),
+            },
+            U256::ZERO,
+        )
+        .unwrap();
+        let value = evm
+            .transact_call_sol(addr, TestContract::valueCall {}, U256::ZERO)
+            .unwrap()
+            ._0;
+        assert_eq!(U256::from(6), value);
+
+        // owner is in slot 0, value in slot 1
+        assert_eq!(value, evm.get_storage_slot(addr, U256::from(1)).unwrap());
+        assert_eq!(
+            owner.into_word(),
+            B256::from(evm.get_storage_slot(addr, U256::ZERO).unwrap())
+        );
+        assert_eq!(
+            U256::ZERO,
+            evm.get_storage_slot(addr, U256::from(2)).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn record_and_replay(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let initial = evm.create_snapshot().unwrap();
+
+        evm.enable_recording();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        evm.update_block(12);
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        // reads are not recorded
+        evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+            .unwrap();
+
+        let txs = evm.recorded_transactions();
+        assert_eq!(2, txs.len());
+        assert_eq!(None, txs[0].to);
+        assert_eq!(Some(addr), txs[1].to);
+        assert_eq!(txs[0].block_number + 1, txs[1].block_number);
+
+        let mut evm2 = BaseEvm::new_from_snapshot(initial);
+        replay(&txs, &mut evm2).unwrap();
+
+        assert_eq!(
+            serde_json::to_string(&evm.create_snapshot().unwrap()).unwrap(),
+            serde_json::to_string(&evm2.create_snapshot().unwrap()).unwrap()
+        );
+        assert_eq!(
+            U256::from(2),
+            evm2.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+    }
+
+    #[rstest]
+    fn batch_read_storage_slots(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(7),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let slots = (0..5).map(U256::from).collect::<Vec<_>>();
+        let values = evm.get_storage_slots(addr, &slots).unwrap();
+        let expected = slots
+            .iter()
+            .map(|slot| evm.backend.storage_ref(addr, *slot).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, values);
+        assert_eq!(U256::from_be_slice(owner.as_slice()), values[0]);
+        assert_eq!(U256::from(7), values[1]);
+        assert_eq!(U256::from(0), values[4]);
+    }
+
+    #[test]
+    fn deploy_revert_returns_reason() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // constructor that reverts with `require(false, "bad init")`
+        let bytecode = hex::decode(
+            "7f08c379a0000000000000000000000000000000000000000000000000000000\
+            00600052602060045260086024527f62616420696e6974000000000000000000\
+            00000000000000000000000000000060445260646000fd",
+        )
+        .unwrap();
+
+        let err = evm.deploy(owner, bytecode, U256::from(0)).unwrap_err();
+        assert!(err.to_string().contains("bad init"));
+    }
+
+    #[rstest]
+    fn save_and_load_snapshot_file(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(3),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot.json");
+        evm.save_snapshot(&path).unwrap();
+
+        let mut evm2 = BaseEvm::load_snapshot_file(&path).unwrap();
+        assert_eq!(
+            serde_json::to_string(&evm.create_snapshot().unwrap()).unwrap(),
+            serde_json::to_string(&evm2.create_snapshot().unwrap()).unwrap()
+        );
+        assert_eq!(
+            U256::from(3),
+            evm2.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+
+        let missing = BaseEvm::load_snapshot_file(&dir.path().join("missing.json"));
+        assert!(missing.is_err_and(|e| e.to_string().contains("failed to read snapshot file")));
+    }
+
+    #[rstest]
+    fn commit_without_auto_nonce(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let nonce = evm.backend.basic_ref(owner).unwrap().unwrap().nonce;
+        assert_eq!(1, nonce);
+
+        evm.set_auto_nonce(false);
+        for _ in 0..2 {
+            evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+                .unwrap();
+        }
+        assert_eq!(nonce, evm.backend.basic_ref(owner).unwrap().unwrap().nonce);
+        assert_eq!(
+            U256::from(3),
+            evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+
+        evm.set_auto_nonce(true);
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        assert_eq!(
+            nonce + 1,
+            evm.backend.basic_ref(owner).unwrap().unwrap().nonce
+        );
+    }
+
+    #[test]
+    fn block_number_and_timestamp_getters() {
+        let mut evm = BaseEvm::default();
+        let start = evm.timestamp();
+        assert_eq!(1, evm.block_number());
+
+        evm.update_block(12);
+        evm.update_block(10);
+
+        assert_eq!(3, evm.block_number());
+        assert_eq!(start + 22, evm.timestamp());
+    }
+
+    #[rstest]
+    fn load_genesis_alloc(meta_bytecode: Vec<u8>) {
+        // deploy to get the runtime code of BlockMeta
+        let owner = Address::repeat_byte(12);
+        let mut deployer = BaseEvm::default();
+        deployer
+            .create_account(owner, Some(U256::from(1e18)))
+            .unwrap();
+        let info = deployer
+            .deploy_with_info(owner, meta_bytecode, U256::from(0))
+            .unwrap();
+
+        let genesis = format!(
+            r#"{{
+                "config": {{ "chainId": 1337 }},
+                "alloc": {{
+                    "0x0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c0c": {{ "balance": "1000000000000000000" }},
+                    "0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d0d": {{ "balance": "0x10", "nonce": "0x2" }},
+                    "0x1111111111111111111111111111111111111111": {{
+                        "balance": "0x0",
+                        "code": "0x{}",
+                        "storage": {{ "0x01": "0x05" }}
+                    }}
+                }}
+            }}"#,
+            hex::encode(&info.runtime_code)
+        );
+
+        let mut evm = BaseEvm::from_genesis_alloc(&genesis).unwrap();
+        let meta = Address::repeat_byte(0x11);
+
+        assert_eq!(U256::from(1e18), evm.get_balance(owner).unwrap());
+        assert_eq!(
+            U256::from(16),
+            evm.get_balance(Address::repeat_byte(0x0d)).unwrap()
+        );
+        assert_eq!(
+            2,
+            evm.backend
+                .basic_ref(Address::repeat_byte(0x0d))
+                .unwrap()
+                .unwrap()
+                .nonce
+        );
+        assert_eq!(info.runtime_code, evm.get_code(meta).unwrap());
+        assert_eq!(
+            vec![U256::from(5)],
+            evm.get_storage_slots(meta, &[U256::from(1)]).unwrap()
+        );
+        assert_eq!(1, evm.block_number());
+
+        let tx = evm
+            .transact_call_sol(meta, BlockMeta::getMetaCall {}, U256::from(0))
+            .unwrap();
+        assert_eq!(U256::from(1), tx._1);
+
+        assert!(BaseEvm::from_genesis_alloc(r#"{ "nope": {} }"#).is_err());
+    }
+
+    #[rstest]
+    fn reset_local_changes_keeps_fork_cache(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let contract = Address::repeat_byte(13);
+
+        // get the runtime code to serve from the mock fork
+        let mut mem = BaseEvm::default();
+        mem.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let info = mem
+            .deploy_with_info(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .accounts
+            .insert(owner, AccountInfo::from_balance(U256::from(1e18)));
+        let code = Bytecode::new_raw(info.runtime_code);
+        source.accounts.insert(
+            contract,
+            AccountInfo::new(U256::ZERO, 1, code.hash_slow(), code),
+        );
+        source
+            .storage
+            .insert((contract, U256::from(1)), U256::from(5));
+        let source = Arc::new(source);
+
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(source.clone(), None)));
+        evm.transact_commit_sol(
+            owner,
+            contract,
+            TestContract::increment_0Call {},
+            U256::from(0),
+        )
+        .unwrap();
+        assert_eq!(
+            U256::from(6),
+            evm.backend.storage_ref(contract, U256::from(1)).unwrap()
+        );
+        let requests = source.storage_requests();
+
+        evm.reset_local_changes();
+        assert_eq!(
+            U256::from(5),
+            evm.backend.storage_ref(contract, U256::from(1)).unwrap()
+        );
+        assert_eq!(requests, source.storage_requests());
+    }
+
+    #[test]
+    fn reset_local_changes_discards_local_writes() {
+        let alice = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+
+        let mut evm = BaseEvm::default();
+        evm.set_balance(alice, U256::from(100)).unwrap();
+        evm.set_storage_slot(bob, U256::from(1), U256::from(7))
+            .unwrap();
+
+        evm.reset_local_changes();
+        assert_eq!(U256::ZERO, evm.get_balance(alice).unwrap());
+        assert_eq!(
+            U256::ZERO,
+            evm.get_storage_slot(bob, U256::from(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn reset_local_changes_discards_local_writes_on_fork() {
+        let alice = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .accounts
+            .insert(alice, AccountInfo::from_balance(U256::from(10)));
+        source
+            .accounts
+            .insert(bob, AccountInfo::from_balance(U256::ZERO));
+        source.storage.insert((bob, U256::from(1)), U256::from(5));
+        let source = Arc::new(source);
+
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(source.clone(), None)));
+        // read both accounts from the fork, then overwrite them locally
+        assert_eq!(U256::from(10), evm.get_balance(alice).unwrap());
+        assert_eq!(
+            U256::from(5),
+            evm.get_storage_slot(bob, U256::from(1)).unwrap()
+        );
+        evm.set_balance(alice, U256::from(100)).unwrap();
+        evm.set_storage_slot(bob, U256::from(1), U256::from(7))
+            .unwrap();
+        let basic_requests = source.basic_requests();
+        let storage_requests = source.storage_requests();
+
+        evm.reset_local_changes();
+        assert_eq!(U256::from(10), evm.get_balance(alice).unwrap());
+        assert_eq!(
+            U256::from(5),
+            evm.get_storage_slot(bob, U256::from(1)).unwrap()
+        );
+        assert_eq!(basic_requests, source.basic_requests());
+        assert_eq!(storage_requests, source.storage_requests());
+    }
+
+    #[test]
+    fn set_hardfork_by_name() {
+        // runtime: TSTORE 42 in slot 0, TLOAD it and return it. Uses PUSH0.
+        let initcode = hex::decode("61000c8061000d6000396000f3602a5f5d5f5c5f5260205ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+
+        let mut evm = BaseEvm::default();
+        assert!(evm.set_hardfork("osaka").is_err());
+        evm.set_hardfork("cancun").unwrap();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode.clone(), U256::from(0)).unwrap();
+        let result = evm.transact_call(addr, vec![], U256::from(0)).unwrap();
+        assert_eq!(U256::from(42), U256::from_be_slice(&result.result));
+
+        // TSTORE/TLOAD are not available before cancun
+        evm.set_hardfork("shanghai").unwrap();
+        assert!(evm.transact_call(addr, vec![], U256::from(0)).is_err());
+
+        // PUSH0 is not available before shanghai
+        let mut evm = BaseEvm::default();
+        evm.set_hardfork("london").unwrap();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+        assert!(evm.transact_call(addr, vec![], U256::from(0)).is_err());
+    }
+
+    #[test]
+    fn capture_transient_storage_writes() {
+        // runtime: with no calldata TSTORE 42 in slot 0. Always return TLOAD of slot 0.
+        let initcode =
+            hex::decode("6100128061000d6000396000f33661000957602a5f5d5b5f5c5f5260205ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+
+        let mut evm = BaseEvm::default();
+        evm.set_hardfork("cancun").unwrap();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+
+        let (result, writes) = evm
+            .transact_call_with_transient(addr, vec![], U256::from(0))
+            .unwrap();
+        assert_eq!(U256::from(42), U256::from_be_slice(&result.result));
+        assert_eq!(vec![(addr, U256::ZERO, U256::from(42))], writes);
+
+        // the write doesn't persist after the call
+        let (result, writes) = evm
+            .transact_call_with_transient(addr, vec![1], U256::from(0))
+            .unwrap();
+        assert_eq!(U256::ZERO, U256::from_be_slice(&result.result));
+        assert!(writes.is_empty());
+    }
+
+    #[rstest]
+    fn on_commit_callback(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let commits = Arc::new(Mutex::new(Vec::new()));
+
+        let mut evm = BaseEvm::default();
+        let c = commits.clone();
+        evm.on_commit(Box::new(move |info| c.lock().unwrap().push(info.clone())));
+
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+            .unwrap();
+
+        let commits = commits.lock().unwrap();
+        assert_eq!(2, commits.len());
+        assert_eq!(owner, commits[0].caller);
+        assert_eq!(Some(addr), commits[0].to);
+        assert_eq!(Some(addr), commits[1].to);
+        assert!(commits[1].gas_used > 0);
+    }
+
+    #[test]
+    fn base_evm_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<BaseEvm>();
+    }
+
+    #[rstest]
+    fn watch_slot_on_commit(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let writes = Arc::new(Mutex::new(Vec::new()));
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        // `value` is in slot 1
+        let w = writes.clone();
+        evm.watch_slot(
+            addr,
+            U256::from(1),
+            Box::new(move |old, new| w.lock().unwrap().push((old, new))),
+        );
+
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        // reads don't commit
+        evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                (U256::from(1), U256::from(2)),
+                (U256::from(2), U256::from(3))
+            ],
+            *writes.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn call_sol_returns_raw_output() {
+        sol! {
+            #[derive(Debug)]
+            function pair() external returns (uint256, uint256);
+        }
+        // runtime: always return uint256(42)
+        let initcode = hex::decode("6100088061000d6000396000f3602a5f5260205ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+
+        let err = evm
+            .transact_call_sol(addr, pairCall {}, U256::from(0))
+            .unwrap_err();
+        assert!(err.to_string().contains(&format!("0x{:064x}", 42)));
+
+        let (decoded, raw) = evm
+            .transact_call_sol_raw(addr, pairCall {}, U256::from(0))
+            .unwrap();
+        assert!(decoded.is_err());
+        assert_eq!(U256::from(42), U256::from_be_slice(&raw));
+    }
+
+    #[test]
+    fn fork_sets_blob_base_fee() {
+        // runtime: return BLOBBASEFEE
+        let initcode = hex::decode("6100078061000d6000396000f34a5f5260205ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+        let excess_blob_gas = 10_000_000;
+
+        let mut source = MockForkSource::new(19_500_000, 1_710_500_000);
+        source.block.excess_blob_gas = Some(excess_blob_gas);
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+        let result = evm.transact_call(addr, vec![], U256::from(0)).unwrap();
+        assert_eq!(
+            U256::from(calc_blob_gasprice(excess_blob_gas)),
+            U256::from_be_slice(&result.result)
+        );
+        assert!(calc_blob_gasprice(excess_blob_gas) > 1);
+    }
+
+    #[test]
+    fn fork_uses_spec_of_mainnet_block() {
+        // before shanghai
+        let mut source = MockForkSource::new(16_000_000, 1_668_000_000);
+        source.block.chain_id = 1;
+        let evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        assert_eq!(SpecId::MERGE, evm.env().handler_cfg.spec_id);
+
+        let mut source = MockForkSource::new(19_500_000, 1_710_500_000);
+        source.block.chain_id = 1;
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        assert_eq!(SpecId::CANCUN, evm.env().handler_cfg.spec_id);
+
+        evm.set_spec_id(SpecId::SHANGHAI);
+        assert_eq!(SpecId::SHANGHAI, evm.env().handler_cfg.spec_id);
+
+        // unknown chains keep the default
+        let source = MockForkSource::new(19_500_000, 1_710_500_000);
+        let evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        assert_eq!(SpecId::LATEST, evm.env().handler_cfg.spec_id);
+    }
+
+    #[rstest]
+    fn verify_deployed_code_hash(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let info = evm
+            .deploy_with_info(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let expected = keccak256(&info.runtime_code);
+        assert!(evm.verify_code_hash(info.address, expected).unwrap());
+        assert!(!evm
+            .verify_code_hash(info.address, keccak256(b"something else"))
+            .unwrap());
+        assert!(!evm.verify_code_hash(owner, expected).unwrap());
+    }
+
+    #[test]
+    fn prefetch_storage_in_chunks() {
+        let contract = Address::repeat_byte(13);
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .storage
+            .insert((contract, U256::from(249)), U256::from(7));
+        let source = Arc::new(source);
+
+        let mut fork = CreateFork::from_source(source.clone(), None);
+        fork.storage_chunk_size = 100;
+        let mut evm = BaseEvm::new(Some(fork));
+
+        let slots = (0..250).map(U256::from).collect::<Vec<_>>();
+        let values = evm.get_storage_slots(contract, &slots).unwrap();
+        assert_eq!(250, values.len());
+        assert_eq!(U256::from(7), values[249]);
+        assert_eq!(3, source.batch_requests());
+    }
+
+    #[rstest]
+    fn simulate_on_top_of_pending(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let increment = TestContract::increment_0Call {}.abi_encode();
+        let pending = vec![
+            (owner, addr, increment.clone(), U256::from(0)),
+            (owner, addr, increment.clone(), U256::from(0)),
+        ];
+        let result = evm
+            .simulate_on_top(pending, (owner, addr, increment, U256::from(0)))
+            .unwrap();
+        // increment returns the previous value
+        assert_eq!(U256::from(3), U256::from_be_slice(&result.result));
+
+        // nothing was committed
+        assert_eq!(
+            U256::from(1),
+            evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+        assert_eq!(1, evm.backend.basic_ref(owner).unwrap().unwrap().nonce);
+    }
+
+    #[rstest]
+    fn get_account_info_for_contract(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let deployed = evm
+            .deploy_with_info(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let info = evm.get_account_info(deployed.address).unwrap().unwrap();
+        let code = info.code.unwrap();
+        assert!(!code.is_empty());
+        assert_eq!(keccak256(&deployed.runtime_code), info.code_hash);
+        assert_eq!(1, info.nonce);
+
+        let info = evm.get_account_info(owner).unwrap().unwrap();
+        assert_eq!(1, info.nonce);
+    }
+
+    #[rstest]
+    fn bundle_modes(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let increment = TestContract::increment_0Call {}.abi_encode();
+        // only the owner can call changeIt
+        let revert = TestContract::changeItCall {
+            _input: ChangeIt {
+                owner: Address::repeat_byte(1),
+                value: U256::from(10),
+            },
+        }
+        .abi_encode();
+        let bundle = vec![
+            (owner, addr, increment.clone(), U256::from(0)),
+            (Address::repeat_byte(1), addr, revert, U256::from(0)),
+            (owner, addr, increment, U256::from(0)),
+        ];
+        let value = |evm: &mut BaseEvm| {
+            evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        };
+
+        assert!(evm
+            .transact_bundle(bundle.clone(), BundleMode::AllOrNothing)
+            .is_err());
+        assert_eq!(U256::from(1), value(&mut evm));
+
+        let results = evm.transact_bundle(bundle, BundleMode::BestEffort).unwrap();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+        assert_eq!(U256::from(3), value(&mut evm));
+    }
+
+    #[test]
+    fn env_mut_changes_apply_to_calls() {
+        // runtime: return CHAINID
+        let initcode = hex::decode("6100078061000d6000396000f3465f5260205ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+        assert_eq!(1, evm.env().cfg.chain_id);
+
+        evm.env_mut().cfg.chain_id = 1337;
+        let result = evm.transact_call(addr, vec![], U256::from(0)).unwrap();
+        assert_eq!(U256::from(1337), U256::from_be_slice(&result.result));
+    }
+
+    #[rstest]
+    fn etch_runtime_code(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let runtime_code = evm
+            .deploy_with_info(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap()
+            .runtime_code;
+
+        // no constructor is run, so value starts at 0
+        let addr = Address::repeat_byte(99);
+        evm.etch(addr, runtime_code.to_vec()).unwrap();
+        assert_eq!(runtime_code, evm.get_code(addr).unwrap());
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        assert_eq!(
+            U256::from(1),
+            evm.transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+    }
+
+    #[rstest]
+    fn summarize_call_changes(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let result = evm
+            .transact_commit(
+                owner,
+                addr,
+                TestContract::increment_0Call {}.abi_encode(),
+                U256::from(0),
+            )
+            .unwrap();
+        let changes = result.changed_accounts();
+
+        let storage = changes
+            .iter()
+            .filter(|c| !c.storage.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(1, storage.len());
+        assert_eq!(addr, storage[0].address);
+        assert_eq!(
+            vec![(&U256::from(1), &(U256::from(1), U256::from(2)))],
+            storage[0].storage.iter().collect::<Vec<_>>()
+        );
+
+        let caller = changes.iter().find(|c| c.address == owner).unwrap();
+        assert_eq!(2, caller.nonce);
+        assert!(changes.iter().all(|c| c.code.is_none() && !c.destroyed));
+    }
+
+    #[test]
+    fn forks_share_a_provider() {
+        let rpc = MockRpc::start();
+        let provider = Arc::new(HttpProvider::try_from(rpc.url.as_str()).unwrap());
+        let alice = Address::repeat_byte(1);
+        let contract = Address::repeat_byte(2);
+
+        let mut evm1 = BaseEvm::new(Some(CreateFork::with_shared_provider(
+            provider.clone(),
+            Some(100),
+        )));
+        let mut evm2 = BaseEvm::new(Some(CreateFork::with_shared_provider(
+            provider.clone(),
+            Some(100),
+        )));
+        assert_eq!(3, Arc::strong_count(&provider));
+        assert_eq!(100, evm1.block_number());
+        assert_eq!(100, evm2.block_number());
+
+        evm1.create_account(alice, Some(U256::from(5))).unwrap();
+        assert_eq!(U256::from(5), evm1.get_balance(alice).unwrap());
+        assert_eq!(U256::from(1e18), evm2.get_balance(alice).unwrap());
+        assert_eq!(
+            vec![U256::from(42)],
+            evm2.get_storage_slots(contract, &[U256::ZERO]).unwrap()
+        );
+        assert!(rpc.requests() > 2);
+    }
+
+    #[test]
+    fn create_seeded_random_agents() {
+        let min = U256::from(1e18);
+        let max = U256::from(5e18);
+
+        let mut evm = BaseEvm::default();
+        let agents = evm.create_random_agents(7, 10, min, max);
+        assert_eq!(10, agents.len());
+        let balances = agents
+            .iter()
+            .map(|a| evm.get_balance(*a).unwrap())
+            .collect::<Vec<_>>();
+        assert!(balances.iter().all(|b| *b >= min && *b <= max));
+        assert!(balances.iter().any(|b| *b != balances[0]));
+
+        let mut other = BaseEvm::default();
+        assert_eq!(agents, other.create_random_agents(7, 10, min, max));
+        for (agent, balance) in agents.iter().zip(balances) {
+            assert_eq!(balance, other.get_balance(*agent).unwrap());
+        }
+        assert_ne!(
+            agents,
+            BaseEvm::default().create_random_agents(8, 10, min, max)
+        );
+    }
+
+    #[rstest]
+    fn check_is_contract(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        assert!(evm.is_contract(addr).unwrap());
+        assert!(!evm.is_contract(owner).unwrap());
+        assert!(!evm.is_contract(Address::repeat_byte(77)).unwrap());
+    }
+
+    #[test]
+    fn origin_is_the_caller() {
+        // runtime: return (tx.origin, msg.sender)
+        let initcode = hex::decode("61000b8061000d6000396000f3325f523360205260405ff3").unwrap();
+        let owner = Address::repeat_byte(12);
+
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, initcode, U256::from(0)).unwrap();
+
+        let result = evm
+            .transact_commit(owner, addr, vec![], U256::from(0))
+            .unwrap();
+        assert_eq!(owner, Address::from_slice(&result.result[12..32]));
+        assert_eq!(owner, Address::from_slice(&result.result[44..64]));
+
+        let result = evm.transact_call(addr, vec![], U256::from(0)).unwrap();
+        assert_eq!(Address::ZERO, Address::from_slice(&result.result[12..32]));
+    }
+
+    #[rstest]
+    fn load_filtered_snapshot(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let snap = evm.create_snapshot().unwrap();
+        assert!(snap.accounts.contains_key(&owner));
+
+        let mut filtered =
+            BaseEvm::new_from_snapshot_filtered(snap, |_, account| !account.code.is_empty());
+        let loaded = filtered.create_snapshot().unwrap();
+        assert_eq!(vec![&addr], loaded.accounts.keys().collect::<Vec<_>>());
+        assert_eq!(
+            U256::from(1),
+            filtered
+                .transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+                .unwrap()
+                ._0
+        );
+    }
+
+    #[rstest]
+    fn compare_state_roots(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let code = [contract_bytecode, args].concat();
+
+        let mut evm1 = BaseEvm::default();
+        let mut evm2 = BaseEvm::default();
+        let mut addr = Address::ZERO;
+        for evm in [&mut evm1, &mut evm2] {
+            evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+            addr = evm.deploy(owner, code.clone(), U256::from(0)).unwrap();
+        }
+        assert_eq!(evm1.state_root().unwrap(), evm2.state_root().unwrap());
+        assert_ne!(crate::trie::EMPTY_ROOT, evm1.state_root().unwrap());
+
+        evm2.backend
+            .insert_account_storage(addr, U256::from(1), U256::from(2))
+            .unwrap();
+        assert_ne!(evm1.state_root().unwrap(), evm2.state_root().unwrap());
+    }
+
+    #[test]
+    fn fork_limits_concurrent_requests() {
+        let rpc = MockRpc::start();
+        let mut fork = CreateFork::new(rpc.url.clone(), Some(100));
+        fork.max_concurrent_requests = 2;
+        let mut evm = BaseEvm::new(Some(fork));
+
+        let slots = (0..50).map(U256::from).collect::<Vec<_>>();
+        let values = evm
+            .get_storage_slots(Address::repeat_byte(2), &slots)
+            .unwrap();
+        assert_eq!(vec![U256::from(42); 50], values);
+        assert!(rpc.max_in_flight() > 0);
+        assert!(rpc.max_in_flight() <= 2);
+    }
+
+    #[rstest]
+    fn decode_output_of_void_function(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        let mut abi =
+            ContractAbi::from_human_readable(vec!["constructor(uint256)", "function deposit()"]);
+        abi.bytecode = Some(contract_bytecode.into());
+        let (args, _) = abi.encode_constructor("(1)").unwrap();
+        let addr = evm.deploy(owner, args, U256::ZERO).unwrap();
+
+        let (calldata, _, decoder) = abi.encode_function("deposit", "()").unwrap();
+        assert!(decoder.is_none());
+
+        let result = evm
+            .transact_commit(owner, addr, calldata, U256::from(10))
+            .unwrap();
+        assert!(result.result.is_empty());
+        assert!(abi
+            .decode_function_output("deposit", &result.result)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            DynSolValue::Tuple(vec![]),
+            abi.decode_single("deposit", &result.result).unwrap()
+        );
+    }
+
+    #[test]
+    fn apply_storage_overrides_to_many_contracts() {
+        let a = Address::repeat_byte(1);
+        let b = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+
+        let overrides = HashMap::from([
+            (
+                a,
+                HashMap::from([
+                    (U256::from(0), U256::from(10)),
+                    (U256::from(1), U256::from(11)),
+                ]),
+            ),
+            (b, HashMap::from([(U256::from(5), U256::from(50))])),
+        ]);
+        evm.apply_storage_overrides(overrides).unwrap();
+
+        assert_eq!(
+            vec![U256::from(10), U256::from(11)],
+            evm.get_storage_slots(a, &[U256::from(0), U256::from(1)])
+                .unwrap()
+        );
+        assert_eq!(
+            vec![U256::from(50), U256::ZERO],
+            evm.get_storage_slots(b, &[U256::from(5), U256::from(6)])
+                .unwrap()
+        );
+    }
+
+    #[rstest]
+    fn gas_breakdown_of_storage_heavy_call(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::ZERO)
+            .unwrap();
+
+        // increment() reads and writes 'value'
+        let (result, breakdown) = evm
+            .transact_call_gas_breakdown(
+                addr,
+                TestContract::increment_0Call {}.abi_encode(),
+                U256::ZERO,
+            )
+            .unwrap();
+        assert!(breakdown.total() > 0);
+        assert!(breakdown.total() < result.gas_used);
+        assert!(breakdown.storage > breakdown.total() / 2);
+        assert!(breakdown.storage > breakdown.memory + breakdown.arithmetic + breakdown.other);
+        assert_eq!(0, breakdown.call);
+        assert_eq!(0, breakdown.log);
+    }
+
+    #[rstest]
+    fn replay_detects_nonce_gap(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let initial = evm.create_snapshot().unwrap();
+
+        evm.enable_recording();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+
+        let txs = evm.recorded_transactions();
+        assert_eq!(0, txs[0].nonce);
+        assert_eq!(1, txs[1].nonce);
+
+        // out of order
+        let mut evm2 = BaseEvm::new_from_snapshot(initial.clone());
+        let err = replay(&[txs[1].clone(), txs[0].clone()], &mut evm2).unwrap_err();
+        assert!(err.to_string().contains("nonce 1"));
+        assert!(err.to_string().contains("account nonce is 0"));
+
+        // the deployment is missing
+        let mut evm3 = BaseEvm::new_from_snapshot(initial);
+        assert!(replay(&txs[1..], &mut evm3).is_err());
+    }
+
+    #[test]
+    fn max_code_size() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // returns 0x6001 bytes of code: 1 byte over the EIP-170 limit
+        let initcode = hex::decode("6160015ff3").unwrap();
+        assert!(evm.deploy(owner, initcode.clone(), U256::ZERO).is_err());
+
+        evm.set_max_code_size(Some(0x8000));
+        let addr = evm.deploy(owner, initcode.clone(), U256::ZERO).unwrap();
+        assert_eq!(0x6001, evm.get_code(addr).unwrap().len());
+
+        evm.set_max_code_size(None);
+        assert!(evm.deploy(owner, initcode, U256::ZERO).is_ok());
+    }
+
+    #[test]
+    fn can_afford_value_and_gas() {
+        let poor = Address::repeat_byte(1);
+        let rich = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.create_account(poor, Some(U256::from(1e18))).unwrap();
+        evm.create_account(rich, Some(U256::from(2e18))).unwrap();
+
+        let value = U256::from(1e18);
+        let gas_price = U256::from(1e9);
+        assert!(!evm.can_afford(poor, value, 21_000, gas_price).unwrap());
+        assert!(evm.can_afford(poor, value, 0, gas_price).unwrap());
+        assert!(evm.can_afford(rich, value, 21_000, gas_price).unwrap());
+        assert!(!evm.can_afford(rich, U256::MAX, 21_000, gas_price).unwrap());
+    }
+
+    #[test]
+    fn save_and_restore_block_env() {
+        let mut evm = BaseEvm::default();
+        let saved = evm.save_block_env();
+
+        evm.update_block(12);
+        evm.env_mut().block.basefee = U256::from(7);
+        evm.env_mut().block.coinbase = Address::repeat_byte(3);
+        evm.env_mut().cfg.chain_id = 10;
+        let changed = evm.save_block_env();
+        assert_eq!(saved.number + 1, changed.number);
+        assert_eq!(saved.timestamp + 12, changed.timestamp);
+        assert_ne!(saved, changed);
+
+        evm.restore_block_env(saved.clone());
+        assert_eq!(saved, evm.save_block_env());
+        assert_eq!(saved.number, evm.block_number());
+        assert_eq!(saved.timestamp, evm.timestamp());
+        assert_eq!(U256::ZERO, evm.env().block.basefee);
+        assert_eq!(Address::ZERO, evm.env().block.coinbase);
+        assert_eq!(1, evm.env().cfg.chain_id);
+    }
+
+    #[test]
+    fn capture_logs_toggle() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // emits a log with topic 1 and data 42
+        let initcode = hex::decode("61000b8061000d6000396000f3602a5f52600160205fa100").unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        // off by default
+        let result = evm
+            .transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        assert_eq!(1, result.logs.len());
+        assert!(evm.captured_logs().is_empty());
+
+        evm.set_capture_logs(true);
+        evm.transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        evm.transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        // reads are not committed
+        evm.transact_call(addr, vec![], U256::ZERO).unwrap();
+        let logs = evm.captured_logs();
+        assert_eq!(2, logs.len());
+        assert_eq!(addr, logs[0].address);
+        assert_eq!(U256::from(42), U256::from_be_slice(&logs[0].data.data));
+
+        assert_eq!(2, evm.take_captured_logs().len());
+        assert!(evm.captured_logs().is_empty());
+
+        evm.transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        evm.set_capture_logs(false);
+        assert!(evm.captured_logs().is_empty());
+    }
+
+    #[test]
+    fn effective_gas_price_with_priority_fee() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // returns tx.gasprice
+        let initcode = hex::decode("6100078061000d6000396000f33a5f5260205ff3").unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        assert_eq!(U256::ZERO, evm.effective_gas_price());
+
+        let env = evm.env_mut();
+        env.tx.gas_limit = 1_000_000;
+        env.block.basefee = U256::from(10e9);
+        env.tx.gas_price = U256::from(50e9);
+        env.tx.gas_priority_fee = Some(U256::from(2e9));
+        assert_eq!(U256::from(12e9), evm.effective_gas_price());
+
+        let result = evm.simulate(owner, addr, vec![], U256::ZERO).unwrap();
+        assert_eq!(
+            evm.effective_gas_price(),
+            U256::from_be_slice(&result.result)
+        );
+
+        // capped by the max fee
+        evm.env_mut().tx.gas_price = U256::from(11e9);
+        assert_eq!(U256::from(11e9), evm.effective_gas_price());
+        let result = evm
+            .transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        assert_eq!(U256::from(11e9), U256::from_be_slice(&result.result));
+        assert_eq!(
+            U256::from(1e18) - U256::from(11e9) * U256::from(result.gas_used),
+            evm.get_balance(owner).unwrap()
+        );
+    }
+
+    #[test]
+    fn caller_pays_the_fees() {
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+        let start = U256::from(1e18);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(start)).unwrap();
+
+        // the caller must afford the gas limit at the gas price
+        evm.set_gas_limit(1_000_000);
+        evm.set_fee_params(U256::from(5e9), None);
+        let result = evm
+            .transact_commit(owner, bob, vec![], U256::from(1))
+            .unwrap();
+        assert_eq!(21_000, result.gas_used);
+        let fee = U256::from(5e9) * U256::from(21_000);
+        assert_eq!(start - U256::from(1) - fee, evm.get_balance(owner).unwrap());
+
+        // EIP-1559: basefee + priority fee, capped by the gas price
+        evm.env_mut().block.basefee = U256::from(10e9);
+        evm.set_fee_params(U256::from(20e9), Some(U256::from(2e9)));
+        assert_eq!(U256::from(12e9), evm.effective_gas_price());
+        let before = evm.get_balance(owner).unwrap();
+        evm.transact_commit(owner, bob, vec![], U256::from(1))
+            .unwrap();
+        assert_eq!(
+            before - U256::from(1) - U256::from(12e9) * U256::from(21_000),
+            evm.get_balance(owner).unwrap()
+        );
+    }
+
+    #[test]
+    fn fees_without_a_gas_limit() {
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+        let start = U256::from(1e18);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(start)).unwrap();
+        evm.env_mut().cfg.disable_base_fee = true;
+
+        // the default gas limit is capped to what the caller can afford
+        evm.set_fee_params(U256::from(5e9), None);
+        assert!(evm.env().cfg.disable_base_fee);
+        let result = evm
+            .transact_commit(owner, bob, vec![], U256::from(1))
+            .unwrap();
+        let fee = U256::from(5e9) * U256::from(result.gas_used);
+        assert_eq!(start - U256::from(1) - fee, evm.get_balance(owner).unwrap());
+
+        // not enough to pay for the intrinsic gas
+        let poor = Address::repeat_byte(14);
+        evm.create_account(poor, Some(U256::from(1e9))).unwrap();
+        assert!(evm.transact_commit(poor, bob, vec![], U256::ZERO).is_err());
+    }
+
+    sol! {
+        contract Store {
+            event Stored(uint256 value);
+
+            // emits Stored(v) and returns v + 1
+            function store(uint256 v) external returns (uint256);
+        }
+    }
+
+    #[test]
+    fn commit_sol_full_returns_value_and_events() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let initcode = hex::decode(
+            "6100358061000d6000396000f36004355f527fc6d8c0af6d21f291e7c359603aa97e0ed5\
+            00f04db6e983b9fce75a91c6b8da6b60205fa15f516001015f5260205ff3",
+        )
+        .unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        let abi = ContractAbi::from_human_readable(vec![
+            "function store(uint256) (uint256)",
+            "event Stored(uint256)",
+        ]);
+        let result = evm
+            .transact_commit_sol_full(
+                owner,
+                addr,
+                Store::storeCall { v: U256::from(41) },
+                U256::ZERO,
+                &abi,
+            )
+            .unwrap();
+        assert_eq!(U256::from(42), result.value._0);
+        assert!(result.gas_used > 21_000);
+        assert_eq!(1, result.logs.len());
+        assert_eq!(
+            Store::Stored::SIGNATURE_HASH,
+            result.logs[0].data.topics()[0]
+        );
+        assert_eq!(
+            vec![(
+                "Stored".to_string(),
+                DynSolValue::Tuple(vec![DynSolValue::Uint(U256::from(41), 256)])
+            )],
+            result.decoded_events
+        );
+    }
+
+    #[test]
+    fn remote_balance_ignores_local_changes() {
+        let alice = Address::repeat_byte(1);
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .accounts
+            .insert(alice, AccountInfo::from_balance(U256::from(1e18)));
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+
+        evm.set_balance(alice, U256::from(5)).unwrap();
+        assert_eq!(U256::from(5), evm.get_balance(alice).unwrap());
+        assert_eq!(U256::from(1e18), evm.remote_balance(alice).unwrap());
+        assert_eq!(
+            U256::ZERO,
+            evm.remote_balance(Address::repeat_byte(2)).unwrap()
+        );
+
+        assert!(BaseEvm::default().remote_balance(alice).is_err());
+    }
+
+    #[rstest]
+    fn read_missing_storage(deployed_contract: (BaseEvm, Address, Address)) {
+        let (mut evm, _, addr) = deployed_contract;
+
+        // slot 1 is 'value', slot 5 is unused
+        assert_eq!(
+            vec![U256::from(1), U256::ZERO],
+            evm.get_storage_slots(addr, &[U256::from(1), U256::from(5)])
+                .unwrap()
+        );
+
+        evm.set_treat_missing_storage_as_zero(false);
+        assert_eq!(
+            vec![U256::from(1)],
+            evm.get_storage_slots(addr, &[U256::from(1)]).unwrap()
+        );
+        assert!(evm
+            .get_storage_slots(addr, &[U256::from(1), U256::from(5)])
+            .is_err());
+        assert_eq!(
+            U256::from(1),
+            evm.get_storage_slot(addr, U256::from(1)).unwrap()
+        );
+        assert!(evm.get_storage_slot(addr, U256::from(5)).is_err());
+    }
+
+    #[rstest]
+    fn reconstruct_session_with_replay_from(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let bob = Address::repeat_byte(13);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let initial = evm.create_snapshot().unwrap();
+
+        evm.enable_recording();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::ZERO)
+            .unwrap();
+        for _ in 0..3 {
+            evm.update_block(12);
+            evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::ZERO)
+                .unwrap();
+        }
+        evm.transact_commit(owner, bob, vec![], U256::from(100))
+            .unwrap();
+
+        let rebuilt = BaseEvm::replay_from(initial, &evm.recorded_transactions()).unwrap();
+        assert_eq!(evm.block_number(), rebuilt.block_number());
+        assert_eq!(evm.timestamp(), rebuilt.timestamp());
+        assert_eq!(evm.state_root().unwrap(), rebuilt.state_root().unwrap());
+        assert_eq!(
+            serde_json::to_string(&evm.create_snapshot().unwrap()).unwrap(),
+            serde_json::to_string(&rebuilt.create_snapshot().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn describe_transfer_call() {
+        sol! {
+            function transfer(address to, uint256 amount) external returns (bool);
+        }
+        let evm = BaseEvm::default();
+        let abi = ContractAbi::from_human_readable(vec![
+            "function transfer(address, uint256) (bool)",
+            "function setName(string, bytes32[])",
+        ]);
+        let to = Address::repeat_byte(0xab);
+        let data = transferCall {
+            to,
+            amount: U256::from(100),
+        }
+        .abi_encode();
+        assert_eq!(
+            Some(format!("transfer({}, 100)", to)),
+            evm.describe_call(&abi, &data)
+        );
+
+        let (data, _, _) = abi
+            .encode_function("setName", &format!("(bob, [0x{}])", "01".repeat(32)))
+            .unwrap();
+        assert_eq!(
+            Some(format!("setName(\"bob\", [0x{}])", "01".repeat(32))),
+            evm.describe_call(&abi, &data)
+        );
+
+        assert_eq!(None, evm.describe_call(&abi, &[1, 2, 3, 4]));
+    }
+
+    #[rstest]
+    fn call_at_block(meta_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let addr = evm.deploy(owner, meta_bytecode, U256::ZERO).unwrap();
+        let (number, timestamp) = (evm.block_number(), evm.timestamp());
+
+        let future = timestamp + 86_400;
+        let result = evm
+            .transact_call_at(
+                addr,
+                BlockMeta::getMetaCall {}.abi_encode(),
+                U256::ZERO,
+                number + 100,
+                future,
+            )
+            .unwrap();
+        let meta = BlockMeta::getMetaCall::abi_decode_returns(&result.result, true).unwrap();
+        assert_eq!(U256::from(future), meta._0);
+        assert_eq!(U256::from(number + 100), meta._1);
+
+        // the clock didn't change
+        assert_eq!(number, evm.block_number());
+        assert_eq!(timestamp, evm.timestamp());
+        let meta = evm
+            .transact_call_sol(addr, BlockMeta::getMetaCall {}, U256::ZERO)
+            .unwrap();
+        assert_eq!(U256::from(timestamp), meta._0);
+        assert_eq!(U256::from(number), meta._1);
+    }
+
+    #[test]
+    fn deploy_reports_create_collision() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        // returns 42
+        let initcode = hex::decode("6100088061000d6000396000f3602a5f5260205ff3").unwrap();
+        let salt = B256::repeat_byte(1);
+
+        let addr = evm
+            .deploy_create2(owner, initcode.clone(), U256::ZERO, salt)
+            .unwrap();
+        let err = evm
+            .deploy_create2(owner, initcode, U256::ZERO, salt)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("create collision"));
+        assert!(err.contains(&addr.to_string()));
+    }
+
+    #[test]
+    fn dump_storage_as_json() {
+        let contract = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.apply_storage_overrides(HashMap::from([(
+            contract,
+            HashMap::from([
+                (U256::ZERO, U256::from(42)),
+                (U256::from(255), U256::from(0xabcdef)),
+            ]),
+        )]))
+        .unwrap();
+
+        let json = evm.dump_storage_json(contract).unwrap();
+        let storage: HashMap<String, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(2, storage.len());
+        assert_eq!("0x2a", storage["0x0"]);
+        assert_eq!("0xabcdef", storage["0xff"]);
+
+        assert_eq!(
+            "{}",
+            evm.dump_storage_json(Address::repeat_byte(3)).unwrap()
+        );
+    }
+
+    #[test]
+    fn simulate_with_balance_override() {
+        let whale = Address::repeat_byte(1);
+        let bob = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.create_account(whale, Some(U256::from(1e18))).unwrap();
+
+        let amount = U256::from(1000e18);
+        assert!(evm.simulate(whale, bob, vec![], amount).is_err());
+
+        let result = evm
+            .simulate_with_balance(whale, bob, vec![], amount, U256::from(2000e18))
+            .unwrap();
+        let changes = result.changed_accounts();
+        let bob_change = changes.iter().find(|c| c.address == bob).unwrap();
+        assert_eq!(amount, bob_change.balance);
+
+        assert_eq!(U256::from(1e18), evm.get_balance(whale).unwrap());
+        assert_eq!(U256::ZERO, evm.get_balance(bob).unwrap());
+    }
+
+    #[test]
+    fn decode_custom_error_from_nested_call() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // always reverts with InsufficientBalance(7, 42)
+        let inner = hex::decode(
+            "6100188061000d6000396000f363cf47918160e01b5f526007600452602a60245260445ffd",
+        )
+        .unwrap();
+        // calls the address in the call data, bubbling up a revert
+        let outer =
+            hex::decode("6100168061000d6000396000f35f5f5f5f5f5f355af1610014573d5f5f3e3d5ffd5b00")
+                .unwrap();
+        let inner = evm.deploy(owner, inner, U256::ZERO).unwrap();
+        let outer = evm.deploy(owner, outer, U256::ZERO).unwrap();
+
+        let err = evm
+            .transact_commit(owner, outer, inner.into_word().to_vec(), U256::ZERO)
+            .unwrap_err();
+        let err = err.downcast_ref::<EvmError>().unwrap();
+        assert!(matches!(err, EvmError::Revert { .. }));
+
+        let abi = ContractAbi::from_human_readable(vec![
+            "error InsufficientBalance(uint256 available, uint256 required)",
+        ]);
+        let (name, values) = err.as_custom_error(&abi).unwrap();
+        assert_eq!("InsufficientBalance", name);
+        assert_eq!(
+            vec![
+                DynSolValue::Uint(U256::from(7), 256),
+                DynSolValue::Uint(U256::from(42), 256)
+            ],
+            values
+        );
+
+        let other = ContractAbi::from_human_readable(vec!["error Unauthorized()"]);
+        assert!(err.as_custom_error(&other).is_none());
+    }
+
+    #[test]
+    fn structured_errors_without_anyhow() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        // always reverts with InsufficientBalance(7, 42)
+        let initcode = hex::decode(
+            "6100188061000d6000396000f363cf47918160e01b5f526007600452602a60245260445ffd",
+        )
+        .unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        let abi = ContractAbi::from_human_readable(vec![
+            "error InsufficientBalance(uint256 available, uint256 required)",
+        ]);
+
+        let err = evm
+            .try_transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap_err();
+        match &err {
+            EvmError::Revert { reason, output, .. } => {
+                assert!(reason.is_none());
+                assert_eq!(68, output.len());
+            }
+            _ => panic!("expected a revert"),
+        }
+        assert_eq!("InsufficientBalance", err.as_custom_error(&abi).unwrap().0);
+
+        let err = evm.try_transact_call(addr, vec![], U256::ZERO).unwrap_err();
+        assert!(err.as_custom_error(&abi).is_some());
+
+        // errors before execution
+        let nobody = Address::repeat_byte(13);
+        let err = evm
+            .try_transact_commit(nobody, addr, vec![], U256::from(1))
+            .unwrap_err();
+        assert!(matches!(err, EvmError::Transaction(_)));
+    }
+
+    #[rstest]
+    fn list_contract_addresses(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let eoa = Address::repeat_byte(13);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        evm.create_account(eoa, Some(U256::from(1e18))).unwrap();
+        assert!(evm.contract_addresses().is_empty());
+
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let initcode = [contract_bytecode, args].concat();
+        let a = evm.deploy(owner, initcode.clone(), U256::ZERO).unwrap();
+        let b = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(expected, evm.contract_addresses());
+    }
+
+    #[test]
+    fn scenarios_start_from_the_same_state() {
+        let bob = Address::repeat_byte(1);
+        let alice = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.create_account(bob, Some(U256::from(10e18))).unwrap();
+        let block = evm.block_number();
+
+        let results = evm
+            .run_scenarios(3, |evm, i| {
+                // each scenario starts with bob's original balance
+                assert_eq!(U256::from(10e18), evm.get_balance(bob)?);
+                evm.transfer(bob, alice, U256::from(i + 1))?;
+                evm.update_block(12);
+                evm.get_balance(alice)
+            })
+            .unwrap();
+        assert_eq!(vec![U256::from(1), U256::from(2), U256::from(3)], results);
+
+        assert_eq!(U256::from(10e18), evm.get_balance(bob).unwrap());
+        assert_eq!(U256::ZERO, evm.get_balance(alice).unwrap());
+        assert_eq!(block, evm.block_number());
+    }
+
+    #[test]
+    fn scenarios_discard_logs_gas_report_and_env() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        // emits a log with topic 1 and data 42
+        let initcode = hex::decode("61000b8061000d6000396000f3602a5f52600160205fa100").unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        evm.set_capture_logs(true);
+        evm.enable_gas_report();
+
+        evm.run_scenarios(2, |evm, _| {
+            assert!(evm.captured_logs().is_empty());
+            assert!(evm.gas_report().unwrap().get(&[1, 2, 3, 4]).is_none());
+            assert_eq!(U256::ZERO, evm.env().block.basefee);
+            evm.transact_commit(owner, addr, vec![1, 2, 3, 4], U256::ZERO)?;
+            evm.env_mut().block.basefee = U256::from(1e9);
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(evm.captured_logs().is_empty());
+        assert!(evm.gas_report().unwrap().get(&[1, 2, 3, 4]).is_none());
+        assert_eq!(U256::ZERO, evm.env().block.basefee);
+    }
+
+    #[test]
+    fn read_many_balances() {
+        let addresses = generate_random_addresses(3);
+        let mut evm = BaseEvm::default();
+        for (i, address) in addresses.iter().enumerate() {
+            evm.create_account(*address, Some(U256::from(i + 1)))
+                .unwrap();
+        }
+
+        let balances = evm.get_balances(&addresses).unwrap();
+        let expected = addresses
+            .iter()
+            .map(|address| evm.get_balance(*address).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, balances);
+        assert_eq!(vec![U256::from(1), U256::from(2), U256::from(3)], balances);
+    }
+
+    #[test]
+    fn read_many_balances_from_fork() {
+        let rpc = MockRpc::start();
+        let addresses = generate_random_addresses(3);
+        let mut evm = BaseEvm::new(Some(CreateFork::new(rpc.url.clone(), None)));
+
+        let balances = evm.get_balances(&addresses).unwrap();
+        assert_eq!(vec![U256::from(1e18); 3], balances);
+        let requests = rpc.requests();
+
+        // already loaded
+        evm.get_balances(&addresses).unwrap();
+        assert_eq!(requests, rpc.requests());
+    }
+
+    #[test]
+    fn snapshot_without_zero_slots() {
+        let contract = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        evm.apply_storage_overrides(HashMap::from([(
+            contract,
+            HashMap::from([(U256::ZERO, U256::from(42)), (U256::from(1), U256::ZERO)]),
+        )]))
+        .unwrap();
+
+        let full = evm.create_snapshot().unwrap();
+        assert!(full.accounts[&contract]
+            .storage
+            .contains_key(&U256::from(1)));
+
+        let pruned = evm.create_snapshot_pruned().unwrap();
+        let storage = &pruned.accounts[&contract].storage;
+        assert_eq!(1, storage.len());
+        assert!(!storage.contains_key(&U256::from(1)));
+        assert_eq!(full.state_root(), pruned.state_root());
+
+        let mut loaded = BaseEvm::new_from_snapshot(pruned);
+        assert_eq!(
+            vec![U256::from(42), U256::ZERO],
+            loaded
+                .get_storage_slots(contract, &[U256::ZERO, U256::from(1)])
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn log_indices_within_a_block() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // emits 2 logs
+        let initcode =
+            hex::decode("6100118061000d6000396000f3602a5f52600160205fa1600260205fa100").unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        let first = evm
+            .transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        let second = evm
+            .transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        assert_eq!(0, first.log_index_base);
+        assert_eq!(2, second.log_index_base);
+        let indices = [&first, &second]
+            .iter()
+            .flat_map(|r| (0..r.logs.len()).map(|i| r.log_index_base + i))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 3], indices);
+
+        // starts again in a new block
+        evm.update_block(12);
+        let result = evm
+            .transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        assert_eq!(0, result.log_index_base);
+    }
+
+    #[test]
+    fn setup_agent_with_eth_and_tokens() {
+        sol! {
+            interface IERC20 {
+                function balanceOf(address owner) external view returns (uint256);
+            }
+        }
+
+        let owner = Address::repeat_byte(12);
+        let agent = Address::repeat_byte(13);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // balanceOf reads the balances mapping at slot 0 and slot 3
+        let token_a =
+            hex::decode("6100158061000d6000396000f36004355f52600060205260405f20545f5260205ff3")
+                .unwrap();
+        let token_b =
+            hex::decode("6100158061000d6000396000f36004355f52600360205260405f20545f5260205ff3")
+                .unwrap();
+        let token_a = evm.deploy(owner, token_a, U256::ZERO).unwrap();
+        let token_b = evm.deploy(owner, token_b, U256::ZERO).unwrap();
+
+        evm.setup_agent(
+            agent,
+            U256::from(5e18),
+            &[
+                (token_a, U256::from(100), U256::ZERO),
+                (token_b, U256::from(250), U256::from(3)),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(U256::from(5e18), evm.get_balance(agent).unwrap());
+        let balance = |evm: &mut BaseEvm, token| {
+            evm.transact_call_sol(token, IERC20::balanceOfCall { owner: agent }, U256::ZERO)
+                .unwrap()
+                ._0
+        };
+        assert_eq!(U256::from(100), balance(&mut evm, token_a));
+        assert_eq!(U256::from(250), balance(&mut evm, token_b));
+    }
+
+    #[rstest]
+    fn read_variable_from_storage_layout(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(7),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+
+        let layout = r#"{
+            "storage": [
+                {"astId": 3, "contract": "TestContract.sol:TestContract", "label": "owner",
+                 "offset": 0, "slot": "0", "type": "t_address"},
+                {"astId": 5, "contract": "TestContract.sol:TestContract", "label": "value",
+                 "offset": 0, "slot": "1", "type": "t_uint256"}
+            ],
+            "types": {
+                "t_address": {"encoding": "inplace", "label": "address", "numberOfBytes": "20"},
+                "t_uint256": {"encoding": "inplace", "label": "uint256", "numberOfBytes": "32"}
+            }
+        }"#;
+        let abi = ContractAbi::from_human_readable(vec![
+            "function owner() returns (address)",
+            "function value() returns (uint256)",
+        ])
+        .with_storage_layout(layout)
+        .unwrap();
+
+        let expected_owner = evm
+            .transact_call_sol(addr, TestContract::ownerCall {}, U256::ZERO)
+            .unwrap()
+            ._0;
+        let expected_value = evm
+            .transact_call_sol(addr, TestContract::valueCall {}, U256::ZERO)
+            .unwrap()
+            ._0;
+        assert_eq!(
+            DynSolValue::Address(expected_owner),
+            evm.read_variable(&abi, addr, "owner").unwrap()
+        );
+        assert_eq!(
+            DynSolValue::Uint(expected_value, 256),
+            evm.read_variable(&abi, addr, "value").unwrap()
+        );
+        assert!(evm.read_variable(&abi, addr, "missing").is_err());
+    }
+
+    #[test]
+    fn read_packed_variables() {
+        let contract = Address::repeat_byte(2);
+        let mut evm = BaseEvm::default();
+        // slot 0: int8 a = -2 | bytes2 b = 0xbeef | bool c = true
+        let word = U256::from(0xfe) | (U256::from(0xbeef) << 8) | (U256::from(1) << 24);
+        evm.apply_storage_overrides(HashMap::from([(
+            contract,
+            HashMap::from([(U256::ZERO, word)]),
+        )]))
+        .unwrap();
+
+        let layout = r#"{
+            "storage": [
+                {"label": "a", "offset": 0, "slot": "0", "type": "t_int8"},
+                {"label": "b", "offset": 1, "slot": "0", "type": "t_bytes2"},
+                {"label": "c", "offset": 3, "slot": "0", "type": "t_bool"}
+            ],
+            "types": {
+                "t_int8": {"encoding": "inplace", "label": "int8", "numberOfBytes": "1"},
+                "t_bytes2": {"encoding": "inplace", "label": "bytes2", "numberOfBytes": "2"},
+                "t_bool": {"encoding": "inplace", "label": "bool", "numberOfBytes": "1"}
+            }
+        }"#;
+        let abi = ContractAbi::from_human_readable(vec![])
+            .with_storage_layout(layout)
+            .unwrap();
+
+        assert_eq!(
+            DynSolValue::Int(I256::try_from(-2).unwrap(), 8),
+            evm.read_variable(&abi, contract, "a").unwrap()
+        );
+        let mut b = B256::ZERO;
+        b[..2].copy_from_slice(&[0xbe, 0xef]);
+        assert_eq!(
+            DynSolValue::FixedBytes(b, 2),
+            evm.read_variable(&abi, contract, "b").unwrap()
+        );
+        assert_eq!(
+            DynSolValue::Bool(true),
+            evm.read_variable(&abi, contract, "c").unwrap()
+        );
+    }
+
+    #[test]
+    fn max_call_depth_halts_recursion() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // calls itself with `n - 1` until `n` is 0, ignoring the result of the call
+        let initcode = hex::decode(
+            "61001b8061000d6000396000f35f35801561001957600190035f525f5f60205f5f305af150005b00",
+        )
+        .unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        let depth = |n: u64| U256::from(n).to_be_bytes_vec();
+
+        evm.set_max_call_depth(3);
+        assert!(evm
+            .transact_commit(owner, addr, depth(3), U256::ZERO)
+            .is_ok());
+
+        let err = evm
+            .transact_commit(owner, addr, depth(4), U256::ZERO)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EvmError>(),
+            Some(EvmError::Halt {
+                reason: HaltReason::CallTooDeep,
+                ..
+            })
+        ));
+
+        // also when run with an inspector
+        assert!(evm.transact_call_trace(addr, depth(3), U256::ZERO).is_ok());
+        let err = evm
+            .transact_call_trace(addr, depth(4), U256::ZERO)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EvmError>(),
+            Some(EvmError::Halt {
+                reason: HaltReason::CallTooDeep,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn max_call_depth_halts_nested_create() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+
+        // runtime: CREATE an empty contract and return its address
+        let initcode = hex::decode("61000a8061000d6000396000f35f5f5ff05f5260205ff3").unwrap();
+        let factory = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+
+        evm.set_max_call_depth(0);
+        let err = evm
+            .transact_commit(owner, factory, vec![], U256::ZERO)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EvmError>(),
+            Some(EvmError::Halt {
+                reason: HaltReason::CallTooDeep,
+                ..
+            })
+        ));
+
+        evm.set_max_call_depth(1);
+        let result = evm
+            .transact_commit(owner, factory, vec![], U256::ZERO)
+            .unwrap();
+        assert_eq!(
+            factory.create(1),
+            Address::from_word(B256::from_slice(&result.result))
+        );
+    }
+
+    #[rstest]
+    fn stream_trace_steps(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let data = TestContract::valueCall {}.abi_encode();
+
+        let (_, steps) = evm
+            .transact_call_trace(addr, data.clone(), U256::ZERO)
+            .unwrap();
+        assert!(!steps.is_empty());
+        assert!(steps.iter().all(|s| s.address == addr && s.depth == 0));
+        assert!(steps.iter().any(|s| s.opcode == opcode::SLOAD));
+
+        let mut count = 0;
+        let mut sloads = 0;
+        let result = evm
+            .transact_call_trace_streaming(addr, data, U256::ZERO, |step| {
+                count += 1;
+                if step.opcode == opcode::SLOAD {
+                    sloads += 1;
+                }
+            })
+            .unwrap();
+        assert_eq!(steps.len(), count);
+        assert_eq!(1, sloads);
+        assert_eq!(U256::from(1), U256::from_be_slice(&result.result));
+    }
+
+    #[rstest]
+    fn etch_code_with_storage_and_balance(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mock = Address::repeat_byte(20);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        let runtime_code = evm.get_code(addr).unwrap().to_vec();
+
+        let storage = HashMap::from([
+            (U256::ZERO, U256::from_be_slice(owner.as_slice())),
+            (U256::from(1), U256::from(99)),
+        ]);
+        evm.etch_with_storage(mock, runtime_code, storage, U256::from(5e18))
+            .unwrap();
+
+        let value = evm
+            .transact_call_sol(mock, TestContract::valueCall {}, U256::ZERO)
+            .unwrap()
+            ._0;
+        assert_eq!(U256::from(99), value);
+        let stored_owner = evm
+            .transact_call_sol(mock, TestContract::ownerCall {}, U256::ZERO)
+            .unwrap()
+            ._0;
+        assert_eq!(owner, stored_owner);
+        assert_eq!(
+            vec![U256::from(99)],
+            evm.get_storage_slots(mock, &[U256::from(1)]).unwrap()
+        );
+        assert_eq!(U256::from(5e18), evm.get_balance(mock).unwrap());
+    }
+
+    #[test]
+    fn fork_at_block_tag() {
+        let rpc = MockRpc::start();
+        let evm = BaseEvm::new(Some(CreateFork::at_tag(
+            rpc.url.clone(),
+            BlockTag::Finalized,
+        )));
+        assert_eq!(90, evm.block_number());
+
+        let evm = BaseEvm::new(Some(CreateFork::at_tag(rpc.url.clone(), BlockTag::Latest)));
+        assert_eq!(100, evm.block_number());
+
+        // only latest and earliest are supported by default
+        let source = Arc::new(MockForkSource::new(100, 1_700_000_000));
+        assert!(source.block_by_tag(BlockTag::Safe).is_err());
+    }
+
+    #[test]
+    fn sync_block_env_from_fork_header() {
+        let mut source = MockForkSource::new(19_500_000, 1_710_500_000);
+        source.block.base_fee = Some(U256::from(20e9));
+        source.block.coinbase = Address::repeat_byte(7);
+        source.block.prevrandao = Some(B256::repeat_byte(8));
+        source.block.gas_limit = 30_000_000;
+        source.block.excess_blob_gas = Some(10_000_000);
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        evm.update_block(12);
+
+        evm.sync_block_env_from_fork().unwrap();
+        assert_eq!(19_500_000, evm.block_number());
+        assert_eq!(1_710_500_000, evm.timestamp());
+        let block = &evm.env().block;
+        assert_eq!(U256::from(20e9), block.basefee);
+        assert_eq!(Address::repeat_byte(7), block.coinbase);
+        assert_eq!(Some(B256::repeat_byte(8)), block.prevrandao);
+        assert_eq!(U256::from(30_000_000), block.gas_limit);
+        assert_eq!(
+            Some(10_000_000),
+            block
+                .blob_excess_gas_and_price
+                .as_ref()
+                .map(|b| b.excess_blob_gas)
+        );
+
+        // from a node
+        let rpc = MockRpc::start();
+        let mut evm = BaseEvm::new(Some(CreateFork::new(rpc.url.clone(), Some(80))));
+        evm.sync_block_env_from_fork().unwrap();
+        assert_eq!(80, evm.block_number());
+        let block = &evm.env().block;
+        assert_eq!(U256::from(1e9), block.basefee);
+        assert_eq!(Address::repeat_byte(0x22), block.coinbase);
+        assert_eq!(Some(B256::repeat_byte(0x33)), block.prevrandao);
+        assert_eq!(U256::from(30_000_000), block.gas_limit);
+
+        // the prevrandao is kept if the header has none
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source.block.prevrandao = None;
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        evm.env_mut().block.prevrandao = Some(B256::repeat_byte(9));
+        evm.sync_block_env_from_fork().unwrap();
+        assert_eq!(Some(B256::repeat_byte(9)), evm.env().block.prevrandao);
+
+        assert!(BaseEvm::default().sync_block_env_from_fork().is_err());
+    }
+
+    #[rstest]
+    fn transact_after_sync_block_env_from_fork(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut source = MockForkSource::new(19_500_000, 1_710_500_000);
+        source.block.base_fee = Some(U256::from(20e9));
+        source.block.gas_limit = 30_000_000;
+        source
+            .accounts
+            .insert(owner, AccountInfo::from_balance(U256::from(1e18)));
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        evm.sync_block_env_from_fork().unwrap();
+
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        let value = evm
+            .transact_call_sol(addr, TestContract::valueCall {}, U256::from(0))
+            .unwrap();
+        assert_eq!(U256::from(2), value._0);
+        // no fees are paid by default
+        assert_eq!(U256::from(1e18), evm.get_balance(owner).unwrap());
+
+        // fees are paid once set
+        evm.set_fee_params(U256::from(30e9), None);
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::from(0))
+            .unwrap();
+        assert!(evm.get_balance(owner).unwrap() < U256::from(1e18));
+    }
+
+    #[test]
+    fn replay_a_block_of_the_fork() {
+        let alice = Address::repeat_byte(3);
+        let bob = Address::repeat_byte(4);
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source
+            .accounts
+            .insert(alice, AccountInfo::from_balance(U256::from(10e18)));
+        // a transfer, then the deployment of a contract that accepts any value
+        source.transactions = vec![
+            ForkTransaction {
+                from: alice,
+                to: Some(bob),
+                value: U256::from(1e18),
+                nonce: 0,
+                ..Default::default()
+            },
+            ForkTransaction {
+                from: alice,
+                input: hex::decode("6100018061000d6000396000f300").unwrap().into(),
+                nonce: 1,
+                ..Default::default()
+            },
+        ];
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+
+        let results = evm.replay_block(101).unwrap();
+        assert_eq!(2, results.len());
+        assert_eq!(U256::from(1e18), evm.get_balance(bob).unwrap());
+        let deployed = results[1].address.unwrap();
+        assert_eq!(alice.create(1), deployed);
+        assert!(evm.is_contract(deployed).unwrap());
+        assert_eq!(2, evm.caller_nonce(alice).unwrap());
+
+        // the transactions were already applied
+        assert!(evm.replay_block(101).is_err());
+        assert!(BaseEvm::default().replay_block(101).is_err());
+    }
+
+    #[test]
+    fn fork_with_malformed_url() {
+        let err = BaseEvm::try_new(Some(CreateFork::new("not a url".into(), None)))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalid fork url"));
+
+        // the node can't be reached
+        let err = BaseEvm::try_new(Some(CreateFork::new("http://127.0.0.1:1".into(), None)))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("failed to load the fork block"));
+
+        assert!(BaseEvm::try_new(None).is_ok());
+    }
+
+    #[test]
+    fn fork_fetches_code_only_when_needed() {
+        let contract = Address::repeat_byte(2);
+        // returns 42
+        let code = Bytecode::new_raw(hex::decode("602a5f5260205ff3").unwrap().into());
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source.accounts.insert(
+            contract,
+            AccountInfo::new(U256::from(1e18), 1, code.hash_slow(), code.clone()),
+        );
+        let source = Arc::new(source);
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(source.clone(), None)));
+
+        assert_eq!(U256::from(1e18), evm.get_balance(contract).unwrap());
+        assert!(evm.is_contract(contract).unwrap());
+        assert_eq!(0, source.code_requests());
+
+        let result = evm.transact_call(contract, vec![], U256::ZERO).unwrap();
+        assert_eq!(U256::from(42), U256::from_be_slice(&result.result));
+        evm.transact_call(contract, vec![], U256::ZERO).unwrap();
+        assert_eq!(code.original_bytes(), evm.get_code(contract).unwrap());
+        assert_eq!(1, source.code_requests());
+
+        // a node: the account has no code, so it's never fetched
+        let rpc = MockRpc::start();
+        let mut evm = BaseEvm::new(Some(CreateFork::new(rpc.url.clone(), None)));
+        let requests = rpc.requests();
+        assert_eq!(U256::from(1e18), evm.get_balance(contract).unwrap());
+        assert!(evm.get_code(contract).unwrap().is_empty());
+        assert_eq!(requests + 1, rpc.requests());
+    }
+
+    #[test]
+    fn fork_without_eth_get_proof() {
+        let rpc = MockRpc::start_without_proofs();
+        let mut evm = BaseEvm::new(Some(CreateFork::new(rpc.url.clone(), None)));
+        let addresses = generate_random_addresses(2);
+
+        // falls back to fetching the nonce, balance and code, and only tries
+        // `eth_getProof` once
+        assert_eq!(U256::from(1e18), evm.get_balance(addresses[0]).unwrap());
+        assert_eq!(U256::from(1e18), evm.get_balance(addresses[1]).unwrap());
+        assert!(evm.get_code(addresses[1]).unwrap().is_empty());
+        assert_eq!(1, rpc.proof_requests());
+    }
+
+    #[test]
+    fn set_storage_slot_of_untouched_accounts() {
+        let addr = Address::repeat_byte(9);
+        let mut evm = BaseEvm::default();
+        evm.set_storage_slot(addr, U256::from(3), U256::from(33))
+            .unwrap();
+        assert_eq!(
+            U256::from(33),
+            evm.get_storage_slot(addr, U256::from(3)).unwrap()
+        );
+
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source.storage.insert((addr, U256::from(4)), U256::from(44));
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+        evm.set_storage_slot(addr, U256::from(3), U256::from(33))
+            .unwrap();
+        assert_eq!(
+            U256::from(33),
+            evm.get_storage_slot(addr, U256::from(3)).unwrap()
+        );
+        // the other slots are still read from the fork
+        assert_eq!(
+            U256::from(44),
+            evm.get_storage_slot(addr, U256::from(4)).unwrap()
+        );
+    }
+
+    #[test]
+    fn capture_the_writes_of_a_reverted_call() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        // stores 1 in slot 0, then reverts
+        let reverts = hex::decode("6100078061000d6000396000f360015f555f5ffd").unwrap();
+        // stores 2 in slot 0
+        let stores = hex::decode("6100058061000d6000396000f360025f5500").unwrap();
+        let reverts = evm.deploy(owner, reverts, U256::ZERO).unwrap();
+        let stores = evm.deploy(owner, stores, U256::ZERO).unwrap();
+        let nonce = evm.caller_nonce(owner).unwrap();
+
+        let (success, changes) = evm
+            .transact_commit_capture_reverted(owner, reverts, vec![], U256::ZERO)
+            .unwrap();
+        assert!(!success);
+        let slot = &changes[&reverts].storage[&U256::ZERO];
+        assert_eq!(U256::ZERO, slot.original_value());
+        assert_eq!(U256::from(1), slot.present_value());
+        // nothing is committed
+        assert_eq!(
+            U256::ZERO,
+            evm.get_storage_slot(reverts, U256::ZERO).unwrap()
+        );
+        assert_eq!(nonce, evm.caller_nonce(owner).unwrap());
+
+        let (success, changes) = evm
+            .transact_commit_capture_reverted(owner, stores, vec![], U256::ZERO)
+            .unwrap();
+        assert!(success);
+        assert_eq!(
+            U256::from(2),
+            changes[&stores].storage[&U256::ZERO].present_value()
+        );
+        assert_eq!(
+            U256::from(2),
+            evm.get_storage_slot(stores, U256::ZERO).unwrap()
+        );
+        assert_eq!(nonce + 1, evm.caller_nonce(owner).unwrap());
+    }
+
+    #[test]
+    fn commit_expecting_an_event() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        // store(v) emits Stored(v)
+        let initcode = hex::decode(
+            "6100358061000d6000396000f36004355f527fc6d8c0af6d21f291e7c359603aa97e0ed5\
+            00f04db6e983b9fce75a91c6b8da6b60205fa15f516001015f5260205ff3",
+        )
+        .unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        let abi = ContractAbi::from_human_readable(vec![
+            "function store(uint256) (uint256)",
+            "event Stored(uint256)",
+            "event Removed(uint256)",
+        ]);
+        let data = Store::storeCall { v: U256::from(1) }.abi_encode();
+
+        let result = evm
+            .transact_commit_expect_event(owner, addr, data.clone(), U256::ZERO, &abi, "Stored")
+            .unwrap();
+        assert_eq!(1, result.logs.len());
+        let nonce = evm.get_account_info(owner).unwrap().unwrap().nonce;
+
+        let err = evm
+            .transact_commit_expect_event(owner, addr, data.clone(), U256::ZERO, &abi, "Removed")
+            .unwrap_err();
+        assert!(err.to_string().contains("Removed was not emitted"));
+        // not committed
+        assert_eq!(nonce, evm.get_account_info(owner).unwrap().unwrap().nonce);
+
+        assert!(evm
+            .transact_commit_expect_event(owner, addr, data, U256::ZERO, &abi, "Missing")
+            .is_err());
+    }
+
+    #[test]
+    fn record_fork_accesses() {
+        let contract = Address::repeat_byte(2);
+        let alice = Address::repeat_byte(3);
+        // returns the storage slot in the call data
+        let code = Bytecode::new_raw(hex::decode("5f35545f5260205ff3").unwrap().into());
+        let mut source = MockForkSource::new(100, 1_700_000_000);
+        source.accounts.insert(
+            contract,
+            AccountInfo::new(U256::ZERO, 1, code.hash_slow(), code),
+        );
+        source
+            .accounts
+            .insert(alice, AccountInfo::from_balance(U256::from(1e18)));
+        for slot in 1..4 {
+            source
+                .storage
+                .insert((contract, U256::from(slot)), U256::from(slot + 4));
+        }
+        let mut evm = BaseEvm::new(Some(CreateFork::from_source(Arc::new(source), None)));
+
+        // not recorded yet
+        evm.get_balance(alice).unwrap();
+        evm.get_storage_slots(contract, &[U256::from(3)]).unwrap();
+
+        evm.begin_access_recording();
+        let result = evm
+            .transact_call(contract, U256::from(1).to_be_bytes_vec(), U256::ZERO)
+            .unwrap();
+        assert_eq!(U256::from(5), U256::from_be_slice(&result.result));
+        evm.get_storage_slots(contract, &[U256::from(2)]).unwrap();
+
+        let accesses = evm.access_record();
+        assert!(accesses.accounts.contains(&contract));
+        assert!(!accesses.accounts.contains(&alice));
+        assert_eq!(
+            BTreeMap::from([(contract, BTreeSet::from([U256::from(1), U256::from(2)]))]),
+            accesses.storage
+        );
+
+        let snap = evm.create_snapshot_from_accesses().unwrap();
+        assert!(!snap.accounts.contains_key(&alice));
+        assert_eq!(
+            BTreeMap::from([
+                (U256::from(1), U256::from(5)),
+                (U256::from(2), U256::from(6))
+            ]),
+            snap.accounts[&contract].storage
+        );
+
+        // the snapshot is enough to re-run the call
+        let mut replayed = BaseEvm::new_from_snapshot(snap);
+        let result = replayed
+            .transact_call(contract, U256::from(1).to_be_bytes_vec(), U256::ZERO)
+            .unwrap();
+        assert_eq!(U256::from(5), U256::from_be_slice(&result.result));
+    }
+
+    #[test]
+    fn export_logs_as_rpc_json() {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        evm.set_capture_logs(true);
+
+        // emits a log with topic 1 and data 42
+        let initcode = hex::decode("61000b8061000d6000396000f3602a5f52600160205fa100").unwrap();
+        let addr = evm.deploy(owner, initcode, U256::ZERO).unwrap();
+        evm.transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+        evm.transact_commit(owner, addr, vec![], U256::ZERO)
+            .unwrap();
+
+        let json = evm.logs_as_rpc_json().unwrap();
+        let logs: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(2, logs.len());
+        assert_eq!(addr.to_string().to_lowercase(), logs[0]["address"]);
+        assert_eq!(
+            serde_json::json!([B256::from(U256::from(1)).to_string()]),
+            logs[0]["topics"]
+        );
+        assert_eq!(format!("0x{:064x}", 42), logs[0]["data"]);
+        assert_eq!(format!("{:#x}", evm.block_number()), logs[0]["blockNumber"]);
+        assert_eq!("0x0", logs[0]["logIndex"]);
+        assert_eq!("0x1", logs[1]["logIndex"]);
+        assert_eq!(false, logs[1]["removed"]);
+    }
+
+    #[rstest]
+    fn count_accounts_and_storage_slots(contract_bytecode: Vec<u8>) {
+        let owner = Address::repeat_byte(12);
+        let mut evm = BaseEvm::default();
+        assert_eq!(0, evm.account_count());
+        assert_eq!(0, evm.storage_slot_count());
+
+        evm.create_account(owner, Some(U256::from(1e18))).unwrap();
+        assert_eq!(1, evm.account_count());
+
+        // the constructor writes `owner` and `value`
+        let args = TestContract::constructorCall {
+            _value: U256::from(1),
+        }
+        .abi_encode();
+        let addr = evm
+            .deploy(owner, [contract_bytecode, args].concat(), U256::from(0))
+            .unwrap();
+        // the owner, the contract and the coinbase
+        assert_eq!(3, evm.account_count());
+        assert_eq!(2, evm.storage_slot_count());
+
+        evm.apply_storage_overrides(HashMap::from([(
+            addr,
+            HashMap::from([
+                (U256::from(10), U256::from(1)),
+                (U256::from(11), U256::from(2)),
+            ]),
+        )]))
+        .unwrap();
+        assert_eq!(4, evm.storage_slot_count());
+
+        // rewriting a slot doesn't add one
+        evm.transact_commit_sol(owner, addr, TestContract::increment_0Call {}, U256::ZERO)
+            .unwrap();
+        assert_eq!(4, evm.storage_slot_count());
+    }
 }
@@ -0,0 +1,155 @@
+//!
+//! Track and compare gas usage per function selector
+//!
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::BaseEvm;
+
+/// Gas statistics for all calls made to a single function selector
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasStats {
+    /// number of calls recorded
+    pub calls: u64,
+    /// the lowest gas used by a call
+    pub min: u64,
+    /// the highest gas used by a call
+    pub max: u64,
+    /// the total gas used by all calls
+    pub total: u64,
+}
+
+impl GasStats {
+    fn record(&mut self, gas_used: u64) {
+        if self.calls == 0 || gas_used < self.min {
+            self.min = gas_used;
+        }
+        if gas_used > self.max {
+            self.max = gas_used;
+        }
+        self.calls += 1;
+        self.total += gas_used;
+    }
+
+    /// The average gas used per call
+    pub fn avg(&self) -> u64 {
+        if self.calls == 0 {
+            return 0;
+        }
+        self.total / self.calls
+    }
+}
+
+/// Gas used by successful transactions, keyed by the 4-byte function selector
+/// of the call data. Calls without a selector (deploys, plain transfers) are not recorded.
+#[derive(Clone, Debug, Default)]
+pub struct GasReport {
+    pub selectors: BTreeMap<[u8; 4], GasStats>,
+}
+
+impl GasReport {
+    /// Record the `gas_used` by a call with the given call `data`
+    pub fn record(&mut self, data: &[u8], gas_used: u64) {
+        if data.len() < 4 {
+            return;
+        }
+        let mut selector = [0u8; 4];
+        selector.copy_from_slice(&data[..4]);
+        self.selectors.entry(selector).or_default().record(gas_used);
+    }
+
+    /// Return the stats for the given `selector`, if any
+    pub fn get(&self, selector: &[u8; 4]) -> Option<&GasStats> {
+        self.selectors.get(selector)
+    }
+}
+
+/// The difference in average gas for a single selector between two runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasDelta {
+    pub selector: [u8; 4],
+    /// stats from the first (baseline) run, if the selector was called
+    pub left: Option<GasStats>,
+    /// stats from the second run, if the selector was called
+    pub right: Option<GasStats>,
+}
+
+impl GasDelta {
+    /// The change in average gas from `left` to `right`. A negative value means
+    /// the second run used less gas.
+    pub fn delta(&self) -> i128 {
+        let l = self.left.as_ref().map(|s| s.avg()).unwrap_or_default();
+        let r = self.right.as_ref().map(|s| s.avg()).unwrap_or_default();
+        r as i128 - l as i128
+    }
+}
+
+/// Compare the gas used by the same workload against two EVMs. This is useful
+/// to measure the effect of a different configuration, such as the spec id.
+#[derive(Clone, Debug, Default)]
+pub struct GasComparison {
+    pub deltas: Vec<GasDelta>,
+}
+
+impl GasComparison {
+    /// Run the workload `f` against both `left` and `right`, recording gas per selector
+    /// and returning the comparison. This enables the gas report on both EVMs.
+    pub fn run<F>(left: &mut BaseEvm, right: &mut BaseEvm, mut f: F) -> Result<Self>
+    where
+        F: FnMut(&mut BaseEvm) -> Result<()>,
+    {
+        left.enable_gas_report();
+        right.enable_gas_report();
+        f(left)?;
+        f(right)?;
+
+        let empty = GasReport::default();
+        Ok(Self::compare(
+            left.gas_report().unwrap_or(&empty),
+            right.gas_report().unwrap_or(&empty),
+        ))
+    }
+
+    /// Compare 2 gas reports. Every selector found in either report is included.
+    pub fn compare(left: &GasReport, right: &GasReport) -> Self {
+        let mut selectors = left.selectors.keys().collect::<Vec<_>>();
+        selectors.extend(right.selectors.keys());
+        selectors.sort();
+        selectors.dedup();
+
+        let deltas = selectors
+            .into_iter()
+            .map(|s| GasDelta {
+                selector: *s,
+                left: left.get(s).cloned(),
+                right: right.get(s).cloned(),
+            })
+            .collect();
+        Self { deltas }
+    }
+
+    /// Return the delta for the given `selector`, if any
+    pub fn get(&self, selector: &[u8; 4]) -> Option<&GasDelta> {
+        self.deltas.iter().find(|d| &d.selector == selector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_stats() {
+        let mut report = GasReport::default();
+        report.record(&[1, 2, 3, 4, 5], 10);
+        report.record(&[1, 2, 3, 4], 30);
+        report.record(&[], 100);
+
+        let stats = report.get(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(1, report.selectors.len());
+        assert_eq!(2, stats.calls);
+        assert_eq!(10, stats.min);
+        assert_eq!(30, stats.max);
+        assert_eq!(20, stats.avg());
+    }
+}
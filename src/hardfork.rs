@@ -0,0 +1,124 @@
+//!
+//! Select the EVM spec by hardfork name, or by the block on a known chain
+//!
+use revm::primitives::SpecId;
+
+/// Return the `SpecId` for the hardfork `name`, e.g. "london", "shanghai", "cancun".
+/// Case, spaces, dashes and underscores are ignored, so "Tangerine Whistle" and
+/// "tangerine_whistle" both work.  Returns `None` for an unknown name.
+pub fn spec_for_hardfork(name: &str) -> Option<SpecId> {
+    let name = name
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-' | '_'))
+        .collect::<String>()
+        .to_lowercase();
+
+    let spec = match name.as_str() {
+        "frontier" => SpecId::FRONTIER,
+        "homestead" => SpecId::HOMESTEAD,
+        "tangerine" | "tangerinewhistle" => SpecId::TANGERINE,
+        "spuriousdragon" => SpecId::SPURIOUS_DRAGON,
+        "byzantium" => SpecId::BYZANTIUM,
+        "constantinople" => SpecId::CONSTANTINOPLE,
+        "petersburg" => SpecId::PETERSBURG,
+        "istanbul" => SpecId::ISTANBUL,
+        "muirglacier" => SpecId::MUIR_GLACIER,
+        "berlin" => SpecId::BERLIN,
+        "london" => SpecId::LONDON,
+        "arrowglacier" => SpecId::ARROW_GLACIER,
+        "grayglacier" => SpecId::GRAY_GLACIER,
+        "merge" | "paris" => SpecId::MERGE,
+        "shanghai" => SpecId::SHANGHAI,
+        "cancun" => SpecId::CANCUN,
+        "latest" => SpecId::LATEST,
+        _ => return None,
+    };
+    Some(spec)
+}
+
+/// Mainnet hardforks activated by block number, latest first
+const MAINNET_BLOCKS: [(u64, SpecId); 14] = [
+    (15_537_394, SpecId::MERGE),
+    (15_050_000, SpecId::GRAY_GLACIER),
+    (13_773_000, SpecId::ARROW_GLACIER),
+    (12_965_000, SpecId::LONDON),
+    (12_244_000, SpecId::BERLIN),
+    (9_200_000, SpecId::MUIR_GLACIER),
+    (9_069_000, SpecId::ISTANBUL),
+    (7_280_000, SpecId::PETERSBURG),
+    (4_370_000, SpecId::BYZANTIUM),
+    (2_675_000, SpecId::SPURIOUS_DRAGON),
+    (2_463_000, SpecId::TANGERINE),
+    (1_920_000, SpecId::DAO_FORK),
+    (1_150_000, SpecId::HOMESTEAD),
+    (0, SpecId::FRONTIER),
+];
+
+/// Mainnet hardforks activated by timestamp, latest first
+const MAINNET_TIMESTAMPS: [(u64, SpecId); 2] = [
+    (1_710_338_135, SpecId::CANCUN),
+    (1_681_338_455, SpecId::SHANGHAI),
+];
+
+/// Return the `SpecId` active at the block `number` with the given `timestamp` on the
+/// chain `chain_id`.  Only mainnet (chain id 1) is known, other chains return `None`.
+pub fn spec_for_block(chain_id: u64, number: u64, timestamp: u64) -> Option<SpecId> {
+    if chain_id != 1 {
+        return None;
+    }
+    MAINNET_TIMESTAMPS
+        .iter()
+        .find(|(activation, _)| timestamp >= *activation)
+        .or_else(|| {
+            MAINNET_BLOCKS
+                .iter()
+                .find(|(activation, _)| number >= *activation)
+        })
+        .map(|(_, spec)| *spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardfork_names() {
+        assert_eq!(Some(SpecId::CANCUN), spec_for_hardfork("cancun"));
+        assert_eq!(Some(SpecId::SHANGHAI), spec_for_hardfork("Shanghai"));
+        assert_eq!(Some(SpecId::MERGE), spec_for_hardfork("paris"));
+        assert_eq!(
+            Some(SpecId::TANGERINE),
+            spec_for_hardfork("Tangerine Whistle")
+        );
+        assert_eq!(
+            Some(SpecId::GRAY_GLACIER),
+            spec_for_hardfork("gray_glacier")
+        );
+        assert_eq!(None, spec_for_hardfork("osaka"));
+    }
+
+    #[test]
+    fn mainnet_spec_by_block() {
+        assert_eq!(
+            Some(SpecId::FRONTIER),
+            spec_for_block(1, 100, 1_438_270_000)
+        );
+        assert_eq!(
+            Some(SpecId::LONDON),
+            spec_for_block(1, 13_000_000, 1_629_000_000)
+        );
+        assert_eq!(
+            Some(SpecId::MERGE),
+            spec_for_block(1, 15_537_394, 1_663_224_179)
+        );
+        assert_eq!(
+            Some(SpecId::SHANGHAI),
+            spec_for_block(1, 17_034_870, 1_681_338_455)
+        );
+        assert_eq!(
+            Some(SpecId::CANCUN),
+            spec_for_block(1, 19_426_587, 1_710_338_135)
+        );
+        assert_eq!(None, spec_for_block(10, 19_426_587, 1_710_338_135));
+    }
+}
@@ -0,0 +1,328 @@
+//!
+//! Inspectors used to observe the execution of a transaction
+//!
+use alloy_primitives::{Address, Bytes, Log, U256};
+use revm::{
+    interpreter::{
+        gas::CALL_STIPEND, opcode, CallInputs, CallOutcome, CreateInputs, CreateOutcome, Gas,
+        InstructionResult, Interpreter, InterpreterAction, InterpreterResult,
+    },
+    Database, EvmContext, Inspector,
+};
+
+/// A transient storage write: `(contract address, key, value)`
+pub type TransientWrite = (Address, U256, U256);
+
+/// Captures the transient storage (EIP-1153) writes made by `TSTORE`.
+/// The writes are in execution order.
+#[derive(Clone, Debug, Default)]
+pub struct TransientStorageInspector {
+    pending: Option<TransientWrite>,
+    pub writes: Vec<TransientWrite>,
+}
+
+impl<DB: Database> Inspector<DB> for TransientStorageInspector {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        self.pending = None;
+        if interp.current_opcode() == opcode::TSTORE {
+            if let (Ok(key), Ok(value)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                self.pending = Some((interp.contract.address, key, value));
+            }
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        // only keep the write if the TSTORE succeeded
+        if let Some(write) = self.pending.take() {
+            if interp.instruction_result == InstructionResult::Continue {
+                self.writes.push(write);
+            }
+        }
+    }
+}
+
+/// A storage write: `(contract address, slot, value)`
+pub type StorageWrite = (Address, U256, U256);
+
+/// Captures the storage writes made by `SSTORE`, in execution order.  Writes made by
+/// a call that later reverted are kept.
+#[derive(Clone, Debug, Default)]
+pub struct StorageWriteInspector {
+    pending: Option<StorageWrite>,
+    pub writes: Vec<StorageWrite>,
+}
+
+impl<DB: Database> Inspector<DB> for StorageWriteInspector {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        self.pending = None;
+        if interp.current_opcode() == opcode::SSTORE {
+            if let (Ok(slot), Ok(value)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                self.pending = Some((interp.contract.address, slot, value));
+            }
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        // only keep the write if the SSTORE succeeded
+        if let Some(write) = self.pending.take() {
+            if interp.instruction_result == InstructionResult::Continue {
+                self.writes.push(write);
+            }
+        }
+    }
+}
+
+/// Gas used by a transaction, bucketed by the category of the opcodes that used it.
+/// Gas forwarded to a call is counted by the opcodes run by the call, and the
+/// intrinsic gas of the transaction is not included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GasBreakdown {
+    /// SLOAD, SSTORE, TLOAD and TSTORE
+    pub storage: u64,
+    /// memory reads, writes and copies, including memory expansion
+    pub memory: u64,
+    /// calls, contract creation and selfdestruct
+    pub call: u64,
+    /// arithmetic, comparison and bitwise operations, and hashing
+    pub arithmetic: u64,
+    /// LOG0 - LOG4
+    pub log: u64,
+    /// everything else: stack, control flow, environment and block information
+    pub other: u64,
+}
+
+impl GasBreakdown {
+    /// The gas used by all the opcodes
+    pub fn total(&self) -> u64 {
+        self.storage + self.memory + self.call + self.arithmetic + self.log + self.other
+    }
+
+    fn bucket(&mut self, op: u8) -> &mut u64 {
+        match op {
+            opcode::SLOAD | opcode::SSTORE | opcode::TLOAD | opcode::TSTORE => &mut self.storage,
+            opcode::MLOAD
+            | opcode::MSTORE
+            | opcode::MSTORE8
+            | opcode::MSIZE
+            | opcode::MCOPY
+            | opcode::CALLDATACOPY
+            | opcode::CODECOPY
+            | opcode::EXTCODECOPY
+            | opcode::RETURNDATACOPY => &mut self.memory,
+            opcode::CALL
+            | opcode::CALLCODE
+            | opcode::DELEGATECALL
+            | opcode::STATICCALL
+            | opcode::CREATE
+            | opcode::CREATE2
+            | opcode::SELFDESTRUCT => &mut self.call,
+            opcode::ADD..=opcode::SAR | opcode::KECCAK256 => &mut self.arithmetic,
+            opcode::LOG0..=opcode::LOG4 => &mut self.log,
+            _ => &mut self.other,
+        }
+    }
+}
+
+/// Builds a `GasBreakdown` of a transaction
+#[derive(Clone, Debug, Default)]
+pub struct GasBreakdownInspector {
+    // opcode and gas remaining before it runs
+    pending: Option<(u8, u64)>,
+    pub breakdown: GasBreakdown,
+}
+
+impl<DB: Database> Inspector<DB> for GasBreakdownInspector {
+    fn step(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        self.pending = Some((interp.current_opcode(), interp.gas.remaining()));
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        if let Some((op, before)) = self.pending.take() {
+            // the gas forwarded to a call or create is charged here, but used by
+            // the opcodes of the call
+            let forwarded = match &interp.next_action {
+                InterpreterAction::Call { inputs } => {
+                    let value_transfer = matches!(op, opcode::CALL | opcode::CALLCODE)
+                        && inputs.transfer.value != U256::ZERO;
+                    if value_transfer {
+                        inputs.gas_limit.saturating_sub(CALL_STIPEND)
+                    } else {
+                        inputs.gas_limit
+                    }
+                }
+                InterpreterAction::Create { inputs } => inputs.gas_limit,
+                _ => 0,
+            };
+            let used = before
+                .saturating_sub(interp.gas.remaining())
+                .saturating_sub(forwarded);
+            *self.breakdown.bucket(op) += used;
+        }
+    }
+}
+
+/// Fails calls and contract creations nested deeper than `max_depth`, as if they
+/// exceeded the EVM's limit of 1024.  The top-level call is at depth 0.  `exceeded`
+/// is set if a call or creation was failed.
+#[derive(Clone, Debug, Default)]
+pub struct CallDepthInspector {
+    max_depth: u16,
+    pub exceeded: bool,
+}
+
+impl CallDepthInspector {
+    pub fn new(max_depth: u16) -> Self {
+        Self {
+            max_depth,
+            exceeded: false,
+        }
+    }
+
+    // the result of a call or creation that is too deep, or `None` to run it
+    fn check_depth(&mut self, depth: u64, gas_limit: u64) -> Option<InterpreterResult> {
+        if depth <= self.max_depth as u64 {
+            return None;
+        }
+        self.exceeded = true;
+        Some(InterpreterResult {
+            result: InstructionResult::CallTooDeep,
+            output: Bytes::new(),
+            gas: Gas::new(gas_limit),
+        })
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallDepthInspector {
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        self.check_depth(context.journaled_state.depth(), inputs.gas_limit)
+            .map(|result| CallOutcome::new(result, inputs.return_memory_offset.clone()))
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.check_depth(context.journaled_state.depth(), inputs.gas_limit)
+            .map(|result| CreateOutcome::new(result, None))
+    }
+}
+
+/// Runs `inner`, failing calls and creations that are too deep when a `limit` is set.
+/// Used to apply the max call depth to transactions run with another inspector.
+pub(crate) struct WithCallDepth<I> {
+    pub limit: Option<CallDepthInspector>,
+    pub inner: I,
+}
+
+impl<I> WithCallDepth<I> {
+    pub fn exceeded(&self) -> bool {
+        self.limit.as_ref().is_some_and(|limit| limit.exceeded)
+    }
+}
+
+// `inner` sees every call and creation, even a failed one, since revm always
+// calls `call_end`/`create_end`
+impl<DB: Database, I: Inspector<DB>> Inspector<DB> for WithCallDepth<I> {
+    fn initialize_interp(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.initialize_interp(interp, context)
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.step(interp, context)
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.step_end(interp, context)
+    }
+
+    fn log(&mut self, context: &mut EvmContext<DB>, log: &Log) {
+        self.inner.log(context, log)
+    }
+
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        let too_deep = self
+            .limit
+            .as_mut()
+            .and_then(|limit| limit.call(context, inputs));
+        let outcome = self.inner.call(context, inputs);
+        too_deep.or(outcome)
+    }
+
+    fn call_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.inner.call_end(context, inputs, outcome)
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let too_deep = self
+            .limit
+            .as_mut()
+            .and_then(|limit| limit.create(context, inputs));
+        let outcome = self.inner.create(context, inputs);
+        too_deep.or(outcome)
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.inner.create_end(context, inputs, outcome)
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: U256) {
+        Inspector::<DB>::selfdestruct(&mut self.inner, contract, target, value)
+    }
+}
+
+/// A single opcode executed by a transaction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    /// the depth of the call running the opcode. The top-level call is at depth 0
+    pub depth: u64,
+    /// the address of the contract running the opcode
+    pub address: Address,
+    /// the program counter
+    pub pc: usize,
+    /// the opcode
+    pub opcode: u8,
+    /// the gas remaining before the opcode runs
+    pub gas_remaining: u64,
+}
+
+/// Calls `on_step` with each opcode executed by a transaction, in order.  The steps
+/// are not kept.
+pub struct TraceInspector<F: FnMut(&TraceStep)> {
+    pub on_step: F,
+}
+
+impl<DB: Database, F: FnMut(&TraceStep)> Inspector<DB> for TraceInspector<F> {
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        (self.on_step)(&TraceStep {
+            // the journal is one level deeper while a call runs
+            depth: context.journaled_state.depth() - 1,
+            address: interp.contract.address,
+            pc: interp.program_counter(),
+            opcode: interp.current_opcode(),
+            gas_remaining: interp.gas.remaining(),
+        });
+    }
+}
@@ -72,14 +72,22 @@
 //!
 pub mod abi;
 pub mod db;
+pub mod deploy;
+pub mod erc721;
 pub mod errors;
 pub mod evm;
+pub mod gas;
+pub mod hardfork;
+pub mod inspectors;
+pub mod math;
+pub mod recorder;
 pub mod snapshot;
+pub mod trie;
 
 // re-exports
 pub use {abi::ContractAbi, db::CreateFork, evm::BaseEvm, snapshot::SnapShot};
 
-use alloy_primitives::Address;
+use alloy_primitives::{keccak256, Address};
 
 /// Generate the given `num` of addresses
 pub fn generate_random_addresses(num: u8) -> Vec<Address> {
@@ -89,3 +97,14 @@ pub fn generate_random_addresses(num: u8) -> Vec<Address> {
     }
     addresses
 }
+
+/// Generate `num` addresses derived from `seed`.  The same seed always
+/// generates the same addresses.
+pub fn generate_seeded_addresses(seed: u64, num: usize) -> Vec<Address> {
+    (0..num as u64)
+        .map(|i| {
+            let hash = keccak256([seed.to_be_bytes(), i.to_be_bytes()].concat());
+            Address::from_slice(&hash[12..])
+        })
+        .collect()
+}
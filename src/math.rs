@@ -0,0 +1,66 @@
+//!
+//! Overflow-safe math for token amounts
+//!
+use alloy_primitives::{U256, U512};
+use anyhow::{bail, Result};
+
+/// The number of basis points in 100%
+pub const BPS_DENOMINATOR: u32 = 10_000;
+
+/// Compute `a * b / denom`, rounded down.  The product is computed with 512 bits so
+/// it doesn't overflow.  Returns an error if `denom` is zero or the result doesn't
+/// fit in a `U256`.
+pub fn mul_div(a: U256, b: U256, denom: U256) -> Result<U256> {
+    if denom.is_zero() {
+        bail!("mul_div: division by zero");
+    }
+    let product: U512 = a.widening_mul(b);
+    let result = product / U512::from(denom);
+    if result > U512::from(U256::MAX) {
+        bail!("mul_div: result overflows a U256");
+    }
+    Ok(U256::from_limbs_slice(&result.as_limbs()[..4]))
+}
+
+/// Compute `bps` basis points of `value`, rounded down.  For example, a 0.3% fee is
+/// `percent_of(amount, 30)`.  Saturates at `U256::MAX`.
+pub fn percent_of(value: U256, bps: u32) -> U256 {
+    mul_div(value, U256::from(bps), U256::from(BPS_DENOMINATOR)).unwrap_or(U256::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_with_large_intermediate() {
+        // MAX * MAX overflows 256 bits
+        assert!(U256::MAX.checked_mul(U256::MAX).is_none());
+        assert_eq!(U256::MAX, mul_div(U256::MAX, U256::MAX, U256::MAX).unwrap());
+
+        let a = U256::MAX / U256::from(3);
+        let b = U256::from(10).pow(U256::from(30));
+        let denom = U256::from(10).pow(U256::from(31));
+        let expected = a.widening_mul::<256, 4, 512, 8>(b) / U512::from(denom);
+        assert!(a.checked_mul(b).is_none());
+        assert_eq!(U512::from(mul_div(a, b, denom).unwrap()), expected);
+        assert_eq!(a / U256::from(10), mul_div(a, b, denom).unwrap());
+
+        assert_eq!(
+            U256::from(7),
+            mul_div(U256::from(10), U256::from(15), U256::from(20)).unwrap()
+        );
+        assert!(mul_div(U256::from(1), U256::from(1), U256::ZERO).is_err());
+        assert!(mul_div(U256::MAX, U256::from(2), U256::from(1)).is_err());
+    }
+
+    #[test]
+    fn basis_points() {
+        let amount = U256::from(1_000_000);
+        assert_eq!(U256::from(3_000), percent_of(amount, 30));
+        assert_eq!(amount, percent_of(amount, BPS_DENOMINATOR));
+        assert_eq!(U256::ZERO, percent_of(U256::from(333), 30));
+        assert_eq!(U256::MAX / U256::from(2), percent_of(U256::MAX, 5_000));
+        assert_eq!(U256::MAX, percent_of(U256::MAX, 20_000));
+    }
+}
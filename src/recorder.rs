@@ -0,0 +1,43 @@
+//!
+//! Record committed transactions so they can be replayed later
+//!
+use alloy_primitives::{Address, Bytes, B256, U256};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{evm::CallResult, BaseEvm};
+
+/// A transaction captured at commit time. See `BaseEvm::enable_recording`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedTx {
+    /// the sender of the transaction
+    pub caller: Address,
+    /// the recipient of the transaction. `None` is a contract deployment
+    pub to: Option<Address>,
+    /// the call data (or contract bytecode for a deployment)
+    pub data: Bytes,
+    /// the salt of a CREATE2 deployment
+    #[serde(default)]
+    pub salt: Option<B256>,
+    /// the value sent with the transaction
+    pub value: U256,
+    /// the nonce of the caller when the transaction was committed
+    #[serde(default)]
+    pub nonce: u64,
+    /// the block number when the transaction was committed
+    pub block_number: u64,
+    /// the block timestamp when the transaction was committed
+    pub timestamp: u64,
+}
+
+/// Re-apply the recorded transactions, in order, to the given `evm`.  The block number
+/// and timestamp of the `evm` are set to the recorded values for each transaction.
+///
+/// To reproduce the original session, `evm` should start from the same state as the
+/// EVM that recorded the transactions, for example by loading a snapshot.
+///
+/// Returns an error, before applying it, if the nonce of a transaction doesn't match
+/// the current nonce of its caller, e.g. a transaction is missing or out of order.
+pub fn replay(txs: &[RecordedTx], evm: &mut BaseEvm) -> Result<Vec<CallResult>> {
+    txs.iter().map(|tx| evm.apply_recorded_tx(tx)).collect()
+}
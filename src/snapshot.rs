@@ -1,9 +1,20 @@
 //!
 //! Containers for serializing EVM state information
 //!
-use revm::primitives::{Address, Bytes, U256};
+use anyhow::{anyhow, bail, Result};
+use revm::primitives::{keccak256, Address, Bytes, B256, U256};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    db::StorageBackend,
+    trie::{encode_account, trie_root},
+};
 
 /// Source of the snapshop.  Either from a fork or the local in-memory database.
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
@@ -31,3 +42,451 @@ pub struct SnapShot {
     pub timestamp: u64,
     pub accounts: BTreeMap<Address, SnapShotAccountRecord>,
 }
+
+/// What was loaded by `SnapShot::load_with_stats`, and how long it took
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LoadStats {
+    /// the number of accounts, including contracts
+    pub accounts_loaded: usize,
+    /// the number of accounts with code
+    pub contracts_loaded: usize,
+    /// the number of storage slots, across all the accounts
+    pub storage_slots_loaded: usize,
+    /// the time taken to load the snapshot
+    pub elapsed: Duration,
+}
+
+/// The fields of an `eth_getProof` response used to build a `SnapShotAccountRecord`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountProof {
+    address: Option<Address>,
+    balance: U256,
+    nonce: U256,
+    #[serde(default)]
+    storage_proof: Vec<StorageProof>,
+}
+
+#[derive(Deserialize)]
+struct StorageProof {
+    key: U256,
+    value: U256,
+}
+
+/// An `eth_getProof` response may be the full JSON-RPC envelope or just the result
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ProofResponse {
+    Envelope { result: AccountProof },
+    Proof(AccountProof),
+}
+
+impl SnapShot {
+    /// Compute the Merkle Patricia Trie root of the accounts (and their storage) in
+    /// the snapshot, as used for a block's state root.  Empty accounts are not included
+    /// (EIP-161).
+    ///
+    /// Note: this will only match a real state root if the snapshot contains all
+    /// the accounts and storage of the state.
+    pub fn state_root(&self) -> B256 {
+        let items = self
+            .accounts
+            .iter()
+            .filter(|(_, a)| {
+                a.nonce != 0 || !a.balance.is_zero() || !a.code.is_empty() || !a.storage.is_empty()
+            })
+            .map(|(address, account)| (keccak256(address), encode_account(account)))
+            .collect::<Vec<_>>();
+        trie_root(&items)
+    }
+
+    /// Remove the storage slots with a value of zero.  A snapshot is loaded into the
+    /// in-memory database, where a missing slot reads as zero, so this doesn't
+    /// change the state.
+    pub fn prune_zero_storage(&mut self) {
+        for account in self.accounts.values_mut() {
+            account.storage.retain(|_, value| !value.is_zero());
+        }
+    }
+
+    /// Load the snapshot into `backend`, as `StorageBackend::load_snapshot` does, and
+    /// return what was loaded and how long it took.  Useful to diagnose a slow startup.
+    pub fn load_with_stats(self, backend: &mut StorageBackend) -> Result<LoadStats> {
+        let accounts_loaded = self.accounts.len();
+        let contracts_loaded = self
+            .accounts
+            .values()
+            .filter(|a| !a.code.is_empty())
+            .count();
+        let storage_slots_loaded = self.accounts.values().map(|a| a.storage.len()).sum();
+
+        let start = Instant::now();
+        backend.load_snapshot(self);
+        Ok(LoadStats {
+            accounts_loaded,
+            contracts_loaded,
+            storage_slots_loaded,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    /// Return a short, human-readable summary of the snapshot: the block number,
+    /// and the number of accounts, contracts (accounts with code) and storage
+    /// slots, and the total balance of all accounts.
+    pub fn summary(&self) -> String {
+        let contracts = self
+            .accounts
+            .values()
+            .filter(|a| !a.code.is_empty())
+            .count();
+        let slots = self
+            .accounts
+            .values()
+            .map(|a| a.storage.len())
+            .sum::<usize>();
+        let balance = self
+            .accounts
+            .values()
+            .fold(U256::ZERO, |total, a| total.saturating_add(a.balance));
+
+        format!(
+            "block: {}\naccounts: {}\ncontracts: {}\nstorage slots: {}\ntotal balance: {}",
+            self.block_num,
+            self.accounts.len(),
+            contracts,
+            slots,
+            balance
+        )
+    }
+
+    /// Parse an `eth_getProof` JSON response for `address` into an account record
+    /// that can be inserted into `accounts`. The record contains the proven balance,
+    /// nonce, and storage slots.
+    ///
+    /// Note: the proof only contains the hash of the account's code, so the record's
+    /// `code` is empty and must be set separately for contracts.
+    pub fn from_proof(address: Address, proof_json: &str) -> Result<SnapShotAccountRecord> {
+        let proof = match serde_json::from_str::<ProofResponse>(proof_json)
+            .map_err(|e| anyhow!("SnapShot: failed to parse eth_getProof response: {}", e))?
+        {
+            ProofResponse::Envelope { result } => result,
+            ProofResponse::Proof(proof) => proof,
+        };
+
+        if let Some(proven) = proof.address {
+            if proven != address {
+                bail!("SnapShot: proof is for account {} not {}", proven, address);
+            }
+        }
+
+        let nonce = u64::try_from(proof.nonce)
+            .map_err(|_| anyhow!("SnapShot: nonce in proof is too large"))?;
+
+        Ok(SnapShotAccountRecord {
+            nonce,
+            balance: proof.balance,
+            code: Bytes::new(),
+            storage: proof
+                .storage_proof
+                .into_iter()
+                .map(|slot| (slot.key, slot.value))
+                .collect(),
+        })
+    }
+}
+
+/// A single account in a Geth genesis `alloc`
+#[derive(Deserialize)]
+struct GenesisAccount {
+    #[serde(default)]
+    balance: Option<String>,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    code: Option<Bytes>,
+    #[serde(default)]
+    storage: BTreeMap<String, String>,
+}
+
+/// A genesis file may be the full `genesis.json` or just the `alloc` map
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GenesisFile {
+    Genesis {
+        alloc: BTreeMap<String, GenesisAccount>,
+    },
+    Alloc(BTreeMap<String, GenesisAccount>),
+}
+
+// genesis numbers may be either hex (0x prefix) or decimal strings
+fn parse_genesis_number(value: &str) -> Result<U256> {
+    value
+        .parse::<U256>()
+        .map_err(|e| anyhow!("SnapShot: invalid genesis value '{}': {}", value, e))
+}
+
+impl SnapShot {
+    /// Build a snapshot from a Geth-style genesis allocation: a map of
+    /// `address => { balance, nonce, code, storage }`.  `raw` may be either a full
+    /// `genesis.json` (with an `alloc` key) or just the allocation map.
+    pub fn from_genesis_alloc(raw: &str) -> Result<SnapShot> {
+        let alloc = match serde_json::from_str::<GenesisFile>(raw)
+            .map_err(|e| anyhow!("SnapShot: failed to parse genesis alloc: {}", e))?
+        {
+            GenesisFile::Genesis { alloc } => alloc,
+            GenesisFile::Alloc(alloc) => alloc,
+        };
+
+        let mut accounts = BTreeMap::new();
+        for (addr, account) in alloc {
+            let address = addr
+                .parse::<Address>()
+                .map_err(|e| anyhow!("SnapShot: invalid genesis address '{}': {}", addr, e))?;
+            let balance = match account.balance {
+                Some(b) => parse_genesis_number(&b)?,
+                _ => U256::ZERO,
+            };
+            let nonce = match account.nonce {
+                Some(n) => u64::try_from(parse_genesis_number(&n)?)
+                    .map_err(|_| anyhow!("SnapShot: genesis nonce for {} is too large", addr))?,
+                _ => 0,
+            };
+            let storage = account
+                .storage
+                .iter()
+                .map(|(k, v)| Ok((parse_genesis_number(k)?, parse_genesis_number(v)?)))
+                .collect::<Result<BTreeMap<_, _>>>()?;
+
+            accounts.insert(
+                address,
+                SnapShotAccountRecord {
+                    nonce,
+                    balance,
+                    code: account.code.unwrap_or_default(),
+                    storage,
+                },
+            );
+        }
+
+        Ok(SnapShot {
+            source: SnapShotSource::Memory,
+            block_num: 0,
+            timestamp: 0,
+            accounts,
+        })
+    }
+}
+
+/// A named snapshot in an archive
+#[derive(Serialize, Deserialize)]
+struct ArchiveEntry {
+    name: String,
+    snapshot: SnapShot,
+}
+
+impl SnapShot {
+    /// Write many named `snapshots` to a single (JSON) archive file at `path`.
+    /// Names must be unique.  See `read_archive`
+    pub fn write_archive(path: &Path, snapshots: &[(String, SnapShot)]) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut entries = Vec::with_capacity(snapshots.len());
+        for (name, snapshot) in snapshots {
+            if !names.insert(name) {
+                bail!("SnapShot: duplicate name '{}' in archive", name);
+            }
+            entries.push(ArchiveEntry {
+                name: name.clone(),
+                snapshot: snapshot.clone(),
+            });
+        }
+        let json = serde_json::to_string(&entries)
+            .map_err(|e| anyhow!("SnapShot: failed to serialize archive: {}", e))?;
+        fs::write(path, json).map_err(|e| anyhow!("SnapShot: failed to write archive: {}", e))
+    }
+
+    /// Read the named snapshots, in the order they were written, from an archive
+    /// created with `write_archive`
+    pub fn read_archive(path: &Path) -> Result<Vec<(String, SnapShot)>> {
+        let raw = fs::read_to_string(path)
+            .map_err(|e| anyhow!("SnapShot: failed to read archive: {}", e))?;
+        let entries = serde_json::from_str::<Vec<ArchiveEntry>>(&raw)
+            .map_err(|e| anyhow!("SnapShot: failed to parse archive: {}", e))?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.snapshot))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use revm::primitives::address;
+
+    const PROOF: &str = r#"{
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "address": "0x7f0d15c7faae65896648c8273b6d7e43f58fa842",
+            "accountProof": [
+                "0xf90211a0...",
+                "0xf90211a0..."
+            ],
+            "balance": "0x2386f26fc10000",
+            "codeHash": "0xc5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            "nonce": "0x5",
+            "storageHash": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+            "storageProof": [
+                {
+                    "key": "0x0",
+                    "value": "0x2a",
+                    "proof": []
+                },
+                {
+                    "key": "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421",
+                    "value": "0x1",
+                    "proof": []
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn account_from_proof() {
+        let addr = address!("7f0d15c7faae65896648c8273b6d7e43f58fa842");
+        let record = SnapShot::from_proof(addr, PROOF).unwrap();
+
+        assert_eq!(U256::from(10_000_000_000_000_000u64), record.balance);
+        assert_eq!(5, record.nonce);
+        assert!(record.code.is_empty());
+        assert_eq!(2, record.storage.len());
+        assert_eq!(Some(&U256::from(42)), record.storage.get(&U256::ZERO));
+
+        let mut snap = SnapShot::default();
+        snap.accounts.insert(addr, record);
+        assert_eq!(1, snap.accounts.len());
+
+        assert!(SnapShot::from_proof(Address::ZERO, PROOF).is_err());
+        assert!(SnapShot::from_proof(addr, "{}").is_err());
+    }
+
+    #[test]
+    fn summarize_snapshot() {
+        let mut snap = SnapShot {
+            block_num: 10,
+            ..Default::default()
+        };
+        snap.accounts.insert(
+            Address::repeat_byte(1),
+            SnapShotAccountRecord {
+                nonce: 1,
+                balance: U256::from(100),
+                code: Bytes::new(),
+                storage: BTreeMap::new(),
+            },
+        );
+        snap.accounts.insert(
+            Address::repeat_byte(2),
+            SnapShotAccountRecord {
+                nonce: 1,
+                balance: U256::from(50),
+                code: Bytes::from(vec![0x60, 0x00]),
+                storage: BTreeMap::from([
+                    (U256::from(0), U256::from(1)),
+                    (U256::from(1), U256::from(2)),
+                ]),
+            },
+        );
+
+        assert_eq!(
+            "block: 10\naccounts: 2\ncontracts: 1\nstorage slots: 2\ntotal balance: 150",
+            snap.summary()
+        );
+    }
+
+    #[test]
+    fn load_snapshot_with_stats() {
+        let mut snap = SnapShot::default();
+        snap.accounts.insert(
+            Address::repeat_byte(1),
+            SnapShotAccountRecord {
+                nonce: 0,
+                balance: U256::from(100),
+                code: Bytes::new(),
+                storage: BTreeMap::new(),
+            },
+        );
+        for i in 2..4 {
+            snap.accounts.insert(
+                Address::repeat_byte(i),
+                SnapShotAccountRecord {
+                    nonce: 1,
+                    balance: U256::ZERO,
+                    code: Bytes::from(vec![0x60, 0x00]),
+                    storage: BTreeMap::from([
+                        (U256::from(0), U256::from(1)),
+                        (U256::from(1), U256::from(2)),
+                        (U256::from(2), U256::from(3)),
+                    ]),
+                },
+            );
+        }
+
+        let mut backend = StorageBackend::default();
+        let stats = snap.load_with_stats(&mut backend).unwrap();
+        assert_eq!(3, stats.accounts_loaded);
+        assert_eq!(2, stats.contracts_loaded);
+        assert_eq!(6, stats.storage_slots_loaded);
+        assert_eq!(3, backend.known_accounts().len());
+        assert_eq!(3, backend.known_storage(Address::repeat_byte(2)).len());
+    }
+
+    #[test]
+    fn write_and_read_archive() {
+        let mut first = SnapShot {
+            block_num: 1,
+            ..Default::default()
+        };
+        first.accounts.insert(
+            Address::repeat_byte(1),
+            SnapShotAccountRecord {
+                nonce: 1,
+                balance: U256::from(10),
+                code: Bytes::new(),
+                storage: BTreeMap::new(),
+            },
+        );
+        let second = SnapShot {
+            block_num: 2,
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scenarios.json");
+        SnapShot::write_archive(
+            &path,
+            &[("first".into(), first.clone()), ("second".into(), second)],
+        )
+        .unwrap();
+
+        let archive = SnapShot::read_archive(&path).unwrap();
+        assert_eq!(
+            vec!["first", "second"],
+            archive.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>()
+        );
+        let (_, snap) = archive.iter().find(|(name, _)| name == "first").unwrap();
+        assert_eq!(1, snap.block_num);
+        assert_eq!(first.state_root(), snap.state_root());
+        let (_, snap) = archive.iter().find(|(name, _)| name == "second").unwrap();
+        assert_eq!(2, snap.block_num);
+
+        assert!(SnapShot::write_archive(
+            &path,
+            &[
+                ("same".into(), SnapShot::default()),
+                ("same".into(), SnapShot::default())
+            ],
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//!
+//! Compute Merkle Patricia Trie roots, as used for the Ethereum state and storage roots
+//!
+use alloy_primitives::{keccak256, B256, U256};
+use alloy_rlp::{Encodable, Header};
+
+use crate::snapshot::SnapShotAccountRecord;
+
+/// The root of an empty trie: `keccak256(rlp(""))`
+pub const EMPTY_ROOT: B256 = B256::new([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
+
+/// Compute the root of the trie containing the given `key => value` pairs
+pub fn trie_root<K: AsRef<[u8]>, V: AsRef<[u8]>>(items: &[(K, V)]) -> B256 {
+    if items.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let mut nibbles = items
+        .iter()
+        .map(|(k, v)| (to_nibbles(k.as_ref()), v.as_ref()))
+        .collect::<Vec<_>>();
+    nibbles.sort_by(|a, b| a.0.cmp(&b.0));
+    keccak256(encode_node(&nibbles, 0))
+}
+
+/// Compute the storage root of an account.  Keys are hashed, and slots with a
+/// value of zero are not included.
+pub fn storage_root<'a>(storage: impl IntoIterator<Item = (&'a U256, &'a U256)>) -> B256 {
+    let items = storage
+        .into_iter()
+        .filter(|(_, value)| !value.is_zero())
+        .map(|(slot, value)| {
+            (
+                keccak256(slot.to_be_bytes::<32>()),
+                rlp_bytes(&value.to_be_bytes_trimmed_vec()),
+            )
+        })
+        .collect::<Vec<_>>();
+    trie_root(&items)
+}
+
+/// Encode an account as stored in the state trie: `[nonce, balance, storage root, code hash]`
+pub fn encode_account(account: &SnapShotAccountRecord) -> Vec<u8> {
+    let storage_root = storage_root(&account.storage);
+    let code_hash = keccak256(&account.code);
+
+    let fields = [
+        rlp_bytes(&U256::from(account.nonce).to_be_bytes_trimmed_vec()),
+        rlp_bytes(&account.balance.to_be_bytes_trimmed_vec()),
+        rlp_bytes(storage_root.as_slice()),
+        rlp_bytes(code_hash.as_slice()),
+    ];
+    rlp_list(&fields)
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<u8> {
+    key.iter().flat_map(|b| [b >> 4, b & 0x0f]).collect()
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    bytes.encode(&mut out);
+    out
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload_length = items.iter().map(|i| i.len()).sum();
+    let mut out = Vec::new();
+    Header {
+        list: true,
+        payload_length,
+    }
+    .encode(&mut out);
+    for item in items {
+        out.extend_from_slice(item);
+    }
+    out
+}
+
+// hex-prefix encoding of a partial path
+fn compact_path(nibbles: &[u8], leaf: bool) -> Vec<u8> {
+    let flag = if leaf { 2 } else { 0 };
+    let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+    let rest = if nibbles.len() % 2 == 1 {
+        out.push(((flag + 1) << 4) | nibbles[0]);
+        &nibbles[1..]
+    } else {
+        out.push(flag << 4);
+        nibbles
+    };
+    out.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    out
+}
+
+// a reference to a child node: embedded if its encoding is short, otherwise its hash
+fn node_ref(encoded: Vec<u8>) -> Vec<u8> {
+    if encoded.len() < 32 {
+        encoded
+    } else {
+        rlp_bytes(keccak256(encoded).as_slice())
+    }
+}
+
+// Encode the node for the sorted `items` sharing the first `depth` nibbles
+fn encode_node(items: &[(Vec<u8>, &[u8])], depth: usize) -> Vec<u8> {
+    if items.len() == 1 {
+        let (key, value) = &items[0];
+        return rlp_list(&[
+            rlp_bytes(&compact_path(&key[depth..], true)),
+            rlp_bytes(value),
+        ]);
+    }
+
+    // length of the path shared by all the items, after `depth`
+    let first = &items[0].0;
+    let shared = items[1..]
+        .iter()
+        .fold(first.len() - depth, |len, (key, _)| {
+            first[depth..]
+                .iter()
+                .zip(&key[depth..])
+                .take(len)
+                .take_while(|(a, b)| a == b)
+                .count()
+        });
+    if shared > 0 {
+        return rlp_list(&[
+            rlp_bytes(&compact_path(&first[depth..depth + shared], false)),
+            node_ref(encode_node(items, depth + shared)),
+        ]);
+    }
+
+    // branch node.  Items are sorted, so a key ending here is first
+    let (value, items) = if first.len() == depth {
+        (items[0].1, &items[1..])
+    } else {
+        (&[][..], items)
+    };
+    let mut children = Vec::with_capacity(17);
+    for nibble in 0..16u8 {
+        let child = items
+            .iter()
+            .filter(|(key, _)| key[depth] == nibble)
+            .cloned()
+            .collect::<Vec<_>>();
+        if child.is_empty() {
+            children.push(rlp_bytes(&[]));
+        } else {
+            children.push(node_ref(encode_node(&child, depth + 1)));
+        }
+    }
+    children.push(rlp_bytes(value));
+    rlp_list(&children)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::b256;
+
+    #[test]
+    fn empty_root() {
+        assert_eq!(keccak256([0x80]), EMPTY_ROOT);
+        assert_eq!(EMPTY_ROOT, trie_root::<&[u8], &[u8]>(&[]));
+    }
+
+    #[test]
+    fn known_trie_roots() {
+        // from the ethereum/tests trie tests
+        let items = [
+            ("doe", "reindeer"),
+            ("dog", "puppy"),
+            ("dogglesworth", "cat"),
+        ];
+        assert_eq!(
+            b256!("8aad789dff2f538bca5d8ea56e8abe10f4c7ba3a5dea95fea4cd6e7c3a1168d3"),
+            trie_root(&items)
+        );
+
+        let items = [
+            ("do", "verb"),
+            ("horse", "stallion"),
+            ("doge", "coin"),
+            ("dog", "puppy"),
+        ];
+        assert_eq!(
+            b256!("5991bb8c6514148a29db676a14ac506cd2cd5775ace63c30a4fe457715e9ac84"),
+            trie_root(&items)
+        );
+    }
+}